@@ -0,0 +1,163 @@
+// Classic workloads under each optimization pass and driver loop, in instructions per second.
+//
+//     cargo bench --bench classic [-- NAME...]
+//
+// Every `.b` program in benches/programs and in $BF_BENCH_DIR runs, with `NAME.in` as its input,
+// and so does every `.bfl` program after `compile_lang`. A program which never ends, like
+// factorial.b, stops once it output the number of bytes in `NAME.limit`. Where the bundled
+// programs come from is in benches/programs/README.md.
+extern crate rusty_brainfuck;
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use rusty_brainfuck::{compile_lang, minify, Brainfuck, Dialect, EofPolicy, Inst, Passes};
+
+// Fast workloads are repeated for at least this long, and the best run counts
+const MIN_TIME: Duration = Duration::from_millis(500);
+
+#[derive(Clone, Copy, PartialEq)]
+enum Driver {
+    Step,      // `step` called in a loop which also answers `,`, like the CLI and the GUI do
+    StepLoop,  // `step_loop`, for programs without input
+}
+
+struct Variant {
+    name:   &'static str,
+    passes: Passes,
+    minify: bool,
+    driver: Driver,
+}
+
+const NONE: Passes = Passes { fold_runs: false, clear_loops: false };
+const FOLD_ONLY: Passes = Passes { fold_runs: true, clear_loops: false };
+const CLEAR_ONLY: Passes = Passes { fold_runs: false, clear_loops: true };
+const ALL: Passes = Passes { fold_runs: true, clear_loops: true };
+
+const VARIANTS: [Variant; 6] = [
+    Variant { name: "naive",       passes: NONE,       minify: false, driver: Driver::Step },
+    Variant { name: "fold runs",   passes: FOLD_ONLY,  minify: false, driver: Driver::Step },
+    Variant { name: "clear loops", passes: CLEAR_ONLY, minify: false, driver: Driver::Step },
+    Variant { name: "optimized",   passes: ALL,        minify: false, driver: Driver::Step },
+    Variant { name: "minified",    passes: ALL,        minify: true,  driver: Driver::Step },
+    Variant { name: "step_loop",   passes: ALL,        minify: false, driver: Driver::StepLoop },
+];
+
+struct Workload {
+    name:   String,
+    source: String,
+    input:  Vec<u8>,
+    limit:  Option<usize>,  // Output bytes after which the program is stopped
+}
+
+#[derive(Clone)]
+struct Measure {
+    time:   Duration,
+    insts:  u64,
+    output: String,
+}
+
+fn workloads(dir: &Path) -> Vec<Workload> {
+    let mut paths: Vec<PathBuf> = match fs::read_dir(dir) {
+        Ok(entries) => entries.filter_map(|entry| entry.ok().map(|entry| entry.path())).collect(),
+        Err(_) => return Vec::new(),
+    };
+    paths.sort();
+    paths.into_iter()
+        .filter_map(|path| {
+            let name = path.file_name()?.to_str()?.to_string();
+            let text = fs::read_to_string(&path).ok()?;
+            let source = match path.extension()?.to_str()? {
+                "b" | "bf" => text,
+                "bfl" => match compile_lang(&text) {
+                    Ok(program) => program,
+                    Err(err) => panic!("{}:{}", path.display(), err.describe(&text)),
+                },
+                _ => return None,
+            };
+            let input = fs::read(path.with_extension("in")).unwrap_or_default();
+            let limit = fs::read_to_string(path.with_extension("limit")).ok()
+                .map(|text| text.trim().parse().unwrap_or_else(|_| panic!("{}: invalid limit", path.display())));
+            Some(Workload { name, source, input, limit })
+        })
+        .collect()
+}
+
+fn run(workload: &Workload, variant: &Variant) -> Measure {
+    let source = if variant.minify { minify(&workload.source).unwrap() } else { workload.source.clone() };
+    let mut bf = Brainfuck::with_passes(source, &Dialect::brainfuck(), variant.passes).unwrap();
+    bf.set_fuel(Some(u64::MAX));
+    let mut input = workload.input.iter();
+    let mut left = workload.limit.unwrap_or(usize::MAX);
+    let started = Instant::now();
+    match variant.driver {
+        Driver::StepLoop => bf.step_loop().unwrap(),
+        Driver::Step => while (!bf.reach_eop() || bf.is_input_mode()) && left > 0 {
+            if bf.is_input_mode() {
+                match input.next() {
+                    Some(&byte) => bf.set_input_byte(byte).unwrap(),
+                    None => bf.set_eof(EofPolicy::Zero).unwrap(),
+                }
+                continue;
+            }
+            if workload.limit.is_some() && bf.insts()[bf.pc()] == Inst::Wrt {
+                left -= 1;
+            }
+            bf.step().unwrap();
+        },
+    }
+    let time = started.elapsed();
+    Measure { time, insts: u64::MAX - bf.fuel().unwrap(), output: bf.pop_result() }
+}
+
+// The best of as many runs as fit in `MIN_TIME`
+fn measure(workload: &Workload, variant: &Variant) -> Measure {
+    let mut best = run(workload, variant);
+    let mut total = best.time;
+    while total < MIN_TIME {
+        let next = run(workload, variant);
+        total += next.time;
+        if next.time < best.time {
+            best = next;
+        }
+    }
+    best
+}
+
+fn per_second(count: u64, time: Duration) -> f64 {
+    count as f64 / time.as_secs_f64().max(1e-9) / 1e6
+}
+
+fn main() {
+    // `cargo bench` passes `--bench`, anything else selects workloads by name
+    let filters: Vec<String> = env::args().skip(1).filter(|arg| !arg.starts_with('-')).collect();
+    let mut all = workloads(&Path::new(env!("CARGO_MANIFEST_DIR")).join("benches/programs"));
+    if let Some(dir) = env::var_os("BF_BENCH_DIR") {
+        all.extend(workloads(Path::new(&dir)));
+    }
+    all.retain(|workload| filters.is_empty() || filters.iter().any(|filter| workload.name.contains(filter.as_str())));
+
+    println!("{:<16} {:<12} {:>10} {:>14} {:>10} {:>10}", "workload", "variant", "time", "instructions", "Minst/s", "Mcmd/s");
+    for workload in &all {
+        // The naive run executes one instruction per command, which makes every variant comparable
+        let naive = measure(workload, &VARIANTS[0]);
+        for variant in &VARIANTS {
+            if variant.driver == Driver::StepLoop && workload.source.contains(',') {
+                println!("{:<16} {:<12} {:>10}", workload.name, variant.name, "(reads input)");
+                continue;
+            }
+            if variant.driver == Driver::StepLoop && workload.limit.is_some() {
+                println!("{:<16} {:<12} {:>10}", workload.name, variant.name, "(never ends)");
+                continue;
+            }
+            let result = if variant.name == VARIANTS[0].name { naive.clone() } else { measure(workload, variant) };
+            assert_eq!(result.output, naive.output, "{} gives another output with {}", workload.name, variant.name);
+            println!(
+                "{:<16} {:<12} {:>9.3}s {:>14} {:>10.1} {:>10.1}",
+                workload.name, variant.name, result.time.as_secs_f64(), result.insts,
+                per_second(result.insts, result.time), per_second(naive.insts, result.time));
+        }
+    }
+}
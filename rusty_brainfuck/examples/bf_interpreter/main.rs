@@ -9,7 +9,7 @@ mod interpret_io {
     type Filename = String;
     type Program = String;
 
-    use rusty_brainfuck::Brainfuck;
+    use rusty_brainfuck::{Brainfuck, Dialect, decompile, lint_with_dialect, generate, GenerateConfig, Preprocessor, Tradeoff};
 
     enum IsContinue {
         Yes,
@@ -30,12 +30,7 @@ mod interpret_io {
     }
 
     pub fn exec_loop() {
-        loop {
-            match wait_input() {
-                Yes => (),
-                No  => { break; },
-            }
-        }
+        while let Yes = wait_input() {}
     }
 
     pub fn file_exec(filename: Filename) {
@@ -59,6 +54,76 @@ mod interpret_io {
         }
     }
 
+    pub fn file_lint(filename: Filename) {
+        let mut program = Program::new();
+        match File::open(&filename).as_mut() {
+            Ok(file) => {
+                match file.read_to_string(&mut program) {
+                    Ok(_) => {
+                        let lints = lint_with_dialect(&program, &Dialect::detect(&program));
+                        if lints.is_empty() {
+                            println!("No problems found.");
+                        }
+                        for l in lints {
+                            println!("{}:{}", filename, l.describe(&program));
+                        }
+                    },
+                    Err(err) => { println!("error: {}", err); },
+                }
+            }
+            Err(err) => { println!("error: {}", err); },
+        }
+    }
+
+    // よく使われるイディオムを疑似コードにして表示する
+    pub fn file_decompile(filename: Filename) {
+        let mut program = Program::new();
+        match File::open(&filename).as_mut() {
+            Ok(file) => {
+                match file.read_to_string(&mut program) {
+                    Ok(_) => match decompile(&program) {
+                        Ok(pseudocode) => print!("{}", pseudocode.render(&program)),
+                        Err(err) => println!("error: {}", err),
+                    },
+                    Err(err) => { println!("error: {}", err); },
+                }
+            }
+            Err(err) => { println!("error: {}", err); },
+        }
+    }
+
+    // マクロを展開した Brainfuck を表示する
+    pub fn file_expand(filename: Filename) {
+        match Preprocessor::new().run_file(&filename) {
+            Ok(expansion) => println!("{}", expansion.program),
+            Err(err) => println!("{}", err),
+        }
+    }
+
+    // --tradeoff size|speed と --max-cells N を読んでから、最後の引数を出力するプログラムを生成する
+    pub fn text_generate(args: Vec<String>) {
+        let mut config = GenerateConfig::default();
+        let mut args = args.into_iter();
+        let mut text = None;
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--tradeoff" => match args.next().as_deref().and_then(Tradeoff::from_name) {
+                    Some(tradeoff) => config.tradeoff = tradeoff,
+                    None => { println!("error: --tradeoff expects size or speed"); return; },
+                },
+                "--max-cells" => match args.next().and_then(|n| n.parse().ok()) {
+                    Some(n) => config.max_cells = n,
+                    None => { println!("error: --max-cells expects a number"); return; },
+                },
+                _ => text = Some(arg),
+            }
+        }
+        match text {
+            Some(text) => println!("{}", generate(text.as_bytes(), &config)),
+            None => println!("error: no text to generate"),
+        }
+    }
+
     fn wait_input() -> IsContinue {
         print!("$ input : ");
         io::stdout().flush().unwrap();
@@ -86,7 +151,9 @@ mod interpret_io {
     }
 
     fn exec_and_input(program: Program) -> Result<(), &'static str>{
-        let mut bf = Brainfuck::new(program)?;
+        // Ook! などの方言で書かれたプログラムもそのまま実行する
+        let dialect = Dialect::detect(&program);
+        let mut bf = Brainfuck::with_dialect(program, &dialect)?;
         let bf_include_comma = bf.include_comma();
         loop {
             // ステップ実行後、"," を踏んでいれば入力モードになっている
@@ -142,15 +209,30 @@ use interpret_io::{
     print_discription_at_file,
     exec_loop,
     file_exec,
+    file_lint,
+    file_expand,
+    file_decompile,
+    text_generate,
 };
 
 fn main() {
     if env::args().count() < 2 {
         print_discription();
         exec_loop();
+    } else if env::args().nth(1).as_deref() == Some("--lint") && env::args().count() > 2 {
+        let filename = env::args().next_back().unwrap();
+        file_lint(filename);
+    } else if env::args().nth(1).as_deref() == Some("--expand") && env::args().count() > 2 {
+        let filename = env::args().next_back().unwrap();
+        file_expand(filename);
+    } else if env::args().nth(1).as_deref() == Some("--decompile") && env::args().count() > 2 {
+        let filename = env::args().next_back().unwrap();
+        file_decompile(filename);
+    } else if env::args().nth(1).as_deref() == Some("--generate") && env::args().count() > 2 {
+        text_generate(env::args().skip(2).collect());
     } else {
         print_discription_at_file();
-        let filename = env::args().last().unwrap();
+        let filename = env::args().next_back().unwrap();
         file_exec(filename);
     }
 }
@@ -0,0 +1,71 @@
+// Generated from src/ffi.rs by tests/ffi.rs. Do not edit.
+#ifndef RUSTY_BRAINFUCK_H
+#define RUSTY_BRAINFUCK_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define BF_OK 0                      // The program ended
+#define BF_NEEDS_INPUT 1             // A `,` waits for bytes from bf_feed_input or bf_close_input
+#define BF_BUDGET_SPENT 2            // The step budget ran out, and bf_run can carry on
+#define BF_ERROR_ARGUMENT (-1)       // A null pointer, a string that isn't UTF-8, or a value out of range
+#define BF_ERROR_PARSE (-2)          // Unbalanced brackets in the program
+#define BF_ERROR_POINTER_LEFT (-3)   // The pointer moved left of the first cell
+#define BF_ERROR_POINTER_RIGHT (-4)  // The pointer moved right of the last cell
+#define BF_ERROR_RUNTIME (-5)        // Any other error while running
+#define BF_ERROR_PANIC (-6)          // A bug in the library, which leaves the machine unusable
+
+#define BF_EOF_ZERO 0                // `,` stores 0 once the input is closed
+#define BF_EOF_MINUS_ONE 1           // `,` stores 255
+#define BF_EOF_UNCHANGED 2           // `,` leaves the cell as it is
+
+typedef struct BfMachine BfMachine;
+
+// Parse a NUL-terminated program and store a new machine in `*machine`, or NULL on failure.
+// A `tape_size` of 0 keeps the default of 30000 cells.
+int bf_create(const char *program, size_t tape_size, BfMachine **machine);
+
+// Free a machine from bf_create. NULL is ignored.
+void bf_destroy(BfMachine *machine);
+
+// What `,` stores once the input is closed and used up, one of the BF_EOF_ values.
+int bf_set_eof(BfMachine *machine, int policy);
+
+// Queue `len` bytes of input for the `,` commands to come.
+int bf_feed_input(BfMachine *machine, const uint8_t *bytes, size_t len);
+
+// Mark the end of the input, so that `,` follows the EOF policy after the queued bytes.
+int bf_close_input(BfMachine *machine);
+
+// Run at most `budget` steps. Returns BF_OK, BF_NEEDS_INPUT, BF_BUDGET_SPENT or an error,
+// and adds the steps taken to `*steps` unless it is NULL.
+int bf_run(BfMachine *machine, uint64_t budget, uint64_t *steps);
+
+// Move up to `capacity` bytes of output into `buffer`, and return how many were moved.
+size_t bf_read_output(BfMachine *machine, uint8_t *buffer, size_t capacity);
+
+// Copy up to `capacity` cells from cell `start` on into `buffer`, and return how many were copied.
+size_t bf_read_tape(const BfMachine *machine, size_t start, uint8_t *buffer, size_t capacity);
+
+// The number of cells on the tape.
+size_t bf_tape_size(const BfMachine *machine);
+
+// The index of the cell under the pointer.
+size_t bf_pointer(const BfMachine *machine);
+
+// The message of the last error of bf_run, with its `line:col`, or "" if there was none.
+// The string belongs to the machine and lives until the next call of bf_run or bf_destroy.
+const char *bf_last_error(const BfMachine *machine);
+
+// A static description of a status code.
+const char *bf_status_message(int code);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,287 @@
+use crate::{CellWidth, CodeGen, Dialect, EofPolicy, Inst, MAX_MEMORY};
+use crate::Inst::*;
+
+const MAGIC: &[u8] = b"BFBC";
+const VERSION: u8 = 1;
+const WASM_PAGE: usize = 65_536;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Target {
+    C,         // A standalone C program
+    Wasm,      // A module in the WebAssembly text format, importing `env.putchar` and `env.getchar`
+    Bytecode,  // The folded instructions, which `decode_bytecode` turns back into Brainfuck
+}
+
+impl Target {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "c" => Some(Target::C),
+            "wasm" | "wat" => Some(Target::Wasm),
+            "bytecode" => Some(Target::Bytecode),
+            _ => None,
+        }
+    }
+}
+
+/// Options of `compile`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CompileConfig {
+    pub target:     Target,
+    pub tape_size:  usize,
+    pub cell_width: CellWidth,  // Bytecode leaves it to the machine which runs it
+    pub eof:        EofPolicy,
+}
+
+impl Default for CompileConfig {
+    fn default() -> Self {
+        CompileConfig {
+            target:     Target::C,
+            tape_size:  MAX_MEMORY,
+            cell_width: CellWidth::Bits8,
+            eof:        EofPolicy::Zero,
+        }
+    }
+}
+
+pub fn compile(program: &str, config: &CompileConfig) -> Result<Vec<u8>, &'static str> {
+    compile_with_dialect(program, &Dialect::brainfuck(), config)
+}
+
+// Only the eight commands can be compiled, whatever the dialect spells them as
+pub fn compile_with_dialect(program: &str, dialect: &Dialect, config: &CompileConfig) -> Result<Vec<u8>, &'static str> {
+    let insts = CodeGen::with_dialect(program, dialect).generate_insts()?;
+    if insts.iter().any(|inst| !matches!(inst, Add(_) | Sub(_) | Shr(_) | Shl(_) | Jpf(_) | Jpb(_) | Wrt | Red | Stz)) {
+        return Err("Only the eight commands can be compiled.");
+    }
+    if config.tape_size == 0 {
+        return Err("Tape needs at least one cell.");
+    }
+    Ok(match config.target {
+        Target::C => to_c(&insts, config).into_bytes(),
+        Target::Wasm => to_wasm(&insts, config).into_bytes(),
+        Target::Bytecode => to_bytecode(&insts),
+    })
+}
+
+fn to_c(insts: &[Inst], config: &CompileConfig) -> String {
+    let cell = match config.cell_width {
+        CellWidth::Bits8 => "unsigned char",
+        CellWidth::Bits16 => "uint16_t",
+        CellWidth::Bits32 => "uint32_t",
+    };
+    let mut code = String::from("#include <stdint.h>\n#include <stdio.h>\n\n");
+    code += &format!("static {} tape[{}];\n\n", cell, config.tape_size);
+    code += &format!("int main(void) {{\n    {} *p = tape;\n    int c;\n", cell);
+    let mut depth = 1;
+    for inst in insts {
+        if let Jpb(_) = inst {
+            depth -= 1;
+        }
+        let line = match *inst {
+            Add(n) => format!("*p += {};", n),
+            Sub(n) => format!("*p -= {};", n),
+            Shr(n) => format!("p += {};", n),
+            Shl(n) => format!("p -= {};", n),
+            Jpf(_) => String::from("while (*p) {"),
+            Jpb(_) => String::from("}"),
+            // putchar writes the lowest 8 bits
+            Wrt => String::from("putchar(*p);"),
+            Red => match config.eof {
+                EofPolicy::Zero => String::from("c = getchar(); *p = c == EOF ? 0 : c;"),
+                EofPolicy::MinusOne => format!("c = getchar(); *p = c == EOF ? {} : c;", config.cell_width.max()),
+                EofPolicy::Unchanged => String::from("c = getchar(); if (c != EOF) *p = c;"),
+            },
+            Stz => String::from("*p = 0;"),
+            _ => unreachable!(),
+        };
+        code += &format!("{}{}\n", "    ".repeat(depth), line);
+        if let Jpf(_) = inst {
+            depth += 1;
+        }
+    }
+    code += "    (void)c;\n    return 0;\n}\n";
+    code
+}
+
+fn to_wasm(insts: &[Inst], config: &CompileConfig) -> String {
+    // Cells take 1, 2 or 4 bytes of memory, so pointer moves are scaled to bytes
+    let size = config.cell_width.bits() as usize / 8;
+    let (load, store) = match config.cell_width {
+        CellWidth::Bits8 => ("i32.load8_u", "i32.store8"),
+        CellWidth::Bits16 => ("i32.load16_u", "i32.store16"),
+        CellWidth::Bits32 => ("i32.load", "i32.store"),
+    };
+    let pages = (config.tape_size * size).div_ceil(WASM_PAGE);
+    let mut code = String::from("(module\n");
+    code += "  (import \"env\" \"putchar\" (func $putchar (param i32)))\n";
+    code += "  (import \"env\" \"getchar\" (func $getchar (result i32)))  ;; -1 at the end of input\n";
+    code += &format!("  (memory (export \"memory\") {})\n", pages);
+    code += "  (func (export \"run\") (local $p i32) (local $c i32)\n";
+    let cell = format!("({} (local.get $p))", load);
+    let byte = if size == 1 { cell.clone() } else { format!("(i32.and {} (i32.const 255))", cell) };
+    let mut depth = 2;
+    for (pc, inst) in insts.iter().enumerate() {
+        if let Jpb(_) = inst {
+            depth -= 1;
+        }
+        let line = match *inst {
+            Add(n) => format!("({} (local.get $p) (i32.add {} (i32.const {})))", store, cell, n),
+            Sub(n) => format!("({} (local.get $p) (i32.sub {} (i32.const {})))", store, cell, n),
+            Shr(n) => format!("(local.set $p (i32.add (local.get $p) (i32.const {})))", n * size),
+            Shl(n) => format!("(local.set $p (i32.sub (local.get $p) (i32.const {})))", n * size),
+            Jpf(_) => format!("(block $b{} (loop $l{} (br_if $b{} (i32.eqz {}))", pc, pc, pc, cell),
+            Jpb(open) => format!("(br $l{})))", open),
+            Wrt => format!("(call $putchar {})", byte),
+            Red => {
+                let eof = match config.eof {
+                    EofPolicy::Zero => format!("({} (local.get $p) (i32.const 0))", store),
+                    EofPolicy::MinusOne => format!("({} (local.get $p) (i32.const {}))", store, config.cell_width.max() as i32),
+                    EofPolicy::Unchanged => String::from("(nop)"),
+                };
+                format!("(local.set $c (call $getchar)) (if (i32.ge_s (local.get $c) (i32.const 0)) \
+                         (then ({} (local.get $p) (local.get $c))) (else {}))", store, eof)
+            },
+            Stz => format!("({} (local.get $p) (i32.const 0))", store),
+            _ => unreachable!(),
+        };
+        code += &format!("{}{}\n", "  ".repeat(depth), line);
+        if let Jpf(_) = inst {
+            depth += 1;
+        }
+    }
+    code += "  )\n)\n";
+    code
+}
+
+fn to_bytecode(insts: &[Inst]) -> Vec<u8> {
+    let mut bytes = MAGIC.to_vec();
+    bytes.push(VERSION);
+    for inst in insts {
+        let (op, operand) = match *inst {
+            Add(n) => (0, Some(n as u32)),
+            Sub(n) => (1, Some(n as u32)),
+            Shr(n) => (2, Some(n as u32)),
+            Shl(n) => (3, Some(n as u32)),
+            Jpf(close) => (4, Some(close as u32)),
+            Jpb(open) => (5, Some(open as u32)),
+            Wrt => (6, None),
+            Red => (7, None),
+            Stz => (8, None),
+            _ => unreachable!(),
+        };
+        bytes.push(op);
+        if let Some(operand) = operand {
+            bytes.extend_from_slice(&operand.to_le_bytes());
+        }
+    }
+    bytes
+}
+
+pub fn is_bytecode(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+// Write bytecode back as a Brainfuck program which compiles to the same instructions
+pub fn decode_bytecode(bytes: &[u8]) -> Result<String, &'static str> {
+    decode_bytecode_with_tape_size(bytes, MAX_MEMORY)
+}
+
+// Like `decode_bytecode`, for a tape of `tape_size` cells. Operands `compile` never writes,
+// like pointer moves past the end of the tape, are rejected before they are spelled out.
+pub fn decode_bytecode_with_tape_size(bytes: &[u8], tape_size: usize) -> Result<String, &'static str> {
+    if !is_bytecode(bytes) || bytes.get(MAGIC.len()) != Some(&VERSION) {
+        return Err("Not bytecode of this version.");
+    }
+    let mut program = String::new();
+    let mut rest = &bytes[MAGIC.len() + 1..];
+    while let Some((&op, tail)) = rest.split_first() {
+        rest = tail;
+        let operand = match op {
+            0..=5 => {
+                if rest.len() < 4 {
+                    return Err("Bytecode ends in the middle of an instruction.");
+                }
+                let (operand, tail) = rest.split_at(4);
+                rest = tail;
+                u32::from_le_bytes([operand[0], operand[1], operand[2], operand[3]]) as usize
+            },
+            _ => 0,
+        };
+        let limit = match op {
+            0 | 1 => u8::MAX as usize,
+            2 | 3 => tape_size.saturating_sub(1),
+            _ => usize::MAX,
+        };
+        if operand > limit {
+            return Err("Invalid operand in bytecode.");
+        }
+        match op {
+            0 => program += &"+".repeat(operand),
+            1 => program += &"-".repeat(operand),
+            2 => program += &">".repeat(operand),
+            3 => program += &"<".repeat(operand),
+            4 => program.push('['),
+            5 => program.push(']'),
+            6 => program.push('.'),
+            7 => program.push(','),
+            8 => program.push_str("[-]"),
+            _ => return Err("Unknown opcode in bytecode."),
+        }
+    }
+    // The jump targets are checked by compiling the program again
+    CodeGen::new(&program).generate_insts()?;
+    Ok(program)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bytecode_round_trip() {
+        let program = "++[->+++<]>.,[-]";
+        let config = CompileConfig { target: Target::Bytecode, ..CompileConfig::default() };
+        let bytes = compile(program, &config).unwrap();
+        assert!(is_bytecode(&bytes));
+        assert_eq!(decode_bytecode(&bytes).unwrap(), program);
+        assert!(decode_bytecode(&bytes[..7]).is_err());
+    }
+
+    #[test]
+    fn rejects_oversized_operands() {
+        let bytecode = |op: u8, operand: u32| [MAGIC, &[VERSION, op], &operand.to_le_bytes()[..]].concat();
+        assert_eq!(decode_bytecode(&bytecode(0, 255)).unwrap().len(), 255);
+        assert_eq!(decode_bytecode(&bytecode(0, u32::MAX)), Err("Invalid operand in bytecode."));
+        assert_eq!(decode_bytecode(&bytecode(1, 256)), Err("Invalid operand in bytecode."));
+        assert_eq!(decode_bytecode_with_tape_size(&bytecode(2, 9), 10).unwrap(), ">>>>>>>>>");
+        assert_eq!(decode_bytecode_with_tape_size(&bytecode(3, 10), 10), Err("Invalid operand in bytecode."));
+    }
+
+    #[test]
+    fn c_and_wasm() {
+        let c = compile("+[.,]", &CompileConfig::default()).unwrap();
+        let c = String::from_utf8(c).unwrap();
+        assert!(c.contains("static unsigned char tape[30000];"));
+        assert!(c.contains("    while (*p) {\n        putchar(*p);\n"));
+        let config = CompileConfig { target: Target::Wasm, eof: EofPolicy::Zero, ..CompileConfig::default() };
+        let wasm = String::from_utf8(compile("+[.,]", &config).unwrap()).unwrap();
+        assert!(wasm.contains("(memory (export \"memory\") 1)"));
+        assert!(wasm.contains("(br $l1)))"));
+        assert!(compile("+Y", &config).is_ok());
+        assert!(compile_with_dialect("+Y", &Dialect::brainfork(), &config).is_err());
+    }
+
+    #[test]
+    fn wide_cells() {
+        let config = CompileConfig { cell_width: CellWidth::Bits16, eof: EofPolicy::MinusOne, ..CompileConfig::default() };
+        let c = String::from_utf8(compile(">,", &config).unwrap()).unwrap();
+        assert!(c.contains("static uint16_t tape[30000];"));
+        assert!(c.contains("*p = c == EOF ? 65535 : c;"));
+        let config = CompileConfig { target: Target::Wasm, cell_width: CellWidth::Bits32, ..config };
+        let wasm = String::from_utf8(compile(">+.", &config).unwrap()).unwrap();
+        assert!(wasm.contains("(memory (export \"memory\") 2)"));
+        assert!(wasm.contains("(i32.const 4)"));
+        assert!(wasm.contains("(i32.store (local.get $p) (i32.add (i32.load (local.get $p)) (i32.const 1)))"));
+        assert!(wasm.contains("(call $putchar (i32.and (i32.load (local.get $p)) (i32.const 255)))"));
+    }
+}
@@ -0,0 +1,289 @@
+use std::fmt;
+
+// Just enough JSON for the messages of the language server protocol
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Value>),
+    Object(Vec<(String, Value)>),
+}
+
+pub fn object(pairs: Vec<(&str, Value)>) -> Value {
+    Value::Object(pairs.into_iter().map(|(key, value)| (key.to_string(), value)).collect())
+}
+
+impl From<&str> for Value {
+    fn from(text: &str) -> Self {
+        Value::String(text.to_string())
+    }
+}
+
+impl From<String> for Value {
+    fn from(text: String) -> Self {
+        Value::String(text)
+    }
+}
+
+impl From<usize> for Value {
+    fn from(n: usize) -> Self {
+        Value::Number(n as f64)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(b: bool) -> Self {
+        Value::Bool(b)
+    }
+}
+
+impl Value {
+    pub fn get(&self, key: &str) -> &Value {
+        match self {
+            Value::Object(pairs) => pairs.iter().find(|(k, _)| k == key).map_or(&Value::Null, |(_, value)| value),
+            _ => &Value::Null,
+        }
+    }
+
+    // `value.at(&["params", "textDocument", "uri"])`
+    pub fn at(&self, path: &[&str]) -> &Value {
+        path.iter().fold(self, |value, key| value.get(key))
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(text) => Some(text),
+            _ => None,
+        }
+    }
+
+    pub fn as_usize(&self) -> Option<usize> {
+        match self {
+            Value::Number(n) if *n >= 0.0 && n.fract() == 0.0 => Some(*n as usize),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> &[Value] {
+        match self {
+            Value::Array(values) => values,
+            _ => &[],
+        }
+    }
+
+    pub fn is_null(&self) -> bool {
+        *self == Value::Null
+    }
+}
+
+fn write_string(f: &mut fmt::Formatter, text: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for c in text.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    write!(f, "\"")
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Null => write!(f, "null"),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Number(n) if n.fract() == 0.0 && n.abs() < 1e15 => write!(f, "{}", *n as i64),
+            Value::Number(n) => write!(f, "{}", n),
+            Value::String(text) => write_string(f, text),
+            Value::Array(values) => {
+                write!(f, "[")?;
+                for (idx, value) in values.iter().enumerate() {
+                    if idx > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", value)?;
+                }
+                write!(f, "]")
+            },
+            Value::Object(pairs) => {
+                write!(f, "{{")?;
+                for (idx, (key, value)) in pairs.iter().enumerate() {
+                    if idx > 0 {
+                        write!(f, ",")?;
+                    }
+                    write_string(f, key)?;
+                    write!(f, ":{}", value)?;
+                }
+                write!(f, "}}")
+            },
+        }
+    }
+}
+
+struct Parser<'a> {
+    bytes: &'a [u8],
+    pos:   usize,
+}
+
+impl<'a> Parser<'a> {
+    fn skip_space(&mut self) {
+        while self.bytes.get(self.pos).is_some_and(|b| b.is_ascii_whitespace()) {
+            self.pos += 1;
+        }
+    }
+
+    fn expect(&mut self, text: &str) -> Result<(), &'static str> {
+        if self.bytes[self.pos..].starts_with(text.as_bytes()) {
+            self.pos += text.len();
+            Ok(())
+        } else {
+            Err("Invalid JSON.")
+        }
+    }
+
+    fn value(&mut self) -> Result<Value, &'static str> {
+        self.skip_space();
+        let value = match self.bytes.get(self.pos) {
+            Some(b'n') => self.expect("null").map(|_| Value::Null)?,
+            Some(b't') => self.expect("true").map(|_| Value::Bool(true))?,
+            Some(b'f') => self.expect("false").map(|_| Value::Bool(false))?,
+            Some(b'"') => Value::String(self.string()?),
+            Some(b'[') => {
+                self.pos += 1;
+                let mut values = Vec::new();
+                self.skip_space();
+                if self.bytes.get(self.pos) == Some(&b']') {
+                    self.pos += 1;
+                    return Ok(Value::Array(values));
+                }
+                loop {
+                    values.push(self.value()?);
+                    self.skip_space();
+                    match self.bytes.get(self.pos) {
+                        Some(b',') => self.pos += 1,
+                        Some(b']') => {
+                            self.pos += 1;
+                            break;
+                        },
+                        _ => return Err("Invalid JSON."),
+                    }
+                }
+                Value::Array(values)
+            },
+            Some(b'{') => {
+                self.pos += 1;
+                let mut pairs = Vec::new();
+                self.skip_space();
+                if self.bytes.get(self.pos) == Some(&b'}') {
+                    self.pos += 1;
+                    return Ok(Value::Object(pairs));
+                }
+                loop {
+                    self.skip_space();
+                    let key = self.string()?;
+                    self.skip_space();
+                    self.expect(":")?;
+                    pairs.push((key, self.value()?));
+                    self.skip_space();
+                    match self.bytes.get(self.pos) {
+                        Some(b',') => self.pos += 1,
+                        Some(b'}') => {
+                            self.pos += 1;
+                            break;
+                        },
+                        _ => return Err("Invalid JSON."),
+                    }
+                }
+                Value::Object(pairs)
+            },
+            Some(_) => {
+                let start = self.pos;
+                while self.bytes.get(self.pos).is_some_and(|b| b"+-.eE0123456789".contains(b)) {
+                    self.pos += 1;
+                }
+                let text = std::str::from_utf8(&self.bytes[start..self.pos]).map_err(|_| "Invalid JSON.")?;
+                Value::Number(text.parse().map_err(|_| "Invalid JSON.")?)
+            },
+            None => return Err("Invalid JSON."),
+        };
+        Ok(value)
+    }
+
+    fn hex(&mut self) -> Result<u32, &'static str> {
+        let digits = self.bytes.get(self.pos..self.pos + 4).ok_or("Invalid JSON.")?;
+        self.pos += 4;
+        let digits = std::str::from_utf8(digits).map_err(|_| "Invalid JSON.")?;
+        u32::from_str_radix(digits, 16).map_err(|_| "Invalid JSON.")
+    }
+
+    fn string(&mut self) -> Result<String, &'static str> {
+        self.expect("\"")?;
+        let mut bytes = Vec::new();
+        loop {
+            let b = *self.bytes.get(self.pos).ok_or("Invalid JSON.")?;
+            self.pos += 1;
+            match b {
+                b'"' => break,
+                b'\\' => {
+                    let escaped = *self.bytes.get(self.pos).ok_or("Invalid JSON.")?;
+                    self.pos += 1;
+                    let c = match escaped {
+                        b'n' => '\n',
+                        b't' => '\t',
+                        b'r' => '\r',
+                        b'b' => '\u{8}',
+                        b'f' => '\u{c}',
+                        b'u' => {
+                            let mut code = self.hex()?;
+                            // A surrogate pair for characters outside the basic plane
+                            if (0xd800..0xdc00).contains(&code) && self.bytes[self.pos..].starts_with(b"\\u") {
+                                self.pos += 2;
+                                code = 0x10000 + ((code - 0xd800) << 10) + (self.hex()? - 0xdc00);
+                            }
+                            char::from_u32(code).unwrap_or('\u{fffd}')
+                        },
+                        other => other as char,
+                    };
+                    let mut buf = [0; 4];
+                    bytes.extend(c.encode_utf8(&mut buf).bytes());
+                },
+                b => bytes.push(b),
+            }
+        }
+        String::from_utf8(bytes).map_err(|_| "Invalid JSON.")
+    }
+}
+
+pub fn parse(text: &str) -> Result<Value, &'static str> {
+    let mut parser = Parser { bytes: text.as_bytes(), pos: 0 };
+    let value = parser.value()?;
+    parser.skip_space();
+    if parser.pos != text.len() {
+        return Err("Invalid JSON.");
+    }
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let text = r#"{"id":1,"params":{"text":"a\"b\né","list":[true,null,-2.5,[]]}}"#;
+        let value = parse(text).unwrap();
+        assert_eq!(value.at(&["params", "text"]).as_str(), Some("a\"b\né"));
+        assert_eq!(value.get("id").as_usize(), Some(1));
+        assert_eq!(parse(&value.to_string()).unwrap(), value);
+        assert_eq!(value.to_string(), r#"{"id":1,"params":{"text":"a\"b\né","list":[true,null,-2.5,[]]}}"#);
+        assert!(parse("{\"a\":}").is_err());
+        assert!(parse("[1] 2").is_err());
+    }
+}
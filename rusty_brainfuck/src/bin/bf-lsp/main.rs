@@ -0,0 +1,77 @@
+// A language server for brainfuck, speaking the protocol over stdio:
+// diagnostics from the linter, hovers with the net effect of a loop, a line or a selection,
+// go to the matching bracket, and formatting.
+extern crate rusty_brainfuck;
+
+mod json;
+mod server;
+
+use std::io::{self, BufRead, Write};
+use std::process;
+
+use json::Value;
+use server::{Server, PARSE_ERROR};
+
+// The body of the next message, or None at the end of input
+fn read_message(reader: &mut impl BufRead) -> io::Result<Option<String>> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                length = value.trim().parse().ok();
+            }
+        }
+    }
+    let length = length.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "No Content-Length header."))?;
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body)?;
+    String::from_utf8(body).map(Some).map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "The message is not UTF-8."))
+}
+
+fn write_message(writer: &mut impl Write, message: &Value) -> io::Result<()> {
+    let body = message.to_string();
+    write!(writer, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    writer.flush()
+}
+
+fn serve() -> io::Result<i32> {
+    let stdin = io::stdin();
+    let mut reader = stdin.lock();
+    let stdout = io::stdout();
+    let mut writer = stdout.lock();
+    let mut server = Server::new();
+    while let Some(body) = read_message(&mut reader)? {
+        let message = match json::parse(&body) {
+            Ok(message) => message,
+            Err(err) => {
+                write_message(&mut writer, &server::error(Value::Null, PARSE_ERROR, err))?;
+                continue;
+            },
+        };
+        if message.get("method").as_str() == Some("exit") {
+            return Ok(if server.is_shut_down() { 0 } else { 1 });
+        }
+        for reply in server.handle(&message) {
+            write_message(&mut writer, &reply)?;
+        }
+    }
+    Ok(1)
+}
+
+fn main() {
+    match serve() {
+        Ok(code) => process::exit(code),
+        Err(err) => {
+            eprintln!("bf-lsp: {}", err);
+            process::exit(1);
+        },
+    }
+}
@@ -0,0 +1,426 @@
+use std::collections::{BTreeMap, HashMap};
+
+use rusty_brainfuck::{format_program, lint_with_dialect, Dialect, FormatConfig, Severity};
+
+use crate::json::{object, Value};
+
+// Error codes of JSON-RPC and of the protocol
+pub const PARSE_ERROR: i32 = -32700;
+const INVALID_REQUEST: i32 = -32600;
+const METHOD_NOT_FOUND: i32 = -32601;
+const INVALID_PARAMS: i32 = -32602;
+const REQUEST_FAILED: i32 = -32803;
+
+pub fn response(id: Value, result: Value) -> Value {
+    object(vec![("jsonrpc", "2.0".into()), ("id", id), ("result", result)])
+}
+
+pub fn error(id: Value, code: i32, message: &str) -> Value {
+    let error = object(vec![("code", Value::Number(code as f64)), ("message", message.into())]);
+    object(vec![("jsonrpc", "2.0".into()), ("id", id), ("error", error)])
+}
+
+fn notification(method: &str, params: Value) -> Value {
+    object(vec![("jsonrpc", "2.0".into()), ("method", method.into()), ("params", params)])
+}
+
+// Byte offset of an LSP position, whose character counts UTF-16 code units
+fn offset(text: &str, position: &Value) -> usize {
+    let line = position.get("line").as_usize().unwrap_or(0);
+    let character = position.get("character").as_usize().unwrap_or(0);
+    let start = match line {
+        0 => 0,
+        _ => match text.match_indices('\n').nth(line - 1) {
+            Some((at, _)) => at + 1,
+            None => return text.len(),
+        },
+    };
+    let mut units = 0;
+    for (at, c) in text[start..].char_indices() {
+        if units >= character || c == '\n' {
+            return start + at;
+        }
+        units += c.len_utf16();
+    }
+    text.len()
+}
+
+fn position(text: &str, offset: usize) -> Value {
+    let before = &text[..offset.min(text.len())];
+    let line = before.matches('\n').count();
+    let line_start = before.rfind('\n').map_or(0, |at| at + 1);
+    let character: usize = before[line_start..].chars().map(char::len_utf16).sum();
+    object(vec![("line", line.into()), ("character", character.into())])
+}
+
+fn range(text: &str, start: usize, end: usize) -> Value {
+    object(vec![("start", position(text, start)), ("end", position(text, end))])
+}
+
+// Byte offsets of the matching brackets, leaving out unmatched ones
+fn brackets(text: &str) -> Vec<(usize, usize)> {
+    let mut pairs = Vec::new();
+    let mut open = Vec::new();
+    for (at, c) in text.char_indices() {
+        match c {
+            '[' => open.push(at),
+            ']' => if let Some(start) = open.pop() {
+                pairs.push((start, at));
+            },
+            _ => (),
+        }
+    }
+    pairs
+}
+
+// What a piece of code does to a cell, relative to the cell under the pointer where it starts
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Cell {
+    Add(u8),
+    Set(u8),
+    AddTimes(isize, u8),  // `+= cell[p+offset]*factor`
+    Input,
+    Changed,              // By a loop, in a way not worth spelling out
+}
+
+#[derive(Debug, Default)]
+struct Effect {
+    moved:   Option<isize>,  // None once a loop moves the pointer by an unknown distance
+    cells:   BTreeMap<isize, Cell>,
+    outputs: usize,
+    inputs:  usize,
+    looped:  bool,           // Whether some I/O happens inside a loop
+}
+
+impl Effect {
+    fn of(code: &[u8]) -> Effect {
+        let mut effect = Effect { moved: Some(0), ..Effect::default() };
+        effect.walk(code);
+        effect
+    }
+
+    fn add(&mut self, at: isize, n: u8) {
+        let cell = match self.cells.get(&at) {
+            None => Cell::Add(n),
+            Some(Cell::Add(m)) => Cell::Add(m.wrapping_add(n)),
+            Some(Cell::Set(v)) => Cell::Set(v.wrapping_add(n)),
+            Some(_) => Cell::Changed,
+        };
+        self.cells.insert(at, cell);
+    }
+
+    fn walk(&mut self, code: &[u8]) {
+        let mut pc = 0;
+        while pc < code.len() {
+            match (code[pc], self.moved) {
+                (b'+', Some(at)) => self.add(at, 1),
+                (b'-', Some(at)) => self.add(at, 255),
+                (b'>', Some(at)) => self.moved = Some(at + 1),
+                (b'<', Some(at)) => self.moved = Some(at - 1),
+                (b'.', _) => self.outputs += 1,
+                (b',', at) => {
+                    self.inputs += 1;
+                    if let Some(at) = at {
+                        self.cells.insert(at, Cell::Input);
+                    }
+                },
+                (b'[', at) => {
+                    let mut depth = 0;
+                    let close = (pc..code.len())
+                        .find(|&idx| {
+                            depth += (code[idx] == b'[') as i32 - (code[idx] == b']') as i32;
+                            depth == 0
+                        })
+                        .unwrap_or(code.len());
+                    let body = Effect::of(&code[pc + 1..close]);
+                    self.looped |= body.outputs + body.inputs > 0;
+                    self.outputs += body.outputs;
+                    self.inputs += body.inputs;
+                    if let Some(at) = at {
+                        self.apply_loop(at, &body);
+                    }
+                    pc = close;
+                },
+                _ => (),
+            }
+            pc += 1;
+        }
+    }
+
+    fn apply_loop(&mut self, at: isize, body: &Effect) {
+        if body.moved != Some(0) {
+            self.moved = None;
+            return;
+        }
+        // A loop counting its cell down by one moves multiples of it to untouched cells
+        let counts_down = body.cells.get(&0) == Some(&Cell::Add(255)) && !self.cells.contains_key(&at);
+        for (&offset, &cell) in &body.cells {
+            let target = at + offset;
+            let moves = match cell {
+                Cell::Add(n) if counts_down && !self.cells.contains_key(&target) => Cell::AddTimes(-offset, n),
+                _ => Cell::Changed,
+            };
+            self.cells.insert(target, moves);
+        }
+        self.cells.insert(at, Cell::Set(0));
+    }
+}
+
+fn cell_name(at: isize) -> String {
+    match at {
+        0 => String::from("cell[p]"),
+        at if at > 0 => format!("cell[p+{}]", at),
+        at => format!("cell[p-{}]", -at),
+    }
+}
+
+fn plural(count: usize, word: &str) -> String {
+    format!("{} {}{}", count, word, if count == 1 { "" } else { "s" })
+}
+
+// Markdown describing the net effect of a piece of code
+fn describe(code: &str) -> Option<String> {
+    let commands: Vec<u8> = code.bytes().filter(|b| b"+-<>[].,".contains(b)).collect();
+    if commands.is_empty() {
+        return None;
+    }
+    let mut depth = 0i32;
+    if commands.iter().any(|&c| {
+        depth += (c == b'[') as i32 - (c == b']') as i32;
+        depth < 0
+    }) || depth != 0 {
+        return Some(String::from("The brackets of this code are unbalanced."));
+    }
+
+    let effect = Effect::of(&commands);
+    let pointer = match effect.moved {
+        None => String::from("moves by an unknown distance, in a loop"),
+        Some(0) => String::from("ends where it started"),
+        Some(at) if at > 0 => format!("moves {} right", at),
+        Some(at) => format!("moves {} left", -at),
+    };
+    let mut text = format!("**Pointer** {}\n", pointer);
+    let cells: Vec<String> = effect.cells.iter()
+        .filter_map(|(&at, &cell)| {
+            let name = cell_name(at);
+            Some(match cell {
+                Cell::Add(0) => return None,
+                Cell::Add(n) if n < 128 => format!("{} += {}", name, n),
+                Cell::Add(n) => format!("{} -= {}", name, n.wrapping_neg()),
+                Cell::Set(v) => format!("{} = {}", name, v),
+                Cell::AddTimes(from, 1) => format!("{} += {}", name, cell_name(at + from)),
+                Cell::AddTimes(from, 255) => format!("{} -= {}", name, cell_name(at + from)),
+                Cell::AddTimes(from, n) if n < 128 => format!("{} += {}*{}", name, cell_name(at + from), n),
+                Cell::AddTimes(from, n) => format!("{} -= {}*{}", name, cell_name(at + from), n.wrapping_neg()),
+                Cell::Input => format!("{} = input", name),
+                Cell::Changed => format!("{} is changed by a loop", name),
+            })
+        })
+        .collect();
+    if cells.is_empty() {
+        text += "\nNo cell changes.\n";
+    } else {
+        text += "\n`p` is the cell under the pointer at the start:\n```\n";
+        text += &cells.join("\n");
+        text += "\n```\n";
+    }
+    if effect.outputs + effect.inputs > 0 {
+        let io = format!("{}, {}", plural(effect.outputs, "output"), plural(effect.inputs, "input"));
+        text += &format!("\n**I/O** {}{}\n", io, if effect.looped { ", some in a loop" } else { "" });
+    }
+    Some(text)
+}
+
+#[derive(Default)]
+pub struct Server {
+    documents: HashMap<String, String>,
+    shutdown:  bool,
+}
+
+impl Server {
+    pub fn new() -> Self {
+        Server::default()
+    }
+
+    // Whether `shutdown` came before `exit`, which decides the exit code
+    pub fn is_shut_down(&self) -> bool {
+        self.shutdown
+    }
+
+    // The responses and notifications to send back for a message
+    pub fn handle(&mut self, message: &Value) -> Vec<Value> {
+        let method = message.get("method").as_str().unwrap_or("");
+        let params = message.get("params");
+        let id = message.get("id").clone();
+        if id.is_null() {
+            return self.notify(method, params);
+        }
+        if self.shutdown {
+            return vec![error(id, INVALID_REQUEST, "The server is shut down.")];
+        }
+        let result = match method {
+            "initialize" => Ok(initialize()),
+            "shutdown" => {
+                self.shutdown = true;
+                Ok(Value::Null)
+            },
+            "textDocument/hover" => self.document(params).map(|text| hover(text, params)),
+            "textDocument/definition" => self.document(params).map(|text| definition(text, params)),
+            "textDocument/formatting" => self.document(params).and_then(|text| formatting(text, params)),
+            _ => Err((METHOD_NOT_FOUND, "Unknown method.")),
+        };
+        match result {
+            Ok(result) => vec![response(id, result)],
+            Err((code, message)) => vec![error(id, code, message)],
+        }
+    }
+
+    fn notify(&mut self, method: &str, params: &Value) -> Vec<Value> {
+        let uri = params.at(&["textDocument", "uri"]).as_str().unwrap_or("").to_string();
+        match method {
+            "textDocument/didOpen" => {
+                let text = params.at(&["textDocument", "text"]).as_str().unwrap_or("");
+                self.documents.insert(uri.clone(), text.to_string());
+            },
+            // Full document sync, so the last change holds the whole text
+            "textDocument/didChange" => match params.get("contentChanges").as_array().last() {
+                Some(change) => {
+                    let text = change.get("text").as_str().unwrap_or("");
+                    self.documents.insert(uri.clone(), text.to_string());
+                },
+                None => return Vec::new(),
+            },
+            "textDocument/didClose" => {
+                self.documents.remove(&uri);
+                return vec![diagnostics(&uri, "")];
+            },
+            _ => return Vec::new(),
+        }
+        vec![diagnostics(&uri, &self.documents[&uri])]
+    }
+
+    fn document(&self, params: &Value) -> Result<&str, (i32, &'static str)> {
+        let uri = params.at(&["textDocument", "uri"]).as_str().ok_or((INVALID_PARAMS, "No document given."))?;
+        self.documents.get(uri).map(String::as_str).ok_or((INVALID_PARAMS, "The document is not open."))
+    }
+}
+
+fn initialize() -> Value {
+    let capabilities = object(vec![
+        ("textDocumentSync", 1.into()),
+        ("hoverProvider", true.into()),
+        ("definitionProvider", true.into()),
+        ("documentFormattingProvider", true.into()),
+    ]);
+    let info = object(vec![("name", "bf-lsp".into()), ("version", env!("CARGO_PKG_VERSION").into())]);
+    object(vec![("capabilities", capabilities), ("serverInfo", info)])
+}
+
+fn diagnostics(uri: &str, text: &str) -> Value {
+    let diagnostics = lint_with_dialect(text, &Dialect::brainfuck()).iter()
+        .map(|lint| {
+            let severity = match lint.kind.severity() {
+                Severity::Error => 1,
+                Severity::Warning => 2,
+            };
+            object(vec![
+                ("range", range(text, lint.span.start, lint.span.end)),
+                ("severity", severity.into()),
+                ("source", "bf".into()),
+                ("message", lint.kind.message().into()),
+            ])
+        })
+        .collect();
+    notification("textDocument/publishDiagnostics", object(vec![("uri", uri.into()), ("diagnostics", Value::Array(diagnostics))]))
+}
+
+// Hovers describe the selection when the client sends one in a `range` beside the position,
+// which is an extension of the protocol, else the innermost loop, else the line
+fn hover(text: &str, params: &Value) -> Value {
+    let (start, end) = if params.get("range").is_null() {
+        let at = offset(text, params.get("position"));
+        match brackets(text).into_iter().filter(|&(open, close)| open <= at && at <= close).max() {
+            Some((open, close)) => (open, close + 1),
+            None => {
+                let start = text[..at].rfind('\n').map_or(0, |idx| idx + 1);
+                let end = text[at..].find('\n').map_or(text.len(), |idx| at + idx);
+                (start, end)
+            },
+        }
+    } else {
+        let selection = params.get("range");
+        (offset(text, selection.get("start")), offset(text, selection.get("end")))
+    };
+    match describe(&text[start.min(end)..end.max(start)]) {
+        Some(markdown) => object(vec![
+            ("contents", object(vec![("kind", "markdown".into()), ("value", markdown.into())])),
+            ("range", range(text, start, end)),
+        ]),
+        None => Value::Null,
+    }
+}
+
+// The bracket matching the one at, or right before, the position
+fn definition(text: &str, params: &Value) -> Value {
+    let at = offset(text, params.get("position"));
+    let on_bracket = |idx: usize| matches!(text.as_bytes().get(idx), Some(b'[') | Some(b']'));
+    let at = match at {
+        at if on_bracket(at) => at,
+        at if at > 0 && on_bracket(at - 1) => at - 1,
+        _ => return Value::Null,
+    };
+    let target = brackets(text).into_iter().find_map(|(open, close)| match at {
+        at if at == open => Some(close),
+        at if at == close => Some(open),
+        _ => None,
+    });
+    match target {
+        Some(target) => object(vec![
+            ("uri", params.at(&["textDocument", "uri"]).clone()),
+            ("range", range(text, target, target + 1)),
+        ]),
+        None => Value::Null,
+    }
+}
+
+fn formatting(text: &str, params: &Value) -> Result<Value, (i32, &'static str)> {
+    let mut config = FormatConfig::default();
+    if let Some(indent) = params.at(&["options", "tabSize"]).as_usize() {
+        config.indent = indent;
+    }
+    let formatted = format_program(text, &config).map_err(|err| (REQUEST_FAILED, err))?;
+    if formatted == text {
+        return Ok(Value::Array(Vec::new()));
+    }
+    let edit = object(vec![("range", range(text, 0, text.len())), ("newText", formatted.into())]);
+    Ok(Value::Array(vec![edit]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::json::parse;
+
+    #[test]
+    fn positions_count_utf16() {
+        let text = "é𝄞+\n[-]";
+        let at = parse(r#"{"line":0,"character":3}"#).unwrap();
+        assert_eq!(offset(text, &at), "é𝄞".len());
+        assert_eq!(position(text, "é𝄞".len()), at);
+        assert_eq!(offset(text, &parse(r#"{"line":1,"character":9}"#).unwrap()), text.len());
+        assert_eq!(offset(text, &parse(r#"{"line":5,"character":0}"#).unwrap()), text.len());
+    }
+
+    #[test]
+    fn describes_effects() {
+        let copy = describe("copy: [->+>++<<]>>-.").unwrap();
+        assert!(copy.contains("**Pointer** moves 2 right"));
+        assert!(copy.contains("cell[p] = 0\ncell[p+1] += cell[p]\ncell[p+2] is changed by a loop"));
+        assert!(copy.contains("**I/O** 1 output, 0 inputs"));
+        let moved = describe("+++<,[>]").unwrap();
+        assert!(moved.contains("moves by an unknown distance"));
+        assert!(moved.contains("cell[p-1] = input\ncell[p] += 3"));
+        assert_eq!(describe("no code"), None);
+        assert_eq!(describe("]["), Some(String::from("The brackets of this code are unbalanced.")));
+    }
+}
@@ -0,0 +1,233 @@
+use rusty_brainfuck::{CellWidth, EofPolicy, GenerateConfig, Target, Tradeoff};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Command {
+    Run,
+    Repl,
+    Debug,
+    Compile,
+    Fmt,
+    Lint,
+    Minify,
+    Generate,
+    Profile,
+    Trace,
+    Test,
+    Help,
+}
+
+// What `,` does once the input has ended
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Eof {
+    Store(EofPolicy),
+    Fail,
+}
+
+// How trace and test write their reports
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Text,
+    Json,
+    Junit,
+}
+
+#[derive(Debug, Clone)]
+pub struct Options {
+    pub command:    Command,
+    pub file:       Option<String>,
+    pub tape_size:  Option<usize>,
+    pub cell_width: CellWidth,
+    pub eof:        Eof,
+    pub fuel:       Option<u64>,
+    pub input:      Option<String>,  // A file read instead of stdin
+    pub dialect:    Option<String>,  // A builtin name or a dialect file, detected when missing
+    pub output:     Option<String>,  // A file written instead of stdout (or stderr for reports)
+    pub target:     Target,
+    pub check:      bool,
+    pub format:     Format,
+    pub every:      u64,
+    pub generate:   GenerateConfig,
+}
+
+pub const USAGE: &str = "\
+usage: bf <command> [options] [file]
+
+commands:
+  run FILE        run a program (Brainfuck, a dialect or bytecode)
+  repl            run code line by line on one tape, :help lists its commands
+  debug FILE      step through a program in the terminal, with the tape, output and input
+  compile FILE    compile to --target c, wasm (text format) or bytecode
+  fmt FILE        format a Brainfuck program, or with --check only report whether it is
+                  formatted
+  lint FILE       report mistakes and redundant code
+  minify FILE     strip comments and redundant code
+  generate FILE   write a program which prints the contents of FILE (- for stdin)
+  profile FILE    run a program and report how often each instruction and loop ran
+  trace FILE      run a program and write every step
+  test [PATH]     run every foo.bf under PATH (default .) against foo.in and foo.out,
+                  or the `;; in:` and `;; out:` lines at its top
+
+options:
+  --tape-size N     number of cells (default 30000)
+  --cell-width BITS 8 (default), 16 or 32, wrapping around past the largest value
+  --eof POLICY      what `,` stores at the end of input: zero (default), minus-one,
+                    unchanged, or error to stop the program
+  --fuel N          stop after N steps (test: 100000000 by default)
+  --input FILE      read the program's input from FILE instead of stdin (in debug, stdin
+                    when it isn't a terminal, or else what is typed)
+  --dialect NAME    a builtin dialect or a dialect file, detected by default (test: the
+                    dialect of fixtures without a `;; dialect:` line, Brainfuck by default)
+  -o, --output FILE write the result (or the report of profile and trace) to FILE
+  --target TARGET   c, wasm or bytecode (compile)
+  --check           don't print the formatted program (fmt)
+  --format FORMAT   text or json (trace), or junit too (test)
+  --every N         write only every Nth step (trace)
+  --tradeoff KIND   size (default) for short programs with a loop, or speed for no
+                    loops at all (generate)
+  --max-cells N     how many cells may hold recently printed bytes (generate, default 4)
+
+exit codes:
+  0  success
+  1  the program failed at run time, lint found errors, fmt --check found changes,
+     or a test failed
+  2  invalid command line
+  3  the program can't be parsed or compiled
+  4  a file couldn't be read or written
+  5  the program ran out of fuel
+  6  the program read past the end of input with --eof error
+";
+
+fn number<T: std::str::FromStr>(flag: &str, value: &str) -> Result<T, String> {
+    value.parse().map_err(|_| format!("{} expects a number, not `{}`", flag, value))
+}
+
+pub fn parse<I: Iterator<Item = String>>(mut args: I) -> Result<Options, String> {
+    let command = match args.next().as_deref() {
+        Some("run") => Command::Run,
+        Some("repl") => Command::Repl,
+        Some("debug") => Command::Debug,
+        Some("compile") => Command::Compile,
+        Some("fmt") => Command::Fmt,
+        Some("lint") => Command::Lint,
+        Some("minify") => Command::Minify,
+        Some("generate") => Command::Generate,
+        Some("profile") => Command::Profile,
+        Some("trace") => Command::Trace,
+        Some("test") => Command::Test,
+        Some("help") | Some("-h") | Some("--help") | None => Command::Help,
+        Some(other) => return Err(format!("unknown command `{}`", other)),
+    };
+    let mut options = Options {
+        command,
+        file:       None,
+        tape_size:  None,
+        cell_width: CellWidth::Bits8,
+        eof:        Eof::Store(EofPolicy::Zero),
+        fuel:       None,
+        input:      None,
+        dialect:    None,
+        output:     None,
+        target:     Target::C,
+        check:      false,
+        format:     Format::Text,
+        every:      1,
+        generate:   GenerateConfig::default(),
+    };
+
+    while let Some(arg) = args.next() {
+        if !arg.starts_with('-') || arg == "-" {
+            if options.file.replace(arg).is_some() {
+                return Err(String::from("only one file can be given"));
+            }
+            continue;
+        }
+        // `--flag=value` and `--flag value` are the same
+        let (flag, inline) = match arg.find('=') {
+            Some(at) => (arg[..at].to_string(), Some(arg[at + 1..].to_string())),
+            None => (arg.clone(), None),
+        };
+        if flag == "--check" {
+            options.check = true;
+            continue;
+        }
+        if flag == "-h" || flag == "--help" {
+            options.command = Command::Help;
+            continue;
+        }
+        let value = match inline.or_else(|| args.next()) {
+            Some(value) => value,
+            None => return Err(format!("{} expects a value", flag)),
+        };
+        match flag.as_str() {
+            "--tape-size" => options.tape_size = Some(number(&flag, &value)?),
+            "--cell-width" => options.cell_width = CellWidth::from_bits(number(&flag, &value)?)
+                .ok_or_else(|| format!("unknown cell width `{}`", value))?,
+            "--eof" => options.eof = match value.as_str() {
+                "error" => Eof::Fail,
+                name => Eof::Store(EofPolicy::from_name(name)
+                    .ok_or_else(|| format!("unknown EOF policy `{}`", name))?),
+            },
+            "--fuel" => options.fuel = Some(number(&flag, &value)?),
+            "--input" => options.input = Some(value),
+            "--dialect" => options.dialect = Some(value),
+            "-o" | "--output" => options.output = Some(value),
+            "--target" => options.target = Target::from_name(&value)
+                .ok_or_else(|| format!("unknown target `{}`", value))?,
+            "--format" => options.format = match value.as_str() {
+                "text" => Format::Text,
+                "json" => Format::Json,
+                "junit" => Format::Junit,
+                _ => return Err(format!("unknown format `{}`", value)),
+            },
+            "--every" => options.every = number(&flag, &value)?,
+            "--tradeoff" => options.generate.tradeoff = Tradeoff::from_name(&value)
+                .ok_or_else(|| format!("unknown tradeoff `{}`", value))?,
+            "--max-cells" => options.generate.max_cells = number(&flag, &value)?,
+            _ => return Err(format!("unknown option `{}`", flag)),
+        }
+    }
+
+    let needs_file = !matches!(options.command, Command::Repl | Command::Test | Command::Help);
+    if needs_file && options.file.is_none() {
+        return Err(String::from("missing the program file"));
+    }
+    if options.format == Format::Junit && options.command != Command::Test {
+        return Err(String::from("--format junit is only for test"));
+    }
+    Ok(options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Result<Options, String> {
+        parse(line.split_whitespace().map(String::from))
+    }
+
+    #[test]
+    fn flags_and_file() {
+        let options = args("run --fuel=10 --eof minus-one hello.bf --tape-size 100").unwrap();
+        assert_eq!(options.command, Command::Run);
+        assert_eq!(options.file.as_deref(), Some("hello.bf"));
+        assert_eq!((options.fuel, options.tape_size), (Some(10), Some(100)));
+        assert_eq!(options.eof, Eof::Store(EofPolicy::MinusOne));
+        assert_eq!(args("run --cell-width 16 a.bf").unwrap().cell_width, CellWidth::Bits16);
+        assert!(args("run --cell-width 12 a.bf").is_err());
+        assert_eq!(args("compile --target wasm a.bf").unwrap().target, Target::Wasm);
+        assert_eq!(args("repl").unwrap().file, None);
+        let generate = args("generate --tradeoff speed --max-cells 2 -").unwrap().generate;
+        assert_eq!(generate, GenerateConfig { tradeoff: Tradeoff::Speed, max_cells: 2 });
+    }
+
+    #[test]
+    fn invalid_arguments() {
+        assert!(args("run").is_err());
+        assert!(args("jump a.bf").is_err());
+        assert!(args("run a.bf b.bf").is_err());
+        assert!(args("run --fuel lots a.bf").is_err());
+        assert!(args("run a.bf --input").is_err());
+        assert!(args("trace --format junit a.bf").is_err());
+        assert!(args("generate --tradeoff fast a.txt").is_err());
+    }
+}
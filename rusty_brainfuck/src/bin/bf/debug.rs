@@ -0,0 +1,505 @@
+use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
+use std::io::{self, IsTerminal, Read, Write};
+use std::process::{Command, Stdio};
+
+use rusty_brainfuck::{Brainfuck, Inst};
+
+use crate::args::{Eof, Options};
+use crate::repl::hex_dump;
+use crate::run::{load, machine, read_file};
+use crate::Failure;
+
+const KEYS: &str = "s step  n step over  c continue  r run to cursor  b breakpoint  \
+                    arrows move  g go to pc  i input  ctrl-l resize  q quit";
+
+// Steps run between looks at the keyboard, so that a key interrupts `continue`
+const POLL_EVERY: u64 = 1 << 16;
+
+// Why running stopped
+#[derive(Debug, PartialEq)]
+enum Stop {
+    Steps,
+    Done,
+    Breakpoint,
+    Target,
+    Input,
+    Interrupted,
+    Error(String),
+}
+
+pub struct Debugger {
+    bf:      Brainfuck,
+    source:  String,
+    lines:   Vec<usize>,     // The source line of each instruction
+    eof:     Eof,
+    queue:   VecDeque<u8>,   // Input waiting for `,`
+    closed:  bool,           // Whether the queue is all the input there will be
+    output:  Vec<u8>,
+    cursor:  usize,          // The instruction the cursor is on
+    steps:   u64,
+    message: String,
+}
+
+impl Debugger {
+    pub fn new(bf: Brainfuck, source: String, eof: Eof) -> Self {
+        let lines = (0..bf.insts().len())
+            .map(|pc| bf.span(pc).map_or(1, |span| span.line_col(&source).0))
+            .collect();
+        Debugger {
+            bf,
+            source,
+            lines,
+            eof,
+            queue:   VecDeque::new(),
+            closed:  false,
+            output:  Vec::new(),
+            cursor:  0,
+            steps:   0,
+            message: String::new(),
+        }
+    }
+
+    pub fn with_input(mut self, input: &[u8]) -> Self {
+        self.queue.extend(input);
+        self.closed = true;
+        self
+    }
+
+    fn run(&mut self, limit: Option<u64>, target: Option<usize>, interrupt: &mut dyn FnMut() -> bool) -> Stop {
+        let mut steps = 0;
+        loop {
+            if self.bf.is_input_mode() {
+                let answered = match (self.queue.pop_front(), self.eof) {
+                    (Some(byte), _) => self.bf.set_input_byte(byte),
+                    (None, Eof::Store(policy)) if self.closed => self.bf.set_eof(policy),
+                    (None, Eof::Fail) if self.closed => return Stop::Error(String::from("read past the end of input")),
+                    (None, _) => return Stop::Input,
+                };
+                if let Err(err) = answered {
+                    return Stop::Error(String::from(err));
+                }
+                continue;
+            }
+            if self.bf.reach_eop() {
+                return Stop::Done;
+            }
+            if steps > 0 && self.bf.at_breakpoint() {
+                return Stop::Breakpoint;
+            }
+            if steps > 0 && target == Some(self.bf.pc()) {
+                return Stop::Target;
+            }
+            if limit == Some(steps) {
+                return Stop::Steps;
+            }
+            if steps > 0 && steps % POLL_EVERY == 0 && interrupt() {
+                return Stop::Interrupted;
+            }
+            let writes = self.bf.insts()[self.bf.pc()] == Inst::Wrt;
+            if let Err(err) = self.bf.step() {
+                let (line, col) = self.bf.span(self.bf.pc()).map_or((0, 0), |span| span.line_col(&self.source));
+                return Stop::Error(format!("{}:{}: {}", line, col, err));
+            }
+            if writes {
+                self.output.extend(self.bf.pop_result().chars().map(|c| c as u8));
+            }
+            steps += 1;
+            self.steps += 1;
+        }
+    }
+
+    fn finish(&mut self, stop: Stop) {
+        self.message = match stop {
+            Stop::Steps | Stop::Target => String::new(),
+            Stop::Done => String::from("the program ended"),
+            Stop::Breakpoint => String::from("stopped at a breakpoint"),
+            Stop::Input => String::from("waiting for input, press i to type it"),
+            Stop::Interrupted => String::from("interrupted"),
+            Stop::Error(message) => format!("error: {}", message),
+        };
+        self.cursor = self.bf.pc().min(self.bf.insts().len().saturating_sub(1));
+    }
+
+    pub fn step(&mut self) {
+        let stop = self.run(Some(1), None, &mut || false);
+        self.finish(stop);
+    }
+
+    // Run a whole loop when on its `[`, otherwise just one step
+    pub fn step_over(&mut self, interrupt: &mut dyn FnMut() -> bool) {
+        let stop = match self.bf.insts().get(self.bf.pc()) {
+            Some(&Inst::Jpf(close)) => self.run(None, Some(close + 1), interrupt),
+            _ => self.run(Some(1), None, interrupt),
+        };
+        self.finish(stop);
+    }
+
+    pub fn resume(&mut self, interrupt: &mut dyn FnMut() -> bool) {
+        let stop = self.run(None, None, interrupt);
+        self.finish(stop);
+    }
+
+    pub fn run_to_cursor(&mut self, interrupt: &mut dyn FnMut() -> bool) {
+        let stop = self.run(None, Some(self.cursor), interrupt);
+        self.finish(stop);
+    }
+
+    pub fn toggle_breakpoint(&mut self) {
+        let pc = self.cursor;
+        if self.bf.breakpoints().contains(&pc) {
+            self.bf.remove_breakpoint(pc);
+        } else if pc < self.bf.insts().len() {
+            self.bf.add_breakpoint(pc);
+        }
+    }
+
+    pub fn type_input(&mut self, line: &str) {
+        self.queue.extend(line.bytes());
+        self.queue.push_back(b'\n');
+    }
+
+    pub fn end_input(&mut self) {
+        self.closed = true;
+    }
+
+    pub fn move_cursor(&mut self, key: Key) {
+        let last = self.lines.len().saturating_sub(1);
+        let line = self.lines.get(self.cursor).copied().unwrap_or(1);
+        self.cursor = match key {
+            Key::Left => self.cursor.saturating_sub(1),
+            Key::Right => (self.cursor + 1).min(last),
+            Key::Up => match (0..self.cursor).rev().find(|&pc| self.lines[pc] < line) {
+                Some(pc) => self.lines.iter().position(|&l| l == self.lines[pc]).unwrap_or(pc),
+                None => self.cursor,
+            },
+            Key::Down => (self.cursor..self.lines.len()).find(|&pc| self.lines[pc] > line).unwrap_or(self.cursor),
+            _ => self.bf.pc().min(last),
+        };
+    }
+
+    fn header(&self, width: usize) -> String {
+        let pc = self.bf.pc();
+        let at = match (self.bf.insts().get(pc), self.bf.span(pc)) {
+            (Some(inst), Some(span)) => {
+                let (line, col) = span.line_col(&self.source);
+                format!("pc {} {:?} at {}:{}", pc, inst, line, col)
+            },
+            _ => String::from("end of program"),
+        };
+        let pointer = self.bf.pointer();
+        let text = format!(" bf debug | {} | pointer {} = {} | {} steps", at, pointer, self.bf.memory()[pointer], self.steps);
+        format!("\x1b[7m{:<width$}\x1b[0m", clip(&text, width), width = width)
+    }
+
+    // Source lines around the cursor: the current instruction reversed, the cursor underlined and
+    // breakpoints in red
+    fn source_pane(&self, width: usize, height: usize) -> Vec<String> {
+        let lines: Vec<&str> = self.source.split('\n').collect();
+        let focus = self.lines.get(self.cursor).copied().unwrap_or(1) - 1;
+        let first = focus.saturating_sub(height / 2).min(lines.len().saturating_sub(height));
+        let pc = self.bf.span(self.bf.pc());
+        let cursor = self.bf.span(self.cursor);
+        let breakpoints: Vec<_> = self.bf.breakpoints().iter().filter_map(|&pc| self.bf.span(pc)).collect();
+        let mut start = lines[..first].iter().map(|line| line.len() + 1).sum::<usize>();
+        let mut pane = Vec::new();
+        for (n, line) in lines.iter().enumerate().skip(first).take(height) {
+            let within = |span: &rusty_brainfuck::Span, at: usize| span.start <= at && at < span.end;
+            let end = start + line.len();
+            let mark = match () {
+                _ if pc.is_some_and(|span| span.start >= start && span.start <= end) => '>',
+                _ if breakpoints.iter().any(|span| span.start >= start && span.start <= end) => '*',
+                _ => ' ',
+            };
+            let mut text = format!("{:>4}{} ", n + 1, mark);
+            let mut style = String::new();
+            for (offset, c) in line.char_indices().take(width.saturating_sub(6)) {
+                let at = start + offset;
+                let mut next = String::new();
+                if pc.is_some_and(|span| within(&span, at)) {
+                    next += "\x1b[7m";
+                }
+                if cursor.is_some_and(|span| within(&span, at)) {
+                    next += "\x1b[4m";
+                }
+                if breakpoints.iter().any(|span| within(span, at)) {
+                    next += "\x1b[31m";
+                }
+                if next != style {
+                    text += "\x1b[0m";
+                    text += &next;
+                    style = next;
+                }
+                text.push(if c.is_control() { ' ' } else { c });
+            }
+            text += "\x1b[0m";
+            pane.push(text);
+            start = end + 1;
+        }
+        pane.resize(height, String::new());
+        pane
+    }
+
+    pub fn render(&self, width: usize, height: usize) -> Vec<String> {
+        let title = |name: &str| clip(&format!("-- {} {}", name, "-".repeat(width)), width);
+        let mut tape: Vec<String> = hex_dump(self.bf.memory(), self.bf.cell_width(), self.bf.pointer(), 16).lines().map(String::from).collect();
+        tape.resize(4, String::new());
+        let output = String::from_utf8_lossy(&self.output);
+        let mut shown: Vec<&str> = output.split('\n').collect();
+        shown.drain(..shown.len().saturating_sub(3));
+        shown.resize(3, "");
+        let mut queue: String = self.queue.iter().flat_map(|&byte| std::ascii::escape_default(byte)).map(char::from).collect();
+        if self.closed {
+            queue += " (end of input)";
+        }
+
+        let mut screen = vec![self.header(width)];
+        screen.extend(self.source_pane(width, height.saturating_sub(14).max(3)));
+        screen.push(title("tape"));
+        screen.extend(tape);
+        screen.push(title("output"));
+        screen.extend(shown.iter().map(|line| line.chars().map(|c| if c.is_control() { ' ' } else { c }).collect()));
+        screen.push(title("input"));
+        screen.push(queue);
+        screen.push(self.message.clone());
+        screen.push(String::from(KEYS));
+        screen.into_iter().map(|line| if line.contains('\x1b') { line } else { clip(&line, width) }).collect()
+    }
+}
+
+fn clip(text: &str, width: usize) -> String {
+    text.chars().take(width).collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Key {
+    Char(char),
+    Up,
+    Down,
+    Left,
+    Right,
+    Enter,
+    Backspace,
+    Escape,
+    Interrupt,
+    EndOfInput,
+    Redraw,
+    Other,
+}
+
+pub fn parse_key(bytes: &[u8]) -> Key {
+    match bytes {
+        [0x1b, b'[', b'A', ..] => Key::Up,
+        [0x1b, b'[', b'B', ..] => Key::Down,
+        [0x1b, b'[', b'C', ..] => Key::Right,
+        [0x1b, b'[', b'D', ..] => Key::Left,
+        [0x1b] => Key::Escape,
+        [b'\r', ..] | [b'\n', ..] => Key::Enter,
+        [0x7f, ..] | [0x08, ..] => Key::Backspace,
+        [0x03, ..] => Key::Interrupt,
+        [0x04, ..] => Key::EndOfInput,
+        [0x0c, ..] => Key::Redraw,
+        [byte, ..] if (0x20..0x7f).contains(byte) => Key::Char(*byte as char),
+        _ => Key::Other,
+    }
+}
+
+// The controlling terminal in raw mode, set up with stty so that no library is needed
+struct Terminal {
+    tty:     File,
+    saved:   String,
+    size:    (usize, usize),  // Columns and rows, asked for again on ctrl-l
+    polling: bool,            // Whether reads return at once, even without a key
+}
+
+fn stty(args: &[&str]) -> Result<String, Failure> {
+    let tty = File::open("/dev/tty").map_err(|err| Failure::Io(format!("/dev/tty: {}", err)))?;
+    let output = Command::new("stty").args(args).stdin(tty).stderr(Stdio::null()).output()
+        .map_err(|err| Failure::Io(format!("stty: {}", err)))?;
+    if !output.status.success() {
+        return Err(Failure::Io(String::from("stty failed, the debugger needs a terminal")));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+fn size() -> (usize, usize) {
+    let size = stty(&["size"]).unwrap_or_default();
+    let mut numbers = size.split_whitespace().filter_map(|n| n.parse().ok());
+    match (numbers.next(), numbers.next()) {
+        (Some(rows), Some(cols)) if rows > 0 && cols > 0 => (cols, rows),
+        _ => (80, 24),
+    }
+}
+
+impl Terminal {
+    fn open() -> Result<Self, Failure> {
+        let tty = OpenOptions::new().read(true).write(true).open("/dev/tty")
+            .map_err(|err| Failure::Io(format!("/dev/tty: {}", err)))?;
+        let saved = stty(&["-g"])?;
+        stty(&["raw", "-echo", "min", "1", "time", "0"])?;
+        let mut terminal = Terminal { tty, saved, size: size(), polling: false };
+        // The alternate screen keeps the shell's scrollback as it was
+        terminal.write("\x1b[?1049h\x1b[?25l")?;
+        Ok(terminal)
+    }
+
+    fn write(&mut self, text: &str) -> Result<(), Failure> {
+        self.tty.write_all(text.as_bytes()).and_then(|_| self.tty.flush())
+            .map_err(|err| Failure::Io(format!("/dev/tty: {}", err)))
+    }
+
+    fn draw(&mut self, screen: &[String]) -> Result<(), Failure> {
+        let frame = format!("\x1b[H{}\x1b[J", screen.join("\x1b[K\r\n"));
+        self.write(&frame)
+    }
+
+    fn key(&mut self) -> Result<Key, Failure> {
+        let mut bytes = [0; 8];
+        loop {
+            let n = self.tty.read(&mut bytes).map_err(|err| Failure::Io(format!("/dev/tty: {}", err)))?;
+            if n > 0 {
+                return Ok(parse_key(&bytes[..n]));
+            }
+        }
+    }
+
+    // Run the program with reads which don't wait for a key, so that `pressed` can look at
+    // the keyboard
+    fn polling<T>(&mut self, run: impl FnOnce(&mut Terminal) -> T) -> T {
+        self.polling = stty(&["min", "0", "time", "0"]).is_ok();
+        let result = run(self);
+        if self.polling {
+            let _ = stty(&["min", "1", "time", "0"]);
+            self.polling = false;
+        }
+        result
+    }
+
+    // Whether a key was pressed, without waiting for one
+    fn pressed(&mut self) -> bool {
+        let mut bytes = [0; 8];
+        self.polling && matches!(self.tty.read(&mut bytes), Ok(n) if n > 0)
+    }
+
+    // A line typed on the status line, or None when it was cancelled
+    fn prompt(&mut self, screen: &mut [String], label: &str) -> Result<Option<String>, Failure> {
+        let mut line = String::new();
+        loop {
+            let at = screen.len() - 2;
+            screen[at] = format!("{}{}_", label, line);
+            self.draw(screen)?;
+            match self.key()? {
+                Key::Enter => return Ok(Some(line)),
+                Key::Escape | Key::Interrupt => return Ok(None),
+                Key::Backspace => {
+                    line.pop();
+                },
+                Key::Char(c) => line.push(c),
+                _ => (),
+            }
+        }
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        let _ = self.write("\x1b[?25h\x1b[?1049l");
+        let _ = stty(&[&self.saved]);
+    }
+}
+
+pub fn debug(options: &Options) -> Result<(), Failure> {
+    let (source, dialect) = load(options)?;
+    let bf = machine(options, &source, &dialect)?;
+    let mut debugger = Debugger::new(bf, source, options.eof);
+    // Keys come from the terminal, so piped stdin can still be the program's input
+    if let Some(path) = options.input.as_deref() {
+        debugger = debugger.with_input(&read_file(path)?);
+    } else if !io::stdin().is_terminal() {
+        debugger = debugger.with_input(&read_file("-")?);
+    }
+
+    let mut terminal = Terminal::open()?;
+    loop {
+        let (width, height) = terminal.size;
+        let mut screen = debugger.render(width, height);
+        terminal.draw(&screen)?;
+        let key = terminal.key()?;
+        match key {
+            Key::Char('q') | Key::Interrupt => return Ok(()),
+            Key::Char('s') | Key::Char(' ') => debugger.step(),
+            Key::Char('n') => terminal.polling(|terminal| debugger.step_over(&mut || terminal.pressed())),
+            Key::Char('c') => terminal.polling(|terminal| debugger.resume(&mut || terminal.pressed())),
+            Key::Char('r') => terminal.polling(|terminal| debugger.run_to_cursor(&mut || terminal.pressed())),
+            Key::Char('b') => debugger.toggle_breakpoint(),
+            Key::Char('i') => {
+                let label = "input (enter adds a line, ctrl-d ends the input, esc cancels): ";
+                if let Some(line) = terminal.prompt(&mut screen, label)? {
+                    debugger.type_input(&line);
+                }
+            },
+            Key::EndOfInput => debugger.end_input(),
+            Key::Redraw => terminal.size = size(),
+            Key::Char('h') => debugger.move_cursor(Key::Left),
+            Key::Char('l') => debugger.move_cursor(Key::Right),
+            Key::Char('k') => debugger.move_cursor(Key::Up),
+            Key::Char('j') => debugger.move_cursor(Key::Down),
+            Key::Char('g') => debugger.move_cursor(Key::Other),
+            key @ (Key::Up | Key::Down | Key::Left | Key::Right) => debugger.move_cursor(key),
+            _ => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rusty_brainfuck::EofPolicy;
+
+    fn debugger(program: &str) -> Debugger {
+        let bf = Brainfuck::new(String::from(program)).unwrap();
+        Debugger::new(bf, String::from(program), Eof::Store(EofPolicy::Zero))
+    }
+
+    #[test]
+    fn step_over_and_run_to_cursor() {
+        let mut d = debugger("+++[>++<-]>.\n>+>+");
+        d.step();
+        d.step_over(&mut || false);
+        assert_eq!((d.bf.pc(), &d.bf.memory()[..2]), (7, &[0, 6][..]));
+        d.move_cursor(Key::Down);
+        assert_eq!(d.cursor, 9);
+        d.run_to_cursor(&mut || false);
+        assert_eq!((d.bf.pc(), d.bf.pointer(), d.output.as_slice()), (9, 1, &[6][..]));
+        d.resume(&mut || false);
+        assert_eq!(d.message, "the program ended");
+    }
+
+    #[test]
+    fn breakpoints_and_input() {
+        let mut d = debugger(",[.,]");
+        d.cursor = 2;
+        d.toggle_breakpoint();
+        d.resume(&mut || false);
+        assert_eq!(d.message, "waiting for input, press i to type it");
+        d.type_input("ab");
+        d.resume(&mut || false);
+        assert_eq!((d.bf.pc(), d.message.as_str()), (2, "stopped at a breakpoint"));
+        d.toggle_breakpoint();
+        d.end_input();
+        d.resume(&mut || false);
+        assert_eq!(d.output, b"ab\n");
+        let screen = d.render(80, 24);
+        assert_eq!(screen.len(), 24);
+        assert!(screen[0].contains("end of program"));
+    }
+
+    #[test]
+    fn keys() {
+        assert_eq!(parse_key(b"\x1b[A"), Key::Up);
+        assert_eq!(parse_key(b"\x1b"), Key::Escape);
+        assert_eq!(parse_key(b"s"), Key::Char('s'));
+        assert_eq!(parse_key(b"\r"), Key::Enter);
+        assert_eq!(parse_key(b"\x0c"), Key::Redraw);
+    }
+}
@@ -0,0 +1,248 @@
+extern crate rusty_brainfuck;
+
+mod args;
+mod debug;
+mod repl;
+mod run;
+
+use std::env;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Write};
+use std::process;
+
+use rusty_brainfuck::{
+    compile_with_dialect, discover, format_program, generate, lint_with_dialect, minify, minify_with_tape_size,
+    CompileConfig, Dialect, FixtureConfig, FormatConfig, Profiler, Severity, TestReport, TraceFilter, TraceFormat, Tracer,
+};
+
+use args::{Command, Format, Options, USAGE};
+use repl::Session;
+use run::{execute, load, machine, read_file, stdout, write_output, Input};
+
+const EXIT_OK: i32 = 0;
+const EXIT_FAILURE: i32 = 1;
+const EXIT_USAGE: i32 = 2;
+const EXIT_PARSE: i32 = 3;
+const EXIT_IO: i32 = 4;
+const EXIT_FUEL: i32 = 5;
+const EXIT_EOF: i32 = 6;
+
+// Why a command failed, each with its own exit code
+pub enum Failure {
+    Usage(String),
+    Io(String),
+    Parse(String),
+    Runtime(String),
+    Fuel,
+    Eof,
+}
+
+impl Failure {
+    fn code(&self) -> i32 {
+        match self {
+            Failure::Usage(_) => EXIT_USAGE,
+            Failure::Io(_) => EXIT_IO,
+            Failure::Parse(_) => EXIT_PARSE,
+            Failure::Runtime(_) => EXIT_FAILURE,
+            Failure::Fuel => EXIT_FUEL,
+            Failure::Eof => EXIT_EOF,
+        }
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Failure::Usage(message) | Failure::Io(message) | Failure::Parse(message) => write!(f, "{}", message),
+            Failure::Runtime(message) => write!(f, "runtime error: {}", message),
+            Failure::Fuel => write!(f, "out of fuel"),
+            Failure::Eof => write!(f, "read past the end of input"),
+        }
+    }
+}
+
+// Reports of profile and trace go to stderr, so they don't mix with the output of the program
+fn report_writer(options: &Options) -> Result<Box<dyn Write>, Failure> {
+    match options.output.as_deref() {
+        Some(path) => {
+            let file = File::create(path).map_err(|err| Failure::Io(format!("{}: {}", path, err)))?;
+            Ok(Box::new(file))
+        },
+        None => Ok(Box::new(io::stderr())),
+    }
+}
+
+fn run_program(options: &Options) -> Result<i32, Failure> {
+    let (source, dialect) = load(options)?;
+    let mut bf = machine(options, &source, &dialect)?;
+    execute(&mut bf, &mut Input::new(options), options.eof, &source, &mut stdout())?;
+    Ok(EXIT_OK)
+}
+
+fn repl(options: &Options) -> Result<i32, Failure> {
+    Session::new(options)?.run_loop()?;
+    Ok(EXIT_OK)
+}
+
+fn debug(options: &Options) -> Result<i32, Failure> {
+    debug::debug(options)?;
+    Ok(EXIT_OK)
+}
+
+fn compile(options: &Options) -> Result<i32, Failure> {
+    let (source, dialect) = load(options)?;
+    let mut config = CompileConfig { target: options.target, ..CompileConfig::default() };
+    config.tape_size = options.tape_size.unwrap_or(config.tape_size);
+    config.cell_width = options.cell_width;
+    if let args::Eof::Store(policy) = options.eof {
+        config.eof = policy;
+    }
+    let code = compile_with_dialect(&source, &dialect, &config).map_err(|err| Failure::Parse(String::from(err)))?;
+    write_output(options, &code)?;
+    Ok(EXIT_OK)
+}
+
+fn fmt(options: &Options) -> Result<i32, Failure> {
+    let (source, dialect) = load(options)?;
+    // The layout of loops and comments is that of Brainfuck, and wouldn't survive a translation
+    if dialect != Dialect::brainfuck() {
+        return Err(Failure::Parse(format!("fmt only formats Brainfuck, not {}", dialect.name())));
+    }
+    let formatted = format_program(&source, &FormatConfig::default()).map_err(|err| Failure::Parse(String::from(err)))?;
+    if options.check {
+        if formatted == source {
+            return Ok(EXIT_OK);
+        }
+        eprintln!("{}: not formatted", options.file.as_deref().unwrap_or("-"));
+        return Ok(EXIT_FAILURE);
+    }
+    write_output(options, formatted.as_bytes())?;
+    Ok(EXIT_OK)
+}
+
+fn lint(options: &Options) -> Result<i32, Failure> {
+    let (source, dialect) = load(options)?;
+    let lints = lint_with_dialect(&source, &dialect);
+    let path = options.file.as_deref().unwrap_or("-");
+    let report: String = lints.iter().map(|l| format!("{}:{}\n", path, l.describe(&source))).collect();
+    write_output(options, report.as_bytes())?;
+    if lints.iter().any(|l| l.kind.severity() == Severity::Error) {
+        Ok(EXIT_FAILURE)
+    } else {
+        Ok(EXIT_OK)
+    }
+}
+
+// Programs in other dialects are minified as Brainfuck, and translated back
+fn minify_program(options: &Options) -> Result<i32, Failure> {
+    let (source, dialect) = load(options)?;
+    let path = options.file.as_deref().unwrap_or("-");
+    let brainfuck = dialect.translate(&source, &Dialect::brainfuck()).map_err(|err| {
+        let (line, col) = err.span.line_col(&source);
+        Failure::Parse(format!("{}:{}:{}: {}", path, line, col, err))
+    })?;
+    let minified = match options.tape_size {
+        Some(size) => minify_with_tape_size(&brainfuck, size),
+        None => minify(&brainfuck),
+    };
+    let minified = minified.map_err(|err| Failure::Parse(format!("{}: {}", path, err)))?;
+    // Every command of Brainfuck has a token in every dialect
+    let minified = Dialect::brainfuck().translate(&minified, &dialect).unwrap();
+    write_output(options, format!("{}\n", minified).as_bytes())?;
+    Ok(EXIT_OK)
+}
+
+fn generate_program(options: &Options) -> Result<i32, Failure> {
+    let text = read_file(options.file.as_deref().unwrap_or("-"))?;
+    write_output(options, format!("{}\n", generate(&text, &options.generate)).as_bytes())?;
+    Ok(EXIT_OK)
+}
+
+fn profile(options: &Options) -> Result<i32, Failure> {
+    let (source, dialect) = load(options)?;
+    let mut report = report_writer(options)?;
+    let mut bf = machine(options, &source, &dialect)?.with_observer(Profiler::new());
+    let result = execute(&mut bf, &mut Input::new(options), options.eof, &source, &mut stdout());
+    // Even a failed run is worth a report
+    let text = bf.observer().report(&bf).report(&source);
+    report.write_all(text.as_bytes()).map_err(|err| Failure::Io(format!("report: {}", err)))?;
+    result.map(|_| EXIT_OK)
+}
+
+fn trace(options: &Options) -> Result<i32, Failure> {
+    let (source, dialect) = load(options)?;
+    let filter = TraceFilter { every: options.every.max(1), ..TraceFilter::default() };
+    let format = if options.format == Format::Json { TraceFormat::JsonLines } else { TraceFormat::Text };
+    let tracer = Tracer::new(report_writer(options)?, format, filter);
+    let mut bf = machine(options, &source, &dialect)?.with_observer(tracer);
+    let result = execute(&mut bf, &mut Input::new(options), options.eof, &source, &mut stdout());
+    let (_, tracer) = bf.detach_observer();
+    tracer.finish().map_err(|err| Failure::Io(String::from(err)))?;
+    result.map(|_| EXIT_OK)
+}
+
+fn test(options: &Options) -> Result<i32, Failure> {
+    let defaults = FixtureConfig::default();
+    let eof = match options.eof {
+        args::Eof::Store(policy) => policy,
+        args::Eof::Fail => return Err(Failure::Usage(String::from("test doesn't support --eof error"))),
+    };
+    if options.input.is_some() {
+        return Err(Failure::Usage(String::from("test doesn't support --input, each fixture has its own")));
+    }
+    let dialect = match options.dialect {
+        Some(_) => run::dialect(options, "")?,
+        None => defaults.dialect,
+    };
+    let config = FixtureConfig {
+        tape_size:  options.tape_size.unwrap_or(defaults.tape_size),
+        cell_width: options.cell_width,
+        eof,
+        fuel:       options.fuel.or(defaults.fuel),
+        dialect,
+    };
+    let fixtures = discover(options.file.as_deref().unwrap_or(".")).map_err(|err| Failure::Io(err.to_string()))?;
+    let report = TestReport::run(&fixtures, &config);
+    let text = match options.format {
+        Format::Text => report.text(),
+        Format::Json => report.json(),
+        Format::Junit => report.junit(),
+    };
+    write_output(options, text.as_bytes())?;
+    Ok(if report.success() { EXIT_OK } else { EXIT_FAILURE })
+}
+
+fn dispatch(options: &Options) -> Result<i32, Failure> {
+    match options.command {
+        Command::Run => run_program(options),
+        Command::Repl => repl(options),
+        Command::Debug => debug(options),
+        Command::Compile => compile(options),
+        Command::Fmt => fmt(options),
+        Command::Lint => lint(options),
+        Command::Minify => minify_program(options),
+        Command::Generate => generate_program(options),
+        Command::Profile => profile(options),
+        Command::Trace => trace(options),
+        Command::Test => test(options),
+        Command::Help => {
+            print!("{}", USAGE);
+            Ok(EXIT_OK)
+        },
+    }
+}
+
+fn main() {
+    let code = match args::parse(env::args().skip(1)) {
+        Ok(options) => dispatch(&options).unwrap_or_else(|failure| {
+            eprintln!("bf: {}", failure);
+            failure.code()
+        }),
+        Err(message) => {
+            eprintln!("bf: {}\n\n{}", message, USAGE);
+            EXIT_USAGE
+        },
+    };
+    process::exit(code);
+}
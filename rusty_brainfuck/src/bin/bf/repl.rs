@@ -0,0 +1,367 @@
+use std::collections::BTreeSet;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::mem;
+
+use rusty_brainfuck::{Brainfuck, CellWidth, Dialect, Inst, Observer, TraceFilter, TraceFormat, Tracer, ERROR_OUT_OF_FUEL};
+
+use crate::args::{Eof, Options};
+use crate::run::{dialect, machine, read_file};
+use crate::Failure;
+
+const HELP: &str = "\
+Lines of code run on the same tape. `#` in a line sets a breakpoint on the next instruction.
+  :mem [n]         hex dump of n cells (default 32) around the pointer
+  :reset           start over with an empty tape
+  :load FILE       run the code in FILE, as if it was typed
+  :save FILE       write the code since the last :reset to FILE, with `#` on its
+                   breakpoints, to be loaded again
+  :step [n]        run n (default 1) instructions of the pending code
+  :continue        run the pending code until it ends or reaches a breakpoint
+  :break [PC]      list breakpoints, or toggle one on instruction PC
+  :break clear     remove every breakpoint
+  :trace on|off    write every step to stderr
+  :history         list the lines of this session, `!N` runs line N again, `!!` the last one
+  :quit            leave (so does the end of input)
+For line editing, run the REPL under a wrapper like `rlwrap bf repl`.
+";
+
+// Why running the pending code stopped
+enum Stop {
+    Done,
+    Breakpoint,
+    Steps,
+}
+
+pub fn hex_dump(memory: &[u32], width: CellWidth, pointer: usize, around: usize) -> String {
+    let digits = width.bits() as usize / 4;
+    let start = pointer.saturating_sub(around) / 16 * 16;
+    let end = (pointer + around + 1).min(memory.len());
+    let mut text = String::new();
+    for row in (start..end).step_by(16) {
+        let cells = &memory[row..(row + 16).min(end)];
+        let hex: Vec<String> = cells.iter().map(|cell| format!("{:0digits$x}", cell, digits = digits)).collect();
+        let ascii: String = cells.iter()
+            .map(|&cell| if (0x20..0x7f).contains(&cell) { cell as u8 as char } else { '.' })
+            .collect();
+        text += &format!("{:05}  {:<hex_width$}  |{}|\n", row, hex.join(" "), ascii, hex_width = 16 * (digits + 1) - 1);
+        if (row..row + 16).contains(&pointer) {
+            text += &format!("{:indent$}{}\n", "", "^".repeat(digits), indent = 7 + (pointer - row) * (digits + 1));
+        }
+    }
+    text
+}
+
+pub struct Session {
+    bf:       Brainfuck<Box<dyn Observer>>,
+    options:  Options,
+    dialect:  Dialect,
+    source:   String,       // Every line run so far, which the spans point into
+    lines:    Vec<String>,  // The lines typed, for `!N`
+    reader:   Box<dyn BufRead>,
+    newline:  bool,         // Whether the output so far ends a line
+}
+
+impl Session {
+    pub fn new(options: &Options) -> Result<Self, Failure> {
+        let dialect = dialect(options, "")?;
+        let bf = machine(options, "", &dialect)?.with_observer(Box::new(()) as Box<dyn Observer>);
+        Ok(Session {
+            bf,
+            options: options.clone(),
+            dialect,
+            source:  String::new(),
+            lines:   Vec::new(),
+            reader:  Box::new(io::stdin().lock()),
+            newline: true,
+        })
+    }
+
+    // The observer is kept, so that tracing stays on
+    fn reset(&mut self) -> Result<(), Failure> {
+        let bf = machine(&self.options, "", &self.dialect)?;
+        let observer = mem::replace(self.bf.observer_mut(), Box::new(()));
+        self.bf = bf.with_observer(observer);
+        self.source.clear();
+        Ok(())
+    }
+
+    // The code run since the last reset, with `#` before the instructions which have a
+    // breakpoint now, wherever it was set
+    fn saved(&self) -> String {
+        let marked: BTreeSet<usize> = self.bf.breakpoints().iter()
+            .filter_map(|&pc| self.bf.span(pc).map(|span| span.start))
+            .collect();
+        let mut text = String::with_capacity(self.source.len());
+        for (at, c) in self.source.char_indices() {
+            if marked.contains(&at) {
+                text.push('#');
+            }
+            if c != '#' {
+                text.push(c);
+            }
+        }
+        text
+    }
+
+    fn read_line(&mut self, prompt: &str) -> Result<Option<String>, Failure> {
+        self.end_line();
+        print!("{}", prompt);
+        io::stdout().flush().map_err(|err| Failure::Io(format!("stdout: {}", err)))?;
+        let mut line = String::new();
+        match self.reader.read_line(&mut line) {
+            Ok(0) => Ok(None),
+            Ok(_) => Ok(Some(line)),
+            Err(err) => Err(Failure::Io(format!("stdin: {}", err))),
+        }
+    }
+
+    // Keep the prompt at the start of a line
+    fn end_line(&mut self) {
+        if !self.newline {
+            println!();
+            self.newline = true;
+        }
+    }
+
+    fn output(&mut self) -> Result<(), Failure> {
+        let bytes: Vec<u8> = self.bf.pop_result().chars().map(|c| c as u8).collect();
+        if let Some(&last) = bytes.last() {
+            self.newline = last == b'\n';
+        }
+        io::stdout().write_all(&bytes).map_err(|err| Failure::Io(format!("stdout: {}", err)))
+    }
+
+    // Code typed at the prompt or loaded from a file. `#` sets a breakpoint on the next instruction.
+    fn enter(&mut self, code: &str) -> Result<(), String> {
+        let code = format!("{}\n", code.trim_end_matches('\n'));
+        let start = self.source.len();
+        let first = self.bf.insts().len();
+        self.bf.extend(&code).map_err(String::from)?;
+        self.source += &code;
+        for (at, _) in code.match_indices('#') {
+            let next = (first..self.bf.insts().len())
+                .find(|&pc| self.bf.span(pc).is_some_and(|span| span.start > start + at));
+            if let Some(pc) = next {
+                self.bf.add_breakpoint(pc);
+            }
+        }
+        self.resume(None)
+    }
+
+    // Run the pending code, at most `limit` steps
+    fn resume(&mut self, limit: Option<u64>) -> Result<(), String> {
+        let stop = self.run(limit).map_err(|failure| failure.to_string());
+        self.output().map_err(|failure| failure.to_string())?;
+        match stop {
+            Ok(Stop::Done) => Ok(()),
+            Ok(Stop::Breakpoint) => {
+                self.end_line();
+                println!("breakpoint: {}", self.status());
+                Ok(())
+            },
+            Ok(Stop::Steps) => {
+                self.end_line();
+                println!("{}", self.status());
+                Ok(())
+            },
+            // The rest of the failed code is skipped, so that the session can go on
+            Err(message) => {
+                let status = self.status();
+                self.bf.halt();
+                Err(format!("{} ({})", message, status))
+            },
+        }
+    }
+
+    fn run(&mut self, limit: Option<u64>) -> Result<Stop, Failure> {
+        let mut steps = 0;
+        loop {
+            if self.bf.is_input_mode() {
+                self.input()?;
+                continue;
+            }
+            if self.bf.reach_eop() {
+                return Ok(Stop::Done);
+            }
+            if steps > 0 && self.bf.at_breakpoint() {
+                return Ok(Stop::Breakpoint);
+            }
+            if limit == Some(steps) {
+                return Ok(Stop::Steps);
+            }
+            let writes = self.bf.insts()[self.bf.pc()] == Inst::Wrt;
+            self.bf.step().map_err(|err| match err {
+                ERROR_OUT_OF_FUEL => Failure::Fuel,
+                err => Failure::Runtime(String::from(err)),
+            })?;
+            if writes {
+                self.output()?;
+            }
+            steps += 1;
+        }
+    }
+
+    // Input for `,` is read a line at a time, newline included
+    fn input(&mut self) -> Result<(), Failure> {
+        if self.bf.queue_remain() > 0 {
+            return self.bf.set_input(String::new()).map_err(|err| Failure::Runtime(String::from(err)));
+        }
+        self.output()?;
+        let answered = match self.read_line("input> ")? {
+            Some(line) => self.bf.set_input(line),
+            None => match self.options.eof {
+                Eof::Store(policy) => self.bf.set_eof(policy),
+                Eof::Fail => return Err(Failure::Eof),
+            },
+        };
+        answered.map_err(|err| Failure::Runtime(String::from(err)))
+    }
+
+    fn status(&self) -> String {
+        let pc = self.bf.pc();
+        let pointer = self.bf.pointer();
+        let cell = self.bf.memory()[pointer];
+        match (self.bf.insts().get(pc), self.bf.span(pc)) {
+            (Some(inst), Some(span)) => {
+                let (line, col) = span.line_col(&self.source);
+                format!("pc {} {:?} at {}:{}, pointer {}, cell {}", pc, inst, line, col, pointer, cell)
+            },
+            _ => format!("end of code, pointer {}, cell {}", pointer, cell),
+        }
+    }
+
+    fn toggle_breakpoint(&mut self, pc: usize) {
+        if self.bf.breakpoints().contains(&pc) {
+            self.bf.remove_breakpoint(pc);
+        } else {
+            self.bf.add_breakpoint(pc);
+        }
+    }
+
+    fn breakpoints(&self) -> String {
+        let list: Vec<String> = self.bf.breakpoints().iter()
+            .map(|&pc| match (self.bf.insts().get(pc), self.bf.span(pc)) {
+                (Some(inst), Some(span)) => {
+                    let (line, col) = span.line_col(&self.source);
+                    format!("pc {} {:?} at {}:{}", pc, inst, line, col)
+                },
+                _ => format!("pc {} (no code yet)", pc),
+            })
+            .collect();
+        if list.is_empty() {
+            String::from("no breakpoints")
+        } else {
+            list.join("\n")
+        }
+    }
+
+    fn meta(&mut self, command: &str, arg: &str) -> Result<bool, String> {
+        let count = |default: u64| if arg.is_empty() {
+            Ok(default)
+        } else {
+            arg.parse::<u64>().map_err(|_| format!("`{}` is not a number", arg))
+        };
+        match command {
+            ":help" | ":h" => print!("{}", HELP),
+            ":quit" | ":q" => return Ok(false),
+            ":mem" => {
+                let around = count(32)? as usize;
+                print!("{}", hex_dump(self.bf.memory(), self.bf.cell_width(), self.bf.pointer(), around));
+            },
+            ":reset" => self.reset().map_err(|failure| failure.to_string())?,
+            ":load" => {
+                let bytes = read_file(arg).map_err(|failure| failure.to_string())?;
+                let code = String::from_utf8(bytes).map_err(|_| format!("{}: not UTF-8 text", arg))?;
+                self.enter(&code)?;
+            },
+            ":save" => {
+                fs::write(arg, self.saved()).map_err(|err| format!("{}: {}", arg, err))?;
+            },
+            ":step" | ":s" => self.resume(Some(count(1)?))?,
+            ":continue" | ":c" => self.resume(None)?,
+            ":break" | ":b" => match arg {
+                "" => println!("{}", self.breakpoints()),
+                "clear" => self.bf.clear_breakpoints(),
+                pc => self.toggle_breakpoint(pc.parse().map_err(|_| format!("`{}` is not an instruction number", pc))?),
+            },
+            ":trace" => match arg {
+                "on" => {
+                    let tracer = Tracer::new(io::stderr(), TraceFormat::Text, TraceFilter::default());
+                    *self.bf.observer_mut() = Box::new(tracer);
+                },
+                "off" => *self.bf.observer_mut() = Box::new(()),
+                _ => return Err(String::from(":trace takes on or off")),
+            },
+            ":history" => {
+                for (n, line) in self.lines.iter().enumerate() {
+                    println!("{:>4}  {}", n + 1, line);
+                }
+            },
+            _ => return Err(format!("unknown command `{}`, try :help", command)),
+        }
+        Ok(true)
+    }
+
+    // Run one line of the session, and tell whether to go on
+    pub fn line(&mut self, line: &str) -> Result<bool, String> {
+        let line = line.trim_end_matches(&['\n', '\r'][..]);
+        let line = match line {
+            "!!" => self.lines.last().cloned().ok_or("no line to repeat")?,
+            _ if line.starts_with('!') => {
+                let n: usize = line[1..].parse().map_err(|_| format!("`{}` is not a line number", &line[1..]))?;
+                self.lines.get(n.wrapping_sub(1)).cloned().ok_or(format!("no line {}", n))?
+            },
+            _ => String::from(line),
+        };
+        if line.trim().is_empty() {
+            return Ok(true);
+        }
+        self.lines.push(line.clone());
+        if line.starts_with(':') {
+            let (command, arg) = match line.find(' ') {
+                Some(at) => (&line[..at], line[at + 1..].trim()),
+                None => (line.as_str(), ""),
+            };
+            return self.meta(command, arg);
+        }
+        self.enter(&line).map(|_| true)
+    }
+
+    pub fn run_loop(&mut self) -> Result<(), Failure> {
+        println!("Type Brainfuck code to run it, :help for commands.");
+        while let Some(line) = self.read_line("bf> ")? {
+            match self.line(&line) {
+                Ok(true) => (),
+                Ok(false) => break,
+                Err(message) => {
+                    self.end_line();
+                    println!("error: {}", message);
+                },
+            }
+        }
+        self.end_line();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dump_marks_the_pointer() {
+        let mut memory = vec![0; 40];
+        memory[17] = 0x41;
+        let dump = hex_dump(&memory, CellWidth::Bits8, 17, 1);
+        let lines: Vec<&str> = dump.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("00016  00 41 00"));
+        assert!(lines[0].ends_with("|.A.|"));
+        assert_eq!(hex_dump(&memory, CellWidth::Bits8, 17, 4).lines().count(), 3);
+        assert_eq!(lines[1], format!("{}^^", " ".repeat(10)));
+        let wide = hex_dump(&memory, CellWidth::Bits16, 17, 1);
+        assert!(wide.starts_with("00016  0000 0041 0000"));
+        assert!(wide.ends_with(&format!("\n{}^^^^\n", " ".repeat(12))));
+    }
+}
@@ -0,0 +1,160 @@
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, IsTerminal, Read, Write};
+
+use rusty_brainfuck::{
+    decode_bytecode, decode_bytecode_with_tape_size, is_bytecode, Brainfuck, Dialect, Inst, Observer, ERROR_OUT_OF_FUEL,
+};
+
+use crate::args::{Eof, Options};
+use crate::Failure;
+
+pub fn read_file(path: &str) -> Result<Vec<u8>, Failure> {
+    if path == "-" {
+        let mut bytes = Vec::new();
+        io::stdin().read_to_end(&mut bytes).map_err(|err| Failure::Io(format!("stdin: {}", err)))?;
+        return Ok(bytes);
+    }
+    fs::read(path).map_err(|err| Failure::Io(format!("{}: {}", path, err)))
+}
+
+pub fn write_output(options: &Options, bytes: &[u8]) -> Result<(), Failure> {
+    match options.output.as_deref() {
+        Some(path) => fs::write(path, bytes).map_err(|err| Failure::Io(format!("{}: {}", path, err))),
+        None => io::stdout().write_all(bytes).map_err(|err| Failure::Io(format!("stdout: {}", err))),
+    }
+}
+
+// The source of the program file, with bytecode turned back into Brainfuck
+pub fn load(options: &Options) -> Result<(String, Dialect), Failure> {
+    let path = options.file.as_deref().unwrap_or("-");
+    let bytes = read_file(path)?;
+    if is_bytecode(&bytes) {
+        let source = match options.tape_size {
+            Some(size) => decode_bytecode_with_tape_size(&bytes, size),
+            None => decode_bytecode(&bytes),
+        };
+        let source = source.map_err(|err| Failure::Parse(format!("{}: {}", path, err)))?;
+        return Ok((source, Dialect::brainfuck()));
+    }
+    let source = String::from_utf8(bytes).map_err(|_| Failure::Parse(format!("{}: not UTF-8 text", path)))?;
+    let dialect = dialect(options, &source)?;
+    Ok((source, dialect))
+}
+
+pub fn dialect(options: &Options, source: &str) -> Result<Dialect, Failure> {
+    let name = match options.dialect.as_deref() {
+        Some(name) => name,
+        None => return Ok(Dialect::detect(source)),
+    };
+    if let Some(dialect) = Dialect::builtin(name) {
+        return Ok(dialect);
+    }
+    let table = String::from_utf8(read_file(name)?)
+        .map_err(|_| Failure::Parse(format!("{}: not UTF-8 text", name)))?;
+    Dialect::parse(&table).map_err(|err| Failure::Parse(format!("{}: {}", name, err)))
+}
+
+pub fn machine(options: &Options, source: &str, dialect: &Dialect) -> Result<Brainfuck, Failure> {
+    let path = options.file.as_deref().unwrap_or("-");
+    let mut bf = Brainfuck::with_dialect(String::from(source), dialect)
+        .map_err(|err| Failure::Parse(format!("{}: {}", path, err)))?;
+    if let Some(size) = options.tape_size {
+        bf.set_tape_size(size).map_err(|err| Failure::Usage(String::from(err)))?;
+    }
+    bf.set_cell_width(options.cell_width);
+    bf.set_fuel(options.fuel);
+    Ok(bf)
+}
+
+// The input of the program, read a byte at a time when `,` asks for it
+pub struct Input {
+    path:   Option<String>,  // Opened at the first `,`, so programs without input never wait for it
+    reader: Option<Box<dyn Read>>,
+}
+
+impl Input {
+    pub fn new(options: &Options) -> Self {
+        Input {
+            path:   Some(options.input.clone().unwrap_or_else(|| String::from("-"))),
+            reader: None,
+        }
+    }
+
+    // The next byte, or None at the end of input
+    fn next(&mut self) -> Result<Option<u8>, Failure> {
+        if let Some(path) = self.path.take() {
+            self.reader = Some(match path.as_str() {
+                "-" => Box::new(io::stdin()),
+                _ => Box::new(BufReader::new(File::open(&path).map_err(|err| Failure::Io(format!("{}: {}", path, err)))?)),
+            });
+        }
+        let reader = match self.reader.as_mut() {
+            Some(reader) => reader,
+            None => return Ok(None),
+        };
+        let mut byte = [0];
+        loop {
+            return match reader.read(&mut byte) {
+                Ok(0) => Ok(None),
+                Ok(_) => Ok(Some(byte[0])),
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => Err(Failure::Io(format!("input: {}", err))),
+            };
+        }
+    }
+}
+
+// Standard output, buffered unless somebody is watching
+pub fn stdout() -> Box<dyn Write> {
+    let stdout = io::stdout();
+    if stdout.is_terminal() {
+        Box::new(stdout)
+    } else {
+        Box::new(BufWriter::new(stdout))
+    }
+}
+
+fn write(bf: &mut Brainfuck<impl Observer>, out: &mut dyn Write) -> Result<(), Failure> {
+    let bytes: Vec<u8> = bf.pop_result().chars().map(|c| c as u8).collect();
+    out.write_all(&bytes).map_err(|err| Failure::Io(format!("output: {}", err)))
+}
+
+fn flush(out: &mut dyn Write) -> Result<(), Failure> {
+    out.flush().map_err(|err| Failure::Io(format!("output: {}", err)))
+}
+
+// Run until every thread reached the end. Output is written to `out` as it is produced, and
+// flushed whenever the program waits for input.
+pub fn execute<O: Observer>(bf: &mut Brainfuck<O>, input: &mut Input, eof: Eof, source: &str, out: &mut dyn Write)
+                            -> Result<(), Failure> {
+    loop {
+        if bf.is_input_mode() {
+            flush(out)?;
+            let answered = match input.next()? {
+                Some(byte) => bf.set_input_byte(byte),
+                None => match eof {
+                    Eof::Store(policy) => bf.set_eof(policy),
+                    Eof::Fail => return Err(Failure::Eof),
+                },
+            };
+            answered.map_err(|err| Failure::Runtime(String::from(err)))?;
+            continue;
+        }
+        if bf.reach_eop() {
+            break;
+        }
+        let writes = bf.insts()[bf.pc()] == Inst::Wrt;
+        if let Err(err) = bf.step() {
+            flush(out)?;
+            if err == ERROR_OUT_OF_FUEL {
+                return Err(Failure::Fuel);
+            }
+            let (line, col) = bf.span(bf.pc()).map_or((0, 0), |span| span.line_col(source));
+            return Err(Failure::Runtime(format!("{}:{}: {}", line, col, err)));
+        }
+        if writes {
+            write(bf, out)?;
+        }
+    }
+    flush(out)
+}
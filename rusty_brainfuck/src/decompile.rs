@@ -0,0 +1,494 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::{CodeGen, Inst, Span};
+use crate::Inst::*;
+
+/// A statement of pseudocode, and the source it was recognized in.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PseudoLine {
+    pub span:  Span,
+    pub depth: usize,  // Nesting level of loops and conditionals
+    pub code:  String,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Pseudocode {
+    pub lines: Vec<PseudoLine>,
+}
+
+impl Pseudocode {
+    // One statement per line, prefixed with its `line:col` in `source`
+    pub fn render(&self, source: &str) -> String {
+        let mut text = String::new();
+        for line in &self.lines {
+            let (l, c) = line.span.line_col(source);
+            let position = format!("{}:{}", l, c);
+            text += &format!("{:<9} {}{}\n", position, "    ".repeat(line.depth), line.code);
+        }
+        text
+    }
+}
+
+/// Recognize the common idioms of a program and write them as pseudocode.
+///
+/// ```
+/// let pseudocode = rusty_brainfuck::decompile("[->>>++<<<]").unwrap();
+/// assert_eq!(pseudocode.lines[0].code, "cell[3] += cell[0]*2; cell[0] = 0");
+/// ```
+pub fn decompile(program: &str) -> Result<Pseudocode, &'static str> {
+    let mut codegen = CodeGen::new(program);
+    let insts = codegen.generate_insts()?;
+    let mut decompiler = Decompiler {
+        insts:  &insts,
+        spans:  &codegen.spans,
+        lines:  Vec::new(),
+        base:   Some(0),
+        offset: 0,
+        values: BTreeMap::new(),
+        fresh:  true,
+        run:    Vec::new(),
+    };
+    decompiler.block(0, insts.len(), 0);
+    Ok(Pseudocode { lines: decompiler.lines })
+}
+
+fn quote(bytes: &[u8]) -> String {
+    let mut text = String::from("\"");
+    for &b in bytes {
+        match b {
+            b'\n' => text.push_str("\\n"),
+            b'\t' => text.push_str("\\t"),
+            b'"' => text.push_str("\\\""),
+            b'\\' => text.push_str("\\\\"),
+            0x20..=0x7e => text.push(b as char),
+            _ => text += &format!("\\x{:02x}", b),
+        }
+    }
+    text.push('"');
+    text
+}
+
+// `target += source*factor`, reading the factor as a signed byte
+fn scaled(target: &str, source: &str, factor: u8) -> String {
+    match factor as i8 {
+        1 => format!("{} += {}", target, source),
+        -1 => format!("{} -= {}", target, source),
+        f if f < 0 => format!("{} -= {}*{}", target, source, -(f as i16)),
+        f => format!("{} += {}*{}", target, source, f),
+    }
+}
+
+fn shift(offset: isize) -> String {
+    if offset < 0 {
+        format!("p -= {}", -offset)
+    } else {
+        format!("p += {}", offset)
+    }
+}
+
+// Straight-line code is kept back, to be shown as a single `print` when it writes known bytes
+enum Item {
+    Stmt(Span, String, isize),  // Changes a cell
+    Put(Span, isize, u8),
+}
+
+// What a piece of code does when it ends where it started, or has no loops that move
+struct Effects {
+    moves:   isize,
+    written: BTreeSet<isize>,
+}
+
+struct Decompiler<'a> {
+    insts:  &'a [Inst],
+    spans:  &'a [Span],
+    lines:  Vec<PseudoLine>,
+    base:   Option<usize>,               // The cell `p` stands for, while it is known
+    offset: isize,                       // The pointer relative to `p`
+    values: BTreeMap<isize, Option<u8>>, // Values of cells relative to `p`, None when unknown
+    fresh:  bool,                        // Whether cells missing from `values` are still 0
+    run:    Vec<Item>,
+}
+
+impl<'a> Decompiler<'a> {
+    fn cell(&self, k: isize) -> String {
+        match (self.base, k) {
+            (Some(base), _) => format!("cell[{}]", base as isize + k),
+            (None, 0) => String::from("cell[p]"),
+            (None, _) => format!("cell[p{:+}]", k),
+        }
+    }
+
+    fn value(&self, k: isize) -> Option<u8> {
+        match self.values.get(&k) {
+            Some(&value) => value,
+            None if self.fresh => Some(0),
+            None => None,
+        }
+    }
+
+    fn forget(&mut self) {
+        self.values.clear();
+        self.fresh = false;
+    }
+
+    fn line(&mut self, span: Span, depth: usize, code: String) {
+        self.lines.push(PseudoLine { span, depth, code });
+    }
+
+    fn block(&mut self, start: usize, end: usize, depth: usize) {
+        let mut pc = start;
+        while pc < end {
+            let span = self.spans[pc];
+            let k = self.offset;
+            match self.insts[pc] {
+                Add(n) => self.adjust(span, n),
+                Sub(n) => self.adjust(span, n.wrapping_neg()),
+                Stz => {
+                    self.values.insert(k, Some(0));
+                    self.run.push(Item::Stmt(span, format!("{} = 0", self.cell(k)), k));
+                },
+                Shr(n) => self.offset += n as isize,
+                Shl(n) => self.offset -= n as isize,
+                Wrt => match self.value(k) {
+                    Some(byte) => self.run.push(Item::Put(span, k, byte)),
+                    None => {
+                        self.flush(depth);
+                        self.line(span, depth, format!("putchar({})", self.cell(k)));
+                    },
+                },
+                Red => {
+                    self.flush(depth);
+                    self.line(span, depth, format!("{} = getchar()", self.cell(k)));
+                    self.values.insert(k, None);
+                },
+                Jpf(close) => {
+                    self.flush(depth);
+                    pc = self.analyze(pc, close, depth);
+                    continue;
+                },
+                inst => {
+                    self.flush(depth);
+                    self.line(span, depth, format!("{:?}", inst));
+                    self.forget();
+                },
+            }
+            pc += 1;
+        }
+        self.flush(depth);
+    }
+
+    fn adjust(&mut self, span: Span, n: u8) {
+        let k = self.offset;
+        let value = self.value(k).map(|value| value.wrapping_add(n));
+        self.values.insert(k, value);
+        let code = match n as i8 {
+            d if d < 0 => format!("{} -= {}", self.cell(k), -(d as i16)),
+            d => format!("{} += {}", self.cell(k), d),
+        };
+        self.run.push(Item::Stmt(span, code, k));
+    }
+
+    fn flush(&mut self, depth: usize) {
+        let run = std::mem::take(&mut self.run);
+        let text: Vec<u8> = run.iter()
+            .filter_map(|item| match item {
+                Item::Put(_, _, byte) => Some(*byte),
+                _ => None,
+            })
+            .collect();
+        let changed: BTreeSet<isize> = run.iter()
+            .filter_map(|item| match item {
+                Item::Stmt(_, _, k) => Some(*k),
+                _ => None,
+            })
+            .collect();
+
+        if !text.is_empty() && changed.iter().all(|&k| self.value(k).is_some()) {
+            let span = match run[0] {
+                Item::Stmt(span, _, _) | Item::Put(span, _, _) => span,
+            };
+            self.line(span, depth, format!("print {}", quote(&text)));
+            let state: Vec<String> = changed.iter()
+                .map(|&k| format!("{} = {}", self.cell(k), self.value(k).unwrap_or(0)))
+                .collect();
+            if !state.is_empty() {
+                self.line(span, depth, state.join("; "));
+            }
+            return;
+        }
+        for item in run {
+            match item {
+                Item::Stmt(span, code, _) => self.line(span, depth, code),
+                Item::Put(span, k, byte) => {
+                    let code = format!("putchar({})  // {}", self.cell(k), quote(&[byte]));
+                    self.line(span, depth, code);
+                },
+            }
+        }
+    }
+
+    // Net pointer movement and written cells of `insts[start..end]`, if nested loops are balanced
+    fn effects(&self, start: usize, end: usize) -> Option<Effects> {
+        let mut effects = Effects { moves: 0, written: BTreeSet::new() };
+        let mut pc = start;
+        while pc < end {
+            match self.insts[pc] {
+                Add(_) | Sub(_) | Stz | Red => {
+                    effects.written.insert(effects.moves);
+                },
+                Shr(n) => effects.moves += n as isize,
+                Shl(n) => effects.moves -= n as isize,
+                Jpf(close) => {
+                    let inner = self.effects(pc + 1, close)?;
+                    if inner.moves != 0 {
+                        return None;
+                    }
+                    let moves = effects.moves;
+                    effects.written.extend(inner.written.iter().map(|k| k + moves));
+                    pc = close;
+                },
+                Jpb(_) | Wrt => (),
+                _ => return None,
+            }
+            pc += 1;
+        }
+        Some(effects)
+    }
+
+    // The changes of a loop body which only adds and returns to the loop cell
+    fn linear(&self, open: usize, close: usize) -> Option<BTreeMap<isize, u8>> {
+        let mut offset = 0;
+        let mut deltas = BTreeMap::new();
+        for inst in &self.insts[open + 1..close] {
+            match *inst {
+                Add(n) => {
+                    let delta = deltas.entry(offset).or_insert(0u8);
+                    *delta = delta.wrapping_add(n);
+                },
+                Sub(n) => {
+                    let delta = deltas.entry(offset).or_insert(0u8);
+                    *delta = delta.wrapping_sub(n);
+                },
+                Shr(n) => offset += n as isize,
+                Shl(n) => offset -= n as isize,
+                _ => return None,
+            }
+        }
+        deltas.retain(|_, delta| *delta != 0);
+        if offset == 0 {
+            Some(deltas)
+        } else {
+            None
+        }
+    }
+
+    // Show the loop at `open` and return where to go on
+    fn analyze(&mut self, open: usize, close: usize, depth: usize) -> usize {
+        let span = Span::new(self.spans[open].start, self.spans[close].end);
+        let x = self.offset;
+
+        // Clears, moves and multiplications run the loop cell down (or up) to 0 one at a time
+        if let Some(deltas) = self.linear(open, close) {
+            if let Some(&step) = deltas.get(&0).filter(|&&step| step == 1 || step == 255) {
+                let factors: Vec<(isize, u8)> = deltas.iter()
+                    .filter(|(&k, _)| k != 0)
+                    .map(|(&k, &d)| (x + k, if step == 255 { d } else { d.wrapping_neg() }))
+                    .collect();
+                if let Some(next) = self.copy(open, close, &factors, depth) {
+                    return next;
+                }
+                let source = self.cell(x);
+                let mut parts: Vec<String> = factors.iter()
+                    .map(|&(t, f)| scaled(&self.cell(t), &source, f))
+                    .collect();
+                parts.push(format!("{} = 0", source));
+                self.line(span, depth, parts.join("; "));
+                let value = self.value(x);
+                for &(t, f) in &factors {
+                    let target = match (value, self.value(t)) {
+                        (Some(v), Some(t)) => Some(t.wrapping_add(f.wrapping_mul(v))),
+                        _ => None,
+                    };
+                    self.values.insert(t, target);
+                }
+                self.values.insert(x, Some(0));
+                return close + 1;
+            }
+        }
+
+        // `[>]` and `[<]` look for a 0 cell
+        if close == open + 2 {
+            if let Shr(n) | Shl(n) = self.insts[open + 1] {
+                let direction = if let Shr(_) = self.insts[open + 1] { "right" } else { "left" };
+                let step = if n > 1 { format!(" (step {})", n) } else { String::new() };
+                let code = format!("p = scan {} from {} to a 0 cell{}", direction, self.cell(x), step);
+                self.line(span, depth, code);
+                self.base = None;
+                self.offset = 0;
+                self.forget();
+                self.values.insert(0, Some(0));
+                return close + 1;
+            }
+        }
+
+        let effects = self.effects(open + 1, close).filter(|effects| effects.moves == 0);
+        match effects {
+            // A body which clears its own cell at the end runs at most once
+            Some(effects) if close > open + 1 && self.insts[close - 1] == Stz => {
+                let certain = self.value(x).unwrap_or(0) != 0;
+                let before = (self.values.clone(), self.fresh);
+                self.line(self.spans[open], depth, format!("if {} {{", self.cell(x)));
+                self.block(open + 1, close, depth + 1);
+                self.line(self.spans[close], depth, String::from("}"));
+                if !certain {
+                    self.values = before.0;
+                    self.fresh = before.1;
+                    for k in effects.written {
+                        self.values.insert(x + k, None);
+                    }
+                }
+                self.values.insert(x, Some(0));
+            },
+            Some(effects) => {
+                for k in effects.written {
+                    self.values.insert(x + k, None);
+                }
+                self.line(self.spans[open], depth, format!("while {} {{", self.cell(x)));
+                self.block(open + 1, close, depth + 1);
+                self.line(self.spans[close], depth, String::from("}"));
+                self.values.insert(x, Some(0));
+            },
+            // The body moves `p`, so it is written relative to where each iteration starts
+            None => {
+                match self.base {
+                    Some(base) => self.line(self.spans[open], depth, format!("p = {}", base as isize + x)),
+                    None if x != 0 => self.line(self.spans[open], depth, shift(x)),
+                    None => (),
+                }
+                self.base = None;
+                self.offset = 0;
+                self.forget();
+                self.line(self.spans[open], depth, String::from("while cell[p] {"));
+                self.block(open + 1, close, depth + 1);
+                if self.offset != 0 {
+                    self.line(self.spans[close], depth + 1, shift(self.offset));
+                }
+                self.line(self.spans[close], depth, String::from("}"));
+                self.offset = 0;
+                self.forget();
+                self.values.insert(0, Some(0));
+            },
+        }
+        close + 1
+    }
+
+    // `[->+>+<<]>>[-<<+>>]` copies through a temporary cell, which the second loop moves back
+    fn copy(&mut self, open: usize, close: usize, factors: &[(isize, u8)], depth: usize) -> Option<usize> {
+        let x = self.offset;
+        let mut t = x;
+        let mut pc = close + 1;
+        while let Some(inst) = self.insts.get(pc) {
+            match *inst {
+                Shr(n) => t += n as isize,
+                Shl(n) => t -= n as isize,
+                _ => break,
+            }
+            pc += 1;
+        }
+        let end = match self.insts.get(pc) {
+            Some(&Jpf(end)) => end,
+            _ => return None,
+        };
+        let back = self.linear(pc, end)?;
+        let mut expected = BTreeMap::new();
+        expected.insert(0, 255);
+        expected.insert(x - t, 1);
+        if back != expected || !factors.contains(&(t, 1)) || self.value(t) != Some(0) || factors.len() < 2 {
+            return None;
+        }
+
+        let source = self.cell(x);
+        let parts: Vec<String> = factors.iter()
+            .filter(|&&(k, _)| k != t)
+            .map(|&(k, f)| scaled(&self.cell(k), &source, f))
+            .collect();
+        let code = format!("{}  // copy using {}", parts.join("; "), self.cell(t));
+        self.line(Span::new(self.spans[open].start, self.spans[end].end), depth, code);
+        let value = self.value(x);
+        for &(k, f) in factors.iter().filter(|&&(k, _)| k != t) {
+            let target = match (value, self.value(k)) {
+                (Some(v), Some(k)) => Some(k.wrapping_add(f.wrapping_mul(v))),
+                _ => None,
+            };
+            self.values.insert(k, target);
+        }
+        self.offset = t;
+        Some(end + 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn codes(program: &str) -> Vec<String> {
+        decompile(program).unwrap().lines.into_iter().map(|line| line.code).collect()
+    }
+
+    #[test]
+    fn moves_and_copies() {
+        assert_eq!(codes("++[->+>++<<]"), vec![
+            "cell[0] += 2",
+            "cell[1] += cell[0]; cell[2] += cell[0]*2; cell[0] = 0",
+        ]);
+        assert_eq!(codes("[+>-<]"), vec!["cell[1] += cell[0]; cell[0] = 0"]);
+        assert_eq!(codes(",[->+>+<<]>>[-<<+>>]"), vec![
+            "cell[0] = getchar()",
+            "cell[1] += cell[0]  // copy using cell[2]",
+        ]);
+    }
+
+    #[test]
+    fn prints_and_conditionals() {
+        assert_eq!(codes("++++++++[>+++++++++<-]>.+++++++++++++++++++++++++++++++++.[-]"), vec![
+            "cell[0] += 8",
+            "cell[1] += cell[0]*9; cell[0] = 0",
+            "print \"Hi\"",
+            "cell[1] = 0",
+        ]);
+        assert_eq!(codes(",[>+<[-]]>."), vec![
+            "cell[0] = getchar()",
+            "if cell[0] {",
+            "cell[1] += 1",
+            "cell[0] = 0",
+            "}",
+            "putchar(cell[1])",
+        ]);
+    }
+
+    #[test]
+    fn moving_loops() {
+        assert_eq!(codes(">>+[<].,[>,]<[.<]"), vec![
+            "cell[2] += 1",
+            "p = scan left from cell[2] to a 0 cell",
+            "print \"\\x00\"",
+            "cell[p] = getchar()",
+            "while cell[p] {",
+            "cell[p+1] = getchar()",
+            "p += 1",
+            "}",
+            "p -= 1",
+            "while cell[p] {",
+            "putchar(cell[p])",
+            "p -= 1",
+            "}",
+        ]);
+    }
+
+    #[test]
+    fn positions() {
+        let source = "+\n  [->+<]\n.";
+        let text = decompile(source).unwrap().render(source);
+        assert_eq!(text.lines().nth(1), Some("2:3       cell[1] += cell[0]; cell[0] = 0"));
+    }
+}
@@ -0,0 +1,390 @@
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use crate::Span;
+
+// The order in which a dialect lists its tokens
+const COMMANDS: [char; 8] = ['+', '-', '>', '<', '[', ']', '.', ','];
+
+// A command of a program and where it was written
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Token {
+    pub(crate) command: char,
+    pub(crate) span:    Span,
+}
+
+/// A command which has no token in the dialect a program is translated into.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TranslateError {
+    pub command: char,
+    pub dialect: String,
+    pub span:    Span,  // Where the command is written in the source
+}
+
+impl fmt::Display for TranslateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "The command `{}` has no token in the dialect {}.", self.command, self.dialect)
+    }
+}
+
+/// A set of commands on top of the eight of Brainfuck.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Extension {
+    Pbrain,     // `(` and `)` define the procedure named by the current cell, `:` calls it
+    Brainfork,  // `Y` forks a thread, which shares the tape
+    Extended,   // Extended Brainfuck Type I: `@` ends, `$` `!` use a storage, `}` `{` `~` `^` `&` `|` are bit operations
+}
+
+impl Extension {
+    pub fn all() -> Vec<Extension> {
+        vec![Extension::Pbrain, Extension::Brainfork, Extension::Extended]
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Extension::Pbrain => "pbrain",
+            Extension::Brainfork => "brainfork",
+            Extension::Extended => "extended",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Extension> {
+        Extension::all().into_iter().find(|ext| ext.name().eq_ignore_ascii_case(name))
+    }
+
+    pub fn commands(&self) -> &'static [char] {
+        match self {
+            Extension::Pbrain => &['(', ')', ':'],
+            Extension::Brainfork => &['Y'],
+            Extension::Extended => &['@', '$', '!', '}', '{', '~', '^', '&', '|'],
+        }
+    }
+}
+
+/// A language which maps tokens one-to-one onto the commands of Brainfuck and
+/// its enabled extensions.
+///
+/// Everything between tokens is a comment. A token may consist of several
+/// words, which match any run of whitespace between them in a source.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Dialect {
+    name:       String,
+    tokens:     Vec<(char, String)>,
+    extensions: Vec<Extension>,
+}
+
+impl Dialect {
+    // `tokens` are given in the order `+ - > < [ ] . ,`
+    pub fn new(name: &str, tokens: [&str; 8]) -> Result<Self, &'static str> {
+        let mut dialect = Dialect {
+            name:       name.to_string(),
+            tokens:     Vec::new(),
+            extensions: Vec::new(),
+        };
+        for (&command, token) in COMMANDS.iter().zip(tokens.iter()) {
+            dialect.set_token(command, token)?;
+        }
+        Ok(dialect)
+    }
+
+    // Enable `extension`. Its commands are written as themselves unless the token is changed.
+    pub fn with_extension(mut self, extension: Extension) -> Result<Self, &'static str> {
+        if !self.extensions.contains(&extension) {
+            self.extensions.push(extension);
+            for &command in extension.commands() {
+                self.set_token(command, &command.to_string())?;
+            }
+        }
+        Ok(self)
+    }
+
+    // Change the token of a command of Brainfuck or of an enabled extension
+    pub fn set_token(&mut self, command: char, token: &str) -> Result<(), &'static str> {
+        let known = COMMANDS.contains(&command) || self.extensions.iter().any(|ext| ext.commands().contains(&command));
+        if !known {
+            return Err("The command is not enabled in the dialect.");
+        }
+        let words: Vec<&str> = token.split_whitespace().collect();
+        if words.is_empty() {
+            return Err("A token of the dialect is empty.");
+        }
+        let token = words.join(" ");
+        if self.tokens.iter().any(|(c, t)| *c != command && *t == token) {
+            return Err("Tokens of the dialect are not distinct.");
+        }
+        match self.tokens.iter_mut().find(|(c, _)| *c == command) {
+            Some(entry) => entry.1 = token,
+            None => self.tokens.push((command, token)),
+        }
+        Ok(())
+    }
+
+    pub fn brainfuck() -> Self {
+        Dialect::new("brainfuck", ["+", "-", ">", "<", "[", "]", ".", ","]).unwrap()
+    }
+
+    pub fn ook() -> Self {
+        Dialect::new("ook", [
+            "Ook. Ook.", "Ook! Ook!", "Ook. Ook?", "Ook? Ook.",
+            "Ook! Ook?", "Ook? Ook!", "Ook! Ook.", "Ook. Ook!",
+        ]).unwrap()
+    }
+
+    pub fn blub() -> Self {
+        Dialect::new("blub", [
+            "Blub. Blub.", "Blub! Blub!", "Blub. Blub?", "Blub? Blub.",
+            "Blub! Blub?", "Blub? Blub!", "Blub! Blub.", "Blub. Blub!",
+        ]).unwrap()
+    }
+
+    pub fn builtins() -> Vec<Dialect> {
+        vec![Dialect::brainfuck(), Dialect::ook(), Dialect::blub()]
+    }
+
+    // Brainfuck with procedures. Not a builtin, as `(` `)` and `:` are common in comments.
+    pub fn pbrain() -> Self {
+        let mut dialect = Dialect::brainfuck().with_extension(Extension::Pbrain).unwrap();
+        dialect.name = String::from("pbrain");
+        dialect
+    }
+
+    // Brainfuck with threads. Not a builtin either, as `Y` is common in comments.
+    pub fn brainfork() -> Self {
+        let mut dialect = Dialect::brainfuck().with_extension(Extension::Brainfork).unwrap();
+        dialect.name = String::from("brainfork");
+        dialect
+    }
+
+    // Extended Brainfuck Type I
+    pub fn extended() -> Self {
+        let mut dialect = Dialect::brainfuck().with_extension(Extension::Extended).unwrap();
+        dialect.name = String::from("extended");
+        dialect
+    }
+
+    pub fn builtin(name: &str) -> Option<Dialect> {
+        Dialect::builtins().into_iter()
+            .chain(vec![Dialect::pbrain(), Dialect::brainfork(), Dialect::extended()])
+            .find(|dialect| dialect.name.eq_ignore_ascii_case(name))
+    }
+
+    /// Read a token table of `key = value` lines.
+    ///
+    /// The keys are `name`, `extensions` (a comma separated list) and the
+    /// commands; lines starting with `#` are comments. Commands of extensions
+    /// may be left out, and are then written as themselves.
+    ///
+    /// ```text
+    /// name = shout
+    /// extensions = pbrain
+    /// + = MORE
+    /// - = LESS
+    /// ...
+    /// ```
+    pub fn parse(table: &str) -> Result<Self, &'static str> {
+        let mut name = "custom";
+        let mut tokens = [""; 8];
+        let mut extensions = Vec::new();
+        let mut extra = Vec::new();
+        for line in table.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (key, value) = line.split_once('=').ok_or("A line of the dialect has no `=`.")?;
+            let (key, value) = (key.trim(), value.trim());
+            if key == "name" {
+                name = value;
+                continue;
+            }
+            if key == "extensions" {
+                for ext in value.split(',').map(str::trim).filter(|ext| !ext.is_empty()) {
+                    extensions.push(Extension::from_name(ext).ok_or("Unknown extension in the dialect.")?);
+                }
+                continue;
+            }
+            let mut chars = key.chars();
+            let command = match (chars.next(), chars.next()) {
+                (Some(command), None) => command,
+                _ => return Err("Unknown key in the dialect."),
+            };
+            match COMMANDS.iter().position(|&c| c == command) {
+                Some(idx) => tokens[idx] = value,
+                None => extra.push((command, value)),
+            }
+        }
+        let mut dialect = Dialect::new(name, tokens)?;
+        for ext in extensions {
+            dialect = dialect.with_extension(ext)?;
+        }
+        for (command, token) in extra {
+            dialect.set_token(command, token)?;
+        }
+        Ok(dialect)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, &'static str> {
+        let table = fs::read_to_string(path).map_err(|_| "Failed to read the dialect file.")?;
+        Dialect::parse(&table)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn extensions(&self) -> &[Extension] {
+        &self.extensions
+    }
+
+    pub fn token(&self, command: char) -> Option<&str> {
+        self.tokens.iter().find(|(c, _)| *c == command).map(|(_, token)| token.as_str())
+    }
+
+    // The length of `token` if it is written at the start of `source`
+    fn match_token(token: &str, source: &str) -> Option<usize> {
+        let mut len = 0;
+        for (idx, word) in token.split(' ').enumerate() {
+            if idx > 0 {
+                let rest = &source[len..];
+                let trimmed = rest.trim_start();
+                if trimmed.len() == rest.len() {
+                    return None;
+                }
+                len += rest.len() - trimmed.len();
+            }
+            if !source[len..].starts_with(word) {
+                return None;
+            }
+            len += word.len();
+        }
+        Some(len)
+    }
+
+    pub(crate) fn tokenize(&self, source: &str) -> Vec<Token> {
+        let single = self.tokens.iter().all(|(_, token)| token.len() == 1);
+        let mut result = Vec::new();
+        let mut pos = 0;
+        while let Some(ch) = source[pos..].chars().next() {
+            let matched = if single {
+                self.tokens.iter().position(|(_, token)| token.starts_with(ch)).map(|idx| (idx, ch.len_utf8()))
+            } else {
+                // The longest token wins
+                self.tokens.iter().enumerate()
+                    .filter(|(_, (_, token))| token.starts_with(ch))
+                    .filter_map(|(idx, (_, token))| Dialect::match_token(token, &source[pos..]).map(|len| (idx, len)))
+                    .max_by_key(|&(idx, len)| (len, std::cmp::Reverse(idx)))
+            };
+            match matched {
+                Some((idx, len)) => {
+                    result.push(Token { command: self.tokens[idx].0, span: Span::new(pos, pos + len) });
+                    pos += len;
+                },
+                None => pos += ch.len_utf8(),
+            }
+        }
+        result
+    }
+
+    // Translate the commands of `source` into `to`. Comments are dropped, and commands which
+    // `to` has no token for are an error.
+    pub fn translate(&self, source: &str, to: &Dialect) -> Result<String, TranslateError> {
+        let separator = if to.tokens.iter().all(|(_, token)| token.len() == 1) { "" } else { " " };
+        let tokens = self.tokenize(source).iter()
+            .map(|token| to.token(token.command).ok_or_else(|| TranslateError {
+                command: token.command,
+                dialect: to.name.clone(),
+                span:    token.span,
+            }))
+            .collect::<Result<Vec<&str>, TranslateError>>()?;
+        Ok(tokens.join(separator))
+    }
+
+    // The builtin dialect whose tokens cover most of the non-whitespace text of `source`
+    pub fn detect(source: &str) -> Dialect {
+        let text_len = source.chars().filter(|c| !c.is_whitespace()).count().max(1);
+        let coverage = |dialect: &Dialect| {
+            let covered: usize = dialect.tokenize(source).iter()
+                .map(|token| source[token.span.start..token.span.end].chars().filter(|c| !c.is_whitespace()).count())
+                .sum();
+            covered * 1000 / text_len
+        };
+        Dialect::builtins().into_iter()
+            .fold((Dialect::brainfuck(), 0), |(best, best_coverage), dialect| {
+                let coverage = coverage(&dialect);
+                if coverage > best_coverage { (dialect, coverage) } else { (best, best_coverage) }
+            })
+            .0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Brainfuck;
+
+    const HELLO: &str = "++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>.>---.+++++++..+++.>>.<-.<.+++.------.--------.>>+.>++.";
+
+    fn run(bf: &mut Brainfuck) -> String {
+        bf.step_loop().unwrap();
+        bf.pop_result()
+    }
+
+    #[test]
+    fn ook_round_trip() {
+        let ook = Dialect::brainfuck().translate(HELLO, &Dialect::ook()).unwrap();
+        assert!(ook.starts_with("Ook. Ook. Ook. Ook."));
+        let mut bf = Brainfuck::with_dialect(ook.replace("Ook. Ook?", "Ook.\n  Ook?"), &Dialect::ook()).unwrap();
+        assert_eq!(run(&mut bf), "Hello World!\n");
+        assert_eq!(Dialect::ook().translate(&ook, &Dialect::brainfuck()).unwrap(), HELLO);
+    }
+
+    #[test]
+    fn spans_point_into_the_dialect_source() {
+        let bf = Brainfuck::with_dialect(String::from("x Ook. Ook. Ook! Ook!"), &Dialect::ook()).unwrap();
+        assert_eq!(bf.span(0), Some(Span::new(2, 11)));
+        assert_eq!(bf.span(1), Some(Span::new(12, 21)));
+    }
+
+    #[test]
+    fn custom_table() {
+        let table = "# Shouting\nname = shout\n+ = MORE\n- = LESS\n> = RIGHT\n< = LEFT\n[ = WHILE\n] = END\n. = SAY\n, = HEAR\n";
+        let shout = Dialect::parse(table).unwrap();
+        assert_eq!(shout.name(), "shout");
+        let source = Dialect::brainfuck().translate("+++[>++<-]>.", &shout).unwrap();
+        assert_eq!(source, "MORE MORE MORE WHILE RIGHT MORE MORE LEFT LESS END RIGHT SAY");
+        let mut bf = Brainfuck::with_dialect(source, &shout).unwrap();
+        assert_eq!(run(&mut bf), "\u{6}");
+
+        assert!(Dialect::parse("+ = a").is_err());
+        assert!(Dialect::parse("+ = a\n- = a\n> = b\n< = c\n[ = d\n] = e\n. = f\n, = g").is_err());
+    }
+
+    #[test]
+    fn extension_tokens() {
+        let table = "extensions = pbrain\n+ = a\n- = b\n> = c\n< = d\n[ = e\n] = f\n. = g\n, = h\n: = call\n";
+        let dialect = Dialect::parse(table).unwrap();
+        assert_eq!(dialect.extensions(), &[Extension::Pbrain]);
+        assert_eq!(dialect.token('('), Some("("));
+        assert_eq!(dialect.token(':'), Some("call"));
+        // Without the extension, its commands are unknown
+        assert!(Dialect::parse(&table.replace("extensions = pbrain", "")).is_err());
+        assert_eq!(Dialect::detect("+(comment).").name(), "brainfuck");
+    }
+
+    #[test]
+    fn untranslatable_commands() {
+        let err = Dialect::brainfork().translate("+Y.", &Dialect::ook()).unwrap_err();
+        assert_eq!(err, TranslateError { command: 'Y', dialect: String::from("ook"), span: Span::new(1, 2) });
+        assert_eq!(err.to_string(), "The command `Y` has no token in the dialect ook.");
+        assert!(Dialect::pbrain().translate("(+):", &Dialect::brainfuck()).is_err());
+        assert_eq!(Dialect::pbrain().translate("(+):", &Dialect::pbrain()).unwrap(), "(+):");
+    }
+
+    #[test]
+    fn detect_builtin() {
+        let ook = Dialect::brainfuck().translate(HELLO, &Dialect::ook()).unwrap();
+        assert_eq!(Dialect::detect(&ook).name(), "ook");
+        assert_eq!(Dialect::detect(HELLO).name(), "brainfuck");
+    }
+}
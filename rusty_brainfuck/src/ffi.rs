@@ -0,0 +1,342 @@
+// The C interface, built into the shared library. include/rusty_brainfuck.h is generated from
+// this file by tests/ffi.rs, so every item of the interface keeps to one of the forms it reads:
+// `pub const` status codes with a trailing comment, and `pub unsafe extern "C" fn` with `///` docs.
+#![allow(clippy::missing_safety_doc)]  // The header states the pointer contracts
+
+use std::collections::VecDeque;
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::slice;
+
+use crate::{Brainfuck, EofPolicy, ERROR_POINTER_LEFT, ERROR_POINTER_RIGHT};
+
+pub const BF_OK: c_int = 0;                   // The program ended
+pub const BF_NEEDS_INPUT: c_int = 1;          // A `,` waits for bytes from bf_feed_input or bf_close_input
+pub const BF_BUDGET_SPENT: c_int = 2;         // The step budget ran out, and bf_run can carry on
+pub const BF_ERROR_ARGUMENT: c_int = -1;      // A null pointer, a string that isn't UTF-8, or a value out of range
+pub const BF_ERROR_PARSE: c_int = -2;         // Unbalanced brackets in the program
+pub const BF_ERROR_POINTER_LEFT: c_int = -3;  // The pointer moved left of the first cell
+pub const BF_ERROR_POINTER_RIGHT: c_int = -4; // The pointer moved right of the last cell
+pub const BF_ERROR_RUNTIME: c_int = -5;       // Any other error while running
+pub const BF_ERROR_PANIC: c_int = -6;         // A bug in the library, which leaves the machine unusable
+
+pub const BF_EOF_ZERO: c_int = 0;             // `,` stores 0 once the input is closed
+pub const BF_EOF_MINUS_ONE: c_int = 1;        // `,` stores 255
+pub const BF_EOF_UNCHANGED: c_int = 2;        // `,` leaves the cell as it is
+
+pub struct BfMachine {
+    bf:     Brainfuck,
+    source: String,
+    input:  VecDeque<u8>,
+    closed: bool,  // Whether the input ends after the queued bytes
+    eof:    EofPolicy,
+    output: VecDeque<u8>,
+    error:  CString,
+}
+
+impl BfMachine {
+    fn fail(&mut self, code: c_int, message: &str) -> c_int {
+        self.error = CString::new(message.replace('\0', "")).unwrap_or_default();
+        code
+    }
+
+    fn run(&mut self, budget: u64, steps: &mut u64) -> c_int {
+        loop {
+            if self.bf.is_input_mode() {
+                let answered = match self.input.pop_front() {
+                    Some(byte) => self.bf.set_input_byte(byte),
+                    None if self.closed => self.bf.set_eof(self.eof),
+                    None => return BF_NEEDS_INPUT,
+                };
+                if let Err(err) = answered {
+                    return self.fail(BF_ERROR_RUNTIME, err);
+                }
+                continue;
+            }
+            if self.bf.reach_eop() {
+                return BF_OK;
+            }
+            if *steps == budget {
+                return BF_BUDGET_SPENT;
+            }
+            let pc = self.bf.pc();
+            if let Err(err) = self.bf.step() {
+                let code = match err {
+                    ERROR_POINTER_LEFT => BF_ERROR_POINTER_LEFT,
+                    ERROR_POINTER_RIGHT => BF_ERROR_POINTER_RIGHT,
+                    _ => BF_ERROR_RUNTIME,
+                };
+                let message = match self.bf.span(pc) {
+                    Some(span) => {
+                        let (line, col) = span.line_col(&self.source);
+                        format!("{}:{}: {}", line, col, err)
+                    },
+                    None => err.to_string(),
+                };
+                return self.fail(code, &message);
+            }
+            *steps += 1;
+            self.output.extend(self.bf.pop_result().chars().map(|c| c as u8));
+        }
+    }
+}
+
+// Keep panics from unwinding into C
+fn guard<T>(on_panic: T, f: impl FnOnce() -> T) -> T {
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or(on_panic)
+}
+
+fn eof_policy(policy: c_int) -> Option<EofPolicy> {
+    match policy {
+        BF_EOF_ZERO => Some(EofPolicy::Zero),
+        BF_EOF_MINUS_ONE => Some(EofPolicy::MinusOne),
+        BF_EOF_UNCHANGED => Some(EofPolicy::Unchanged),
+        _ => None,
+    }
+}
+
+/// Parse a NUL-terminated program and store a new machine in `*machine`, or NULL on failure.
+/// A `tape_size` of 0 keeps the default of 30000 cells.
+#[no_mangle]
+pub unsafe extern "C" fn bf_create(program: *const c_char, tape_size: usize, machine: *mut *mut BfMachine) -> c_int {
+    if machine.is_null() {
+        return BF_ERROR_ARGUMENT;
+    }
+    *machine = ptr::null_mut();
+    if program.is_null() {
+        return BF_ERROR_ARGUMENT;
+    }
+    let source = match CStr::from_ptr(program).to_str() {
+        Ok(source) => source.to_string(),
+        Err(_) => return BF_ERROR_ARGUMENT,
+    };
+    guard(BF_ERROR_PANIC, || {
+        let mut bf = match Brainfuck::new(source.clone()) {
+            Ok(bf) => bf,
+            Err(_) => return BF_ERROR_PARSE,
+        };
+        if tape_size > 0 && bf.set_tape_size(tape_size).is_err() {
+            return BF_ERROR_ARGUMENT;
+        }
+        let created = BfMachine {
+            bf,
+            source,
+            input:  VecDeque::new(),
+            closed: false,
+            eof:    EofPolicy::Zero,
+            output: VecDeque::new(),
+            error:  CString::default(),
+        };
+        *machine = Box::into_raw(Box::new(created));
+        BF_OK
+    })
+}
+
+/// Free a machine from bf_create. NULL is ignored.
+#[no_mangle]
+pub unsafe extern "C" fn bf_destroy(machine: *mut BfMachine) {
+    if !machine.is_null() {
+        drop(Box::from_raw(machine));
+    }
+}
+
+/// What `,` stores once the input is closed and used up, one of the BF_EOF_ values.
+#[no_mangle]
+pub unsafe extern "C" fn bf_set_eof(machine: *mut BfMachine, policy: c_int) -> c_int {
+    match (machine.as_mut(), eof_policy(policy)) {
+        (Some(machine), Some(policy)) => {
+            machine.eof = policy;
+            BF_OK
+        },
+        _ => BF_ERROR_ARGUMENT,
+    }
+}
+
+/// Queue `len` bytes of input for the `,` commands to come.
+#[no_mangle]
+pub unsafe extern "C" fn bf_feed_input(machine: *mut BfMachine, bytes: *const u8, len: usize) -> c_int {
+    match machine.as_mut() {
+        Some(_) if bytes.is_null() && len > 0 => BF_ERROR_ARGUMENT,
+        Some(machine) => {
+            if len > 0 {
+                machine.input.extend(slice::from_raw_parts(bytes, len));
+            }
+            BF_OK
+        },
+        None => BF_ERROR_ARGUMENT,
+    }
+}
+
+/// Mark the end of the input, so that `,` follows the EOF policy after the queued bytes.
+#[no_mangle]
+pub unsafe extern "C" fn bf_close_input(machine: *mut BfMachine) -> c_int {
+    match machine.as_mut() {
+        Some(machine) => {
+            machine.closed = true;
+            BF_OK
+        },
+        None => BF_ERROR_ARGUMENT,
+    }
+}
+
+/// Run at most `budget` steps. Returns BF_OK, BF_NEEDS_INPUT, BF_BUDGET_SPENT or an error,
+/// and adds the steps taken to `*steps` unless it is NULL.
+#[no_mangle]
+pub unsafe extern "C" fn bf_run(machine: *mut BfMachine, budget: u64, steps: *mut u64) -> c_int {
+    let machine = match machine.as_mut() {
+        Some(machine) => machine,
+        None => return BF_ERROR_ARGUMENT,
+    };
+    machine.error = CString::default();
+    let mut taken = 0;
+    let code = guard(BF_ERROR_PANIC, || machine.run(budget, &mut taken));
+    if let Some(steps) = steps.as_mut() {
+        *steps += taken;
+    }
+    code
+}
+
+/// Move up to `capacity` bytes of output into `buffer`, and return how many were moved.
+#[no_mangle]
+pub unsafe extern "C" fn bf_read_output(machine: *mut BfMachine, buffer: *mut u8, capacity: usize) -> usize {
+    match machine.as_mut() {
+        Some(machine) if !buffer.is_null() => {
+            let len = capacity.min(machine.output.len());
+            let buffer = slice::from_raw_parts_mut(buffer, len);
+            for (slot, byte) in buffer.iter_mut().zip(machine.output.drain(..len)) {
+                *slot = byte;
+            }
+            len
+        },
+        _ => 0,
+    }
+}
+
+/// Copy up to `capacity` cells from cell `start` on into `buffer`, and return how many were copied.
+#[no_mangle]
+pub unsafe extern "C" fn bf_read_tape(machine: *const BfMachine, start: usize, buffer: *mut u8, capacity: usize) -> usize {
+    match machine.as_ref() {
+        Some(machine) if !buffer.is_null() => {
+            let cells = machine.bf.memory().get(start..).unwrap_or(&[]);
+            let len = capacity.min(cells.len());
+            // Machines made by `bf_create` have 8-bit cells
+            for (slot, &cell) in slice::from_raw_parts_mut(buffer, len).iter_mut().zip(cells) {
+                *slot = cell as u8;
+            }
+            len
+        },
+        _ => 0,
+    }
+}
+
+/// The number of cells on the tape.
+#[no_mangle]
+pub unsafe extern "C" fn bf_tape_size(machine: *const BfMachine) -> usize {
+    machine.as_ref().map_or(0, |machine| machine.bf.tape_size())
+}
+
+/// The index of the cell under the pointer.
+#[no_mangle]
+pub unsafe extern "C" fn bf_pointer(machine: *const BfMachine) -> usize {
+    machine.as_ref().map_or(0, |machine| machine.bf.pointer())
+}
+
+/// The message of the last error of bf_run, with its `line:col`, or "" if there was none.
+/// The string belongs to the machine and lives until the next call of bf_run or bf_destroy.
+#[no_mangle]
+pub unsafe extern "C" fn bf_last_error(machine: *const BfMachine) -> *const c_char {
+    match machine.as_ref() {
+        Some(machine) => machine.error.as_ptr(),
+        None => b"\0".as_ptr() as *const c_char,
+    }
+}
+
+/// A static description of a status code.
+#[no_mangle]
+pub extern "C" fn bf_status_message(code: c_int) -> *const c_char {
+    let message: &'static [u8] = match code {
+        BF_OK => b"The program ended.\0",
+        BF_NEEDS_INPUT => b"The program waits for input.\0",
+        BF_BUDGET_SPENT => b"The step budget ran out.\0",
+        BF_ERROR_ARGUMENT => b"Invalid argument.\0",
+        BF_ERROR_PARSE => b"The brackets of the program are unbalanced.\0",
+        BF_ERROR_POINTER_LEFT => b"Too small pointer than the first address of memory.\0",
+        BF_ERROR_POINTER_RIGHT => b"Too large pointer than the size of memory.\0",
+        BF_ERROR_RUNTIME => b"Runtime error.\0",
+        BF_ERROR_PANIC => b"Internal error.\0",
+        _ => b"Unknown status code.\0",
+    };
+    message.as_ptr() as *const c_char
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create(program: &str, tape_size: usize) -> *mut BfMachine {
+        let program = CString::new(program).unwrap();
+        let mut machine = ptr::null_mut();
+        assert_eq!(unsafe { bf_create(program.as_ptr(), tape_size, &mut machine) }, BF_OK);
+        machine
+    }
+
+    fn output(machine: *mut BfMachine) -> Vec<u8> {
+        let mut buffer = [0; 64];
+        let len = unsafe { bf_read_output(machine, buffer.as_mut_ptr(), buffer.len()) };
+        buffer[..len].to_vec()
+    }
+
+    #[test]
+    fn runs_with_input_and_budget() {
+        let machine = create(",[.,]", 0);
+        let mut steps = 0;
+        unsafe {
+            assert_eq!(bf_run(machine, 100, &mut steps), BF_NEEDS_INPUT);
+            assert_eq!(bf_feed_input(machine, b"hi".as_ptr(), 2), BF_OK);
+            assert_eq!(bf_run(machine, 3, &mut steps), BF_BUDGET_SPENT);
+            assert_eq!(steps, 4);
+            assert_eq!(bf_run(machine, 100, ptr::null_mut()), BF_NEEDS_INPUT);
+            assert_eq!(output(machine), b"hi");
+            assert_eq!(bf_set_eof(machine, 7), BF_ERROR_ARGUMENT);
+            assert_eq!(bf_close_input(machine), BF_OK);
+            assert_eq!(bf_run(machine, 100, ptr::null_mut()), BF_OK);
+            assert_eq!(bf_tape_size(machine), 30_000);
+            bf_destroy(machine);
+        }
+    }
+
+    #[test]
+    fn reports_errors_and_tape() {
+        let mut machine = ptr::null_mut();
+        let unbalanced = CString::new("[").unwrap();
+        unsafe {
+            assert_eq!(bf_create(unbalanced.as_ptr(), 0, &mut machine), BF_ERROR_PARSE);
+            assert!(machine.is_null());
+            assert_eq!(bf_create(ptr::null(), 0, &mut machine), BF_ERROR_ARGUMENT);
+        }
+        let machine = create("+++>++\n>>", 3);
+        let mut tape = [9; 4];
+        unsafe {
+            assert_eq!(CStr::from_ptr(bf_last_error(machine)).to_str(), Ok(""));
+            assert_eq!(bf_run(machine, 100, ptr::null_mut()), BF_ERROR_POINTER_RIGHT);
+            let message = CStr::from_ptr(bf_last_error(machine)).to_str().unwrap();
+            assert_eq!(message, "2:1: Too large pointer than the size of memory.");
+            // A run without an error clears it
+            assert_eq!(bf_run(machine, 0, ptr::null_mut()), BF_BUDGET_SPENT);
+            assert_eq!(CStr::from_ptr(bf_last_error(machine)).to_str(), Ok(""));
+            assert_eq!(bf_pointer(machine), 1);
+            assert_eq!(bf_read_tape(machine, 0, tape.as_mut_ptr(), tape.len()), 3);
+            assert_eq!(bf_read_tape(machine, 5, tape.as_mut_ptr(), tape.len()), 0);
+            let status = CStr::from_ptr(bf_status_message(BF_ERROR_PARSE)).to_str().unwrap();
+            assert_eq!(status, "The brackets of the program are unbalanced.");
+            let status = CStr::from_ptr(bf_status_message(BF_ERROR_POINTER_LEFT)).to_str().unwrap();
+            assert_eq!(status, ERROR_POINTER_LEFT);
+            let status = CStr::from_ptr(bf_status_message(BF_ERROR_POINTER_RIGHT)).to_str().unwrap();
+            assert_eq!(status, ERROR_POINTER_RIGHT);
+            bf_destroy(machine);
+        }
+        assert_eq!(tape, [3, 2, 0, 9]);
+    }
+}
@@ -0,0 +1,446 @@
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::{Brainfuck, CellWidth, Dialect, EofPolicy, MAX_MEMORY};
+
+const HEADER: &str = ";;";
+
+// Larger outputs are only compared up to their first different line
+const MAX_DIFF_LINES: usize = 2000;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FixtureError {
+    pub file:    String,
+    pub message: &'static str,
+}
+
+impl fmt::Display for FixtureError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.file, self.message)
+    }
+}
+
+/// The semantics fixtures run under, unless their header says otherwise.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FixtureConfig {
+    pub tape_size:  usize,
+    pub cell_width: CellWidth,
+    pub eof:        EofPolicy,
+    pub fuel:       Option<u64>,
+    pub dialect:    Dialect,
+}
+
+impl Default for FixtureConfig {
+    fn default() -> Self {
+        FixtureConfig {
+            tape_size:  MAX_MEMORY,
+            cell_width: CellWidth::Bits8,
+            eof:        EofPolicy::Zero,
+            fuel:       Some(100_000_000),
+            dialect:    Dialect::brainfuck(),
+        }
+    }
+}
+
+/// A program with its input and expected output.
+///
+/// They come from `foo.in` and `foo.out` next to `foo.bf`, or from header lines at the top of the
+/// program, which are left out when it runs:
+///
+/// ```
+/// use rusty_brainfuck::{Fixture, FixtureConfig, Status};
+///
+/// let source = ";; in: ab\n;; out: ba\n,>,.<.";
+/// let fixture = Fixture::parse("swap", source, None, None).unwrap();
+/// assert_eq!(fixture.run(&FixtureConfig::default()).status, Status::Pass);
+/// ```
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Fixture {
+    pub name:       String,
+    pub source:     String,   // With the header blanked out, so that line numbers still match
+    pub input:      Vec<u8>,
+    pub expected:   Option<Vec<u8>>,
+    pub dialect:    Option<String>,
+    pub tape_size:  Option<usize>,
+    pub cell_width: Option<CellWidth>,
+    pub eof:        Option<EofPolicy>,
+    pub fuel:       Option<u64>,
+}
+
+// `\n`, `\t`, `\r`, `\0`, `\\` and `\xNN` in header values
+fn unescape(text: &str) -> Result<Vec<u8>, &'static str> {
+    let mut bytes = Vec::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut buf = [0; 4];
+            bytes.extend(c.encode_utf8(&mut buf).bytes());
+            continue;
+        }
+        bytes.push(match chars.next() {
+            Some('n') => b'\n',
+            Some('t') => b'\t',
+            Some('r') => b'\r',
+            Some('0') => 0,
+            Some('\\') => b'\\',
+            Some('x') => {
+                let hex: String = chars.by_ref().take(2).collect();
+                u8::from_str_radix(&hex, 16).map_err(|_| "Invalid \\x escape in the header.")?
+            },
+            _ => return Err("Invalid escape in the header."),
+        });
+    }
+    Ok(bytes)
+}
+
+fn escape(bytes: &[u8]) -> String {
+    bytes.iter().flat_map(|&byte| std::ascii::escape_default(byte)).map(char::from).collect()
+}
+
+impl Fixture {
+    // The input and expected output files win over the header
+    pub fn parse(name: &str, source: &str, input: Option<Vec<u8>>, expected: Option<Vec<u8>>)
+                 -> Result<Self, &'static str> {
+        let mut fixture = Fixture { name: name.to_string(), ..Fixture::default() };
+        let mut header_input = None;
+        let mut header_output = None;
+        let mut blanked = String::with_capacity(source.len());
+        let mut in_header = true;
+        for line in source.split_inclusive('\n') {
+            in_header &= line.starts_with(HEADER);
+            if !in_header {
+                blanked += line;
+                continue;
+            }
+            blanked.extend(line.chars().map(|c| if c == '\n' { c } else { ' ' }));
+            let entry = line[HEADER.len()..].trim_end_matches(&['\n', '\r'][..]);
+            let (key, value) = match entry.find(':') {
+                Some(at) => (entry[..at].trim(), entry[at + 1..].strip_prefix(' ').unwrap_or(&entry[at + 1..])),
+                None if entry.trim().is_empty() => continue,
+                None => return Err("Header lines need a `key: value`."),
+            };
+            match key {
+                "in" => header_input.get_or_insert_with(Vec::new).extend(unescape(value)?),
+                "out" => header_output.get_or_insert_with(Vec::new).extend(unescape(value)?),
+                "dialect" => fixture.dialect = Some(value.trim().to_string()),
+                "tape" => fixture.tape_size = Some(value.trim().parse().map_err(|_| "Invalid tape size in the header.")?),
+                "cells" => fixture.cell_width = Some(value.trim().parse().ok().and_then(CellWidth::from_bits)
+                    .ok_or("Invalid cell width in the header.")?),
+                "fuel" => fixture.fuel = Some(value.trim().parse().map_err(|_| "Invalid fuel in the header.")?),
+                "eof" => fixture.eof = Some(EofPolicy::from_name(value.trim()).ok_or("Unknown EOF policy in the header.")?),
+                _ => return Err("Unknown key in the header."),
+            }
+        }
+        fixture.source = blanked;
+        fixture.input = input.or(header_input).unwrap_or_default();
+        fixture.expected = expected.or(header_output);
+        Ok(fixture)
+    }
+
+    // Read `path` and the `.in` and `.out` files next to it
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, FixtureError> {
+        let path = path.as_ref();
+        let error = |message| FixtureError { file: path.display().to_string(), message };
+        let source = fs::read_to_string(path).map_err(|_| error("Failed to read the program."))?;
+        let input = fs::read(path.with_extension("in")).ok();
+        let expected = fs::read(path.with_extension("out")).ok();
+        let name = path.with_extension("").display().to_string();
+        Fixture::parse(&name, &source, input, expected).map_err(error)
+    }
+
+    fn execute(&self, config: &FixtureConfig, steps: &mut u64) -> Result<Vec<u8>, String> {
+        let dialect = match self.dialect.as_deref() {
+            Some(name) => Dialect::builtin(name).ok_or("Unknown dialect in the header.")?,
+            None => config.dialect.clone(),
+        };
+        let mut bf = Brainfuck::with_dialect(self.source.clone(), &dialect)?;
+        bf.set_tape_size(self.tape_size.unwrap_or(config.tape_size))?;
+        bf.set_cell_width(self.cell_width.unwrap_or(config.cell_width));
+        bf.set_fuel(self.fuel.or(config.fuel));
+        let eof = self.eof.unwrap_or(config.eof);
+        let mut input = self.input.iter();
+        let mut output = Vec::new();
+        loop {
+            if bf.is_input_mode() {
+                match input.next() {
+                    Some(&byte) => bf.set_input_byte(byte)?,
+                    None => bf.set_eof(eof)?,
+                }
+                continue;
+            }
+            if bf.reach_eop() {
+                break;
+            }
+            if let Err(err) = bf.step() {
+                let (line, col) = bf.span(bf.pc()).map_or((0, 0), |span| span.line_col(&self.source));
+                return Err(format!("{}:{}: {}", line, col, err));
+            }
+            *steps += 1;
+            output.extend(bf.pop_result().chars().map(|c| c as u8));
+        }
+        Ok(output)
+    }
+
+    pub fn run(&self, config: &FixtureConfig) -> Outcome {
+        let started = Instant::now();
+        let mut steps = 0;
+        let status = match (self.execute(config, &mut steps), &self.expected) {
+            (Err(message), _) => Status::Error(message),
+            (Ok(_), None) => Status::Error(String::from("No expected output.")),
+            (Ok(output), Some(expected)) if output == *expected => Status::Pass,
+            (Ok(output), Some(expected)) => Status::Fail(diff(expected, &output)),
+        };
+        Outcome { name: self.name.clone(), status, steps, time: started.elapsed() }
+    }
+}
+
+// Expected lines marked with `-`, actual ones with `+`
+fn diff(expected: &[u8], actual: &[u8]) -> String {
+    let old: Vec<&[u8]> = expected.split_inclusive(|&b| b == b'\n').collect();
+    let new: Vec<&[u8]> = actual.split_inclusive(|&b| b == b'\n').collect();
+    if old.len() > MAX_DIFF_LINES || new.len() > MAX_DIFF_LINES {
+        let line = old.iter().zip(&new).take_while(|(a, b)| a == b).count();
+        return format!(
+            "first difference on line {}\n-{}\n+{}\n",
+            line + 1, escape(old.get(line).unwrap_or(&&[][..])), escape(new.get(line).unwrap_or(&&[][..])));
+    }
+    // Lengths of the longest common subsequences of the suffixes
+    let mut lcs = vec![vec![0; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = if old[i] == new[j] { lcs[i + 1][j + 1] + 1 } else { lcs[i + 1][j].max(lcs[i][j + 1]) };
+        }
+    }
+    let (mut i, mut j) = (0, 0);
+    let mut text = String::new();
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            text += &format!(" {}\n", escape(old[i]));
+            i += 1;
+            j += 1;
+        } else if i < old.len() && (j == new.len() || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            text += &format!("-{}\n", escape(old[i]));
+            i += 1;
+        } else {
+            text += &format!("+{}\n", escape(new[j]));
+            j += 1;
+        }
+    }
+    text
+}
+
+// Every `.bf` file under `path`, or `path` itself when it is a file. Files and directories
+// which can't be read are kept as errors, so that the other fixtures still run.
+pub fn discover<P: AsRef<Path>>(path: P) -> Result<Vec<Result<Fixture, FixtureError>>, FixtureError> {
+    let path = path.as_ref();
+    if path.is_file() {
+        return Ok(vec![Fixture::load(path)]);
+    }
+    let error = |message| FixtureError { file: path.display().to_string(), message };
+    let mut entries: Vec<_> = fs::read_dir(path).map_err(|_| error("Failed to read the directory."))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .collect();
+    entries.sort();
+    let mut fixtures = Vec::new();
+    for entry in entries {
+        if entry.is_dir() {
+            match discover(&entry) {
+                Ok(found) => fixtures.extend(found),
+                Err(err) => fixtures.push(Err(err)),
+            }
+        } else if entry.extension().is_some_and(|ext| ext == "bf" || ext == "b") {
+            fixtures.push(Fixture::load(&entry));
+        }
+    }
+    Ok(fixtures)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Status {
+    Pass,
+    Fail(String),   // A diff of the expected and actual output
+    Error(String),  // The program couldn't run to its end
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Outcome {
+    pub name:   String,
+    pub status: Status,
+    pub steps:  u64,
+    pub time:   Duration,
+}
+
+fn xml(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+fn json(text: &str) -> String {
+    let mut escaped = String::new();
+    for c in text.chars() {
+        match c {
+            '"' => escaped += "\\\"",
+            '\\' => escaped += "\\\\",
+            '\n' => escaped += "\\n",
+            c if c.is_control() => escaped += &format!("\\u{:04x}", c as u32),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// The outcomes of a test run, and their reports as text, JUnit XML or JSON.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct TestReport {
+    pub outcomes: Vec<Outcome>,
+}
+
+impl TestReport {
+    // Fixtures which failed to load are errors
+    pub fn run(fixtures: &[Result<Fixture, FixtureError>], config: &FixtureConfig) -> Self {
+        let outcome = |fixture: &Result<Fixture, FixtureError>| match fixture {
+            Ok(fixture) => fixture.run(config),
+            Err(err) => Outcome {
+                name:   Path::new(&err.file).with_extension("").display().to_string(),
+                status: Status::Error(String::from(err.message)),
+                steps:  0,
+                time:   Duration::default(),
+            },
+        };
+        TestReport { outcomes: fixtures.iter().map(outcome).collect() }
+    }
+
+    fn count(&self, pass: bool, error: bool) -> usize {
+        self.outcomes.iter()
+            .filter(|outcome| match outcome.status {
+                Status::Pass => pass,
+                Status::Fail(_) => !pass && !error,
+                Status::Error(_) => error,
+            })
+            .count()
+    }
+
+    pub fn passed(&self) -> usize {
+        self.count(true, false)
+    }
+
+    pub fn failed(&self) -> usize {
+        self.count(false, false)
+    }
+
+    pub fn errors(&self) -> usize {
+        self.count(false, true)
+    }
+
+    pub fn success(&self) -> bool {
+        self.passed() == self.outcomes.len()
+    }
+
+    pub fn text(&self) -> String {
+        let mut text = String::new();
+        for outcome in &self.outcomes {
+            match &outcome.status {
+                Status::Pass => text += &format!("pass  {}\n", outcome.name),
+                Status::Fail(diff) => text += &format!("FAIL  {}\n{}", outcome.name, diff),
+                Status::Error(message) => text += &format!("ERROR {}: {}\n", outcome.name, message),
+            }
+        }
+        text + &format!("\n{} passed, {} failed, {} errors\n", self.passed(), self.failed(), self.errors())
+    }
+
+    pub fn junit(&self) -> String {
+        let time: Duration = self.outcomes.iter().map(|outcome| outcome.time).sum();
+        let mut text = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        text += &format!(
+            "<testsuite name=\"bf\" tests=\"{}\" failures=\"{}\" errors=\"{}\" time=\"{:.3}\">\n",
+            self.outcomes.len(), self.failed(), self.errors(), time.as_secs_f64());
+        for outcome in &self.outcomes {
+            let case = format!(
+                "  <testcase classname=\"bf\" name=\"{}\" time=\"{:.3}\"",
+                xml(&outcome.name), outcome.time.as_secs_f64());
+            text += &match &outcome.status {
+                Status::Pass => format!("{}/>\n", case),
+                Status::Fail(diff) => format!(
+                    "{}>\n    <failure message=\"output differs\">{}</failure>\n  </testcase>\n", case, xml(diff)),
+                Status::Error(message) => format!(
+                    "{}>\n    <error message=\"{}\"/>\n  </testcase>\n", case, xml(message)),
+            };
+        }
+        text + "</testsuite>\n"
+    }
+
+    pub fn json(&self) -> String {
+        let results: Vec<String> = self.outcomes.iter()
+            .map(|outcome| {
+                let (status, detail) = match &outcome.status {
+                    Status::Pass => ("pass", String::new()),
+                    Status::Fail(diff) => ("fail", format!(",\"diff\":\"{}\"", json(diff))),
+                    Status::Error(message) => ("error", format!(",\"message\":\"{}\"", json(message))),
+                };
+                format!(
+                    "{{\"name\":\"{}\",\"status\":\"{}\",\"steps\":{},\"time\":{:.6}{}}}",
+                    json(&outcome.name), status, outcome.steps, outcome.time.as_secs_f64(), detail)
+            })
+            .collect();
+        format!(
+            "{{\"tests\":{},\"passed\":{},\"failed\":{},\"errors\":{},\"results\":[{}]}}\n",
+            self.outcomes.len(), self.passed(), self.failed(), self.errors(), results.join(","))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn header_and_files() {
+        let source = ";; in: hi\\x21\n;; eof: minus-one\n;; tape: 4\n,[.,+]";
+        let fixture = Fixture::parse("cat", source, None, Some(b"hi".to_vec())).unwrap();
+        assert_eq!(fixture.input, b"hi!");
+        assert_eq!((fixture.eof, fixture.tape_size), (Some(EofPolicy::MinusOne), Some(4)));
+        assert_eq!((fixture.source.len(), fixture.source.trim()), (source.len(), ",[.,+]"));
+        assert_eq!(Fixture::parse("x", ";; color: red\n", None, None), Err("Unknown key in the header."));
+        let fixture = Fixture::parse("x", ";; in: a\n,.", Some(b"b".to_vec()), None).unwrap();
+        assert_eq!(fixture.input, b"b");
+        // 256 wraps to 0 in 8-bit cells only
+        let source = format!(";; cells: 16\n;; out: \\x01\n{}[>+<[-]]>.", "+".repeat(256));
+        let fixture = Fixture::parse("x", &source, None, None).unwrap();
+        assert_eq!(fixture.cell_width, Some(CellWidth::Bits16));
+        assert_eq!(fixture.run(&FixtureConfig::default()).status, Status::Pass);
+        assert_eq!(Fixture::parse("x", ";; cells: 12\n", None, None), Err("Invalid cell width in the header."));
+    }
+
+    #[test]
+    fn outcomes_and_diffs() {
+        let config = FixtureConfig::default();
+        let fixture = Fixture::parse("abc", ";; out: a\\nb\\nc\\n\n++++[>++++<-]>[<++++++>-]<+.", None, None).unwrap();
+        let outcome = fixture.run(&config);
+        assert_eq!(outcome.status, Status::Fail(String::from("-a\\n\n-b\\n\n-c\\n\n+a\n")));
+        let fixture = Fixture::parse("a", ";; out: a\\n\n;; out: b\n+[]", None, None).unwrap();
+        let outcome = fixture.run(&FixtureConfig { fuel: Some(10), ..config.clone() });
+        assert_eq!(outcome.status, Status::Error(String::from("3:3: Out of fuel.")));
+        assert_eq!(diff(b"a\nb\nc\n", b"a\nc\nd\n"), " a\\n\n-b\\n\n c\\n\n+d\\n\n");
+    }
+
+    #[test]
+    fn reports() {
+        let fixtures = vec![
+            Fixture::parse("ok", ";; out: \\x01\n+.", None, None),
+            Fixture::parse("bad<1>", ";; out: x\n+.", None, None),
+            Fixture::parse("none", "+.", None, None),
+        ];
+        let fixtures: Vec<_> = fixtures.into_iter().map(|fixture| Ok(fixture.unwrap())).collect();
+        let report = TestReport::run(&fixtures, &FixtureConfig::default());
+        assert_eq!((report.passed(), report.failed(), report.errors()), (1, 1, 1));
+        assert!(!report.success());
+        let junit = report.junit();
+        assert!(junit.contains("tests=\"3\" failures=\"1\" errors=\"1\""));
+        assert!(junit.contains("name=\"bad&lt;1&gt;\""));
+        assert!(junit.contains("<error message=\"No expected output.\"/>"));
+        let json = report.json();
+        assert!(json.starts_with("{\"tests\":3,\"passed\":1,\"failed\":1,\"errors\":1,\"results\":[{\"name\":\"ok\",\"status\":\"pass\",\"steps\":2,"));
+        assert!(json.contains("\"diff\":\"-x\\n+\\\\x01\\n\""));
+        assert!(report.text().ends_with("1 passed, 1 failed, 1 errors\n"));
+    }
+}
@@ -0,0 +1,260 @@
+use crate::{is_command, CodeGen};
+
+/// Layout options of `format_program`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FormatConfig {
+    pub indent: usize,  // Spaces per loop nesting level
+    pub width:  usize,  // Maximum line width of code, indentation included
+}
+
+impl Default for FormatConfig {
+    fn default() -> Self {
+        FormatConfig {
+            indent: 4,
+            width:  72,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Cmd(char),
+    Open,
+    Close,
+    Comment(String),
+    Blank,
+}
+
+#[derive(Debug, PartialEq)]
+enum Item {
+    Code(String),
+    Comment(String),
+    Blank,
+    Loop(Vec<Item>),
+}
+
+fn push_text(tokens: &mut Vec<Token>, text: &str) {
+    let lines: Vec<&str> = text.split('\n').collect();
+    for (idx, line) in lines.iter().enumerate() {
+        let line = line.trim();
+        if !line.is_empty() {
+            tokens.push(Token::Comment(line.to_string()));
+        } else if idx > 0 && idx + 1 < lines.len() {
+            tokens.push(Token::Blank);
+        }
+    }
+}
+
+fn tokenize(program: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut text = String::new();
+    for c in program.chars() {
+        if !is_command(c) {
+            text.push(c);
+            continue;
+        }
+        push_text(&mut tokens, &text);
+        text.clear();
+        tokens.push(match c {
+            '[' => Token::Open,
+            ']' => Token::Close,
+            _ => Token::Cmd(c),
+        });
+    }
+    push_text(&mut tokens, &text);
+    tokens
+}
+
+fn parse<I: Iterator<Item = Token>>(tokens: &mut I) -> Vec<Item> {
+    let mut items = Vec::new();
+    while let Some(token) = tokens.next() {
+        match token {
+            Token::Cmd(c) => match items.last_mut() {
+                Some(Item::Code(code)) => code.push(c),
+                _ => items.push(Item::Code(c.to_string())),
+            },
+            Token::Open => items.push(Item::Loop(parse(tokens))),
+            Token::Close => break,
+            Token::Comment(text) => items.push(Item::Comment(text)),
+            Token::Blank => {
+                if !items.is_empty() && items.last() != Some(&Item::Blank) {
+                    items.push(Item::Blank);
+                }
+            },
+        }
+    }
+    if items.last() == Some(&Item::Blank) {
+        items.pop();
+    }
+    items
+}
+
+// Runs of the same command, which are never split unless they don't fit on a line
+fn runs(code: &str) -> Vec<&str> {
+    let mut runs = Vec::new();
+    let mut start = 0;
+    let bytes = code.as_bytes();
+    for idx in 1..=bytes.len() {
+        if idx == bytes.len() || bytes[idx] != bytes[start] {
+            runs.push(&code[start..idx]);
+            start = idx;
+        }
+    }
+    runs
+}
+
+struct Printer<'a> {
+    config: &'a FormatConfig,
+    lines:  Vec<String>,
+    line:   String,
+    depth:  usize,
+}
+
+impl<'a> Printer<'a> {
+    fn indent(&self) -> String {
+        " ".repeat(self.depth * self.config.indent)
+    }
+
+    fn available(&self) -> usize {
+        self.config.width.saturating_sub(self.depth * self.config.indent).max(1)
+    }
+
+    fn flush(&mut self) {
+        if !self.line.is_empty() {
+            let line = self.indent() + &self.line;
+            self.lines.push(line);
+            self.line.clear();
+        }
+    }
+
+    fn push_line(&mut self, text: &str) {
+        self.flush();
+        let line = if text.is_empty() { String::new() } else { self.indent() + text };
+        self.lines.push(line);
+    }
+
+    fn pack(&mut self, atom: &str) {
+        let available = self.available();
+        if !self.line.is_empty() && self.line.len() + atom.len() > available {
+            self.flush();
+        }
+        let mut atom = atom;
+        while atom.len() > available {
+            self.line.push_str(&atom[..available]);
+            self.flush();
+            atom = &atom[available..];
+        }
+        self.line.push_str(atom);
+    }
+
+    // A loop is kept on one line if it only contains commands and fits
+    fn inline_loop(&self, body: &[Item]) -> Option<String> {
+        let code = match body {
+            [] => "",
+            [Item::Code(code)] => code,
+            _ => return None,
+        };
+        if code.len() + 2 <= self.available() {
+            Some(format!("[{}]", code))
+        } else {
+            None
+        }
+    }
+
+    fn print(&mut self, items: &[Item]) {
+        for item in items {
+            match item {
+                Item::Code(code) => {
+                    for run in runs(code) {
+                        self.pack(run);
+                    }
+                },
+                Item::Comment(text) => self.push_line(text),
+                Item::Blank => self.push_line(""),
+                Item::Loop(body) => match self.inline_loop(body) {
+                    Some(text) => self.pack(&text),
+                    None => {
+                        self.push_line("[");
+                        self.depth += 1;
+                        self.print(body);
+                        self.flush();
+                        self.depth -= 1;
+                        self.push_line("]");
+                    },
+                },
+            }
+        }
+    }
+}
+
+/// Reflow a program by loop nesting.
+///
+/// Comments are kept in place on lines of their own, and blank lines between
+/// paragraphs are kept as a single blank line. Formatting is idempotent, and
+/// fails if the program is invalid or the formatted program generated
+/// different instructions.
+pub fn format_program(program: &str, config: &FormatConfig) -> Result<String, &'static str> {
+    let before = CodeGen::new(program).generate_insts()?;
+
+    let items = parse(&mut tokenize(program).into_iter());
+    let mut printer = Printer {
+        config,
+        lines: Vec::new(),
+        line:  String::new(),
+        depth: 0,
+    };
+    printer.print(&items);
+    printer.flush();
+    let mut formatted = printer.lines.join("\n");
+    formatted.push('\n');
+
+    let after = CodeGen::new(&formatted).generate_insts()?;
+    if before != after {
+        return Err("Formatting changed the program.");
+    }
+    Ok(formatted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn format(program: &str) -> String {
+        format_program(program, &FormatConfig::default()).unwrap()
+    }
+
+    #[test]
+    fn nests_loops_and_keeps_comments() {
+        let program = "print two ++>+[ loop\n[-]<[->+<] move\n\n>.]";
+        let formatted = format(program);
+        assert_eq!(formatted, "print two\n++>+\n[\n    loop\n    [-]<[->+<]\n    move\n\n    >.\n]\n");
+    }
+
+    #[test]
+    fn idempotent() {
+        let programs = [
+            "++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>.>---.+++++++..+++.>>.<-.<.+++.------.--------.>>+.>++.",
+            "a\n\n\n b [c\n[d]  ]\n\n",
+            "",
+        ];
+        for program in programs.iter() {
+            let once = format(program);
+            assert_eq!(format(&once), once);
+        }
+    }
+
+    #[test]
+    fn wraps_long_lines() {
+        let config = FormatConfig { indent: 2, width: 10 };
+        let formatted = format_program(&"+".repeat(15), &config).unwrap();
+        assert_eq!(formatted, "++++++++++\n+++++\n");
+        let config = FormatConfig { indent: 2, width: 12 };
+        let formatted = format_program("[+++++++++>>>>>>>]", &config).unwrap();
+        assert_eq!(formatted, "[\n  +++++++++\n  >>>>>>>\n]\n");
+    }
+
+    #[test]
+    fn rejects_invalid_programs() {
+        assert!(format_program("[[]", &FormatConfig::default()).is_err());
+    }
+}
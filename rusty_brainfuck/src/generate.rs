@@ -0,0 +1,184 @@
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Tradeoff {
+    Size,   // Initialize cells with a multiplication loop, and emit the shortest program found
+    Speed,  // No loops at all, so every byte takes at most three instructions to print
+}
+
+impl Tradeoff {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "size" => Some(Tradeoff::Size),
+            "speed" => Some(Tradeoff::Speed),
+            _ => None,
+        }
+    }
+}
+
+/// Options of `generate`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GenerateConfig {
+    pub tradeoff:  Tradeoff,
+    pub max_cells: usize,  // How many cells may hold the values of recently printed bytes
+}
+
+impl Default for GenerateConfig {
+    fn default() -> Self {
+        GenerateConfig {
+            tradeoff:  Tradeoff::Size,
+            max_cells: 4,
+        }
+    }
+}
+
+// The shortest way to change a (wrapping) cell from `from` to `to`
+fn adjust(from: u8, to: u8) -> (char, usize) {
+    let up = to.wrapping_sub(from) as usize;
+    if up <= 128 {
+        ('+', up)
+    } else {
+        ('-', 256 - up)
+    }
+}
+
+fn repeat(code: &mut String, c: char, n: usize) {
+    code.extend(std::iter::repeat_n(c, n));
+}
+
+// Prints bytes by picking the cell which is cheapest to move to and adjust.
+struct Emitter {
+    code:    String,
+    pointer: usize,
+    values:  Vec<u8>,
+}
+
+impl Emitter {
+    fn cost(&self, cell: usize, byte: u8) -> usize {
+        cell.abs_diff(self.pointer) + adjust(self.values[cell], byte).1
+    }
+
+    fn move_to(&mut self, cell: usize) {
+        if cell > self.pointer {
+            repeat(&mut self.code, '>', cell - self.pointer);
+        } else {
+            repeat(&mut self.code, '<', self.pointer - cell);
+        }
+        self.pointer = cell;
+    }
+
+    fn print(&mut self, byte: u8) {
+        let cell = (0..self.values.len()).min_by_key(|&cell| self.cost(cell, byte)).unwrap_or(0);
+        self.move_to(cell);
+        let (c, n) = adjust(self.values[cell], byte);
+        repeat(&mut self.code, c, n);
+        self.values[cell] = byte;
+        self.code.push('.');
+    }
+}
+
+fn emit(text: &[u8], code: String, pointer: usize, values: Vec<u8>) -> String {
+    let mut emitter = Emitter { code, pointer, values };
+    for &byte in text {
+        emitter.print(byte);
+    }
+    emitter.code
+}
+
+// Group the distinct bytes into at most `n` clusters by merging the closest neighbours,
+// and return the rounded mean of each cluster.
+fn centers(text: &[u8], n: usize) -> Vec<u8> {
+    let mut bytes = text.to_vec();
+    bytes.sort_unstable();
+    bytes.dedup();
+    let mut clusters: Vec<Vec<u8>> = bytes.into_iter().map(|b| vec![b]).collect();
+    while clusters.len() > n.max(1) {
+        let idx = (1..clusters.len())
+            .min_by_key(|&idx| clusters[idx][0] - clusters[idx - 1][clusters[idx - 1].len() - 1])
+            .unwrap();
+        let merged = clusters.remove(idx);
+        clusters[idx - 1].extend(merged);
+    }
+    clusters.iter()
+        .map(|c| ((c.iter().map(|&b| b as usize).sum::<usize>() + c.len() / 2) / c.len()) as u8)
+        .collect()
+}
+
+// `factor` times `[>a>b>c<<<-]`, leaving `factor * a`, `factor * b`, ... in cells 1, 2, 3, ...
+fn with_loop(text: &[u8], centers: &[u8], factor: usize, spare_cells: usize) -> String {
+    let mut code = String::new();
+    let mut values = vec![0u8];
+    repeat(&mut code, '+', factor);
+    code.push('[');
+    for &center in centers {
+        let times = (center as usize + factor / 2) / factor;
+        code.push('>');
+        repeat(&mut code, '+', times);
+        values.push((factor * times) as u8);
+    }
+    repeat(&mut code, '<', centers.len());
+    code.push_str("-]");
+    values.resize(values.len().max(spare_cells), 0);
+    emit(text, code, 0, values)
+}
+
+/// Generate a program which prints `text`.
+pub fn generate(text: &[u8], config: &GenerateConfig) -> String {
+    let cells = config.max_cells.max(1);
+    let plain = emit(text, String::new(), 0, vec![0; cells]);
+    if config.tradeoff == Tradeoff::Speed || text.is_empty() {
+        return plain;
+    }
+
+    let mut best = plain;
+    for n in 1..=cells {
+        let centers = centers(text, n);
+        for factor in 2..=16 {
+            let candidate = with_loop(text, &centers, factor, cells);
+            if candidate.len() < best.len() {
+                best = candidate;
+            }
+        }
+    }
+    best
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Brainfuck;
+
+    fn run(program: &str) -> Vec<u8> {
+        let mut bf = Brainfuck::new(String::from(program)).unwrap();
+        bf.step_loop().unwrap();
+        bf.pop_result().chars().map(|c| c as u8).collect()
+    }
+
+    #[test]
+    fn prints_the_text() {
+        let all_bytes: Vec<u8> = (0..=255).collect();
+        let texts: [&[u8]; 4] = [b"Hello, World!\n", b"", b"aaaa", &all_bytes];
+        for text in texts.iter() {
+            for &tradeoff in [Tradeoff::Size, Tradeoff::Speed].iter() {
+                let config = GenerateConfig { tradeoff, max_cells: 4 };
+                assert_eq!(run(&generate(text, &config)), text.to_vec());
+            }
+        }
+    }
+
+    #[test]
+    fn size_is_smaller_than_naive() {
+        let text = b"The quick brown fox jumps over the lazy dog.";
+        let size = generate(text, &GenerateConfig::default());
+        let speed = generate(text, &GenerateConfig { tradeoff: Tradeoff::Speed, max_cells: 4 });
+        assert!(size.contains('['));
+        assert!(size.len() < speed.len());
+        // A single cell, adjusted from byte to byte
+        let naive = generate(text, &GenerateConfig { tradeoff: Tradeoff::Speed, max_cells: 1 });
+        assert!(size.len() < naive.len());
+    }
+
+    #[test]
+    fn speed_has_no_loops() {
+        let program = generate(b"Brainfuck", &GenerateConfig { tradeoff: Tradeoff::Speed, max_cells: 2 });
+        assert!(!program.contains('['));
+    }
+}
@@ -0,0 +1,236 @@
+use std::collections::{BTreeMap, VecDeque};
+
+use crate::Pointer;
+use crate::thread::Scheduler;
+
+/// How much execution history a `Brainfuck` keeps.
+///
+/// History is split into segments of at most `snapshot_interval` steps, each
+/// starting with a full snapshot of the machine. At most `max_snapshots`
+/// segments are retained; older ones are dropped, so memory use stays bounded.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HistoryConfig {
+    pub snapshot_interval: usize,
+    pub max_snapshots:     usize,
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        HistoryConfig {
+            snapshot_interval: 1_000,
+            max_snapshots:     64,
+        }
+    }
+}
+
+// The information needed to revert a single `step` or `set_input`.
+#[derive(Clone)]
+pub(crate) struct Undo {
+    pub(crate) counter:    usize,
+    pub(crate) pointer:    Pointer,
+    pub(crate) input_mode: bool,
+    pub(crate) cell:       Option<(usize, u32)>, // Address and old value of a written cell
+    pub(crate) emitted:    usize,                // Characters output before the step
+    pub(crate) input:      Option<(char, usize)>, // Consumed character and the old length of the queue
+    pub(crate) calls:      usize,                 // Depth of the call stack
+    pub(crate) returned:   Option<usize>,         // Call which was popped by a return
+    pub(crate) procedure:  Option<(u32, Option<usize>)>, // Name and old definition of a defined procedure
+    pub(crate) threads:    Option<Box<SavedThreads>>,   // Saved on a step which forks, ends or switches threads
+    pub(crate) slice:      usize,                       // Steps the running thread took in its quantum
+    pub(crate) forked:     Option<(usize, u32)>,        // Address and old value of the first cell of a child
+    pub(crate) storage:    u32,
+}
+
+#[derive(Clone)]
+pub(crate) struct SavedThreads {
+    pub(crate) scheduler:  Scheduler,
+    pub(crate) call_stack: Vec<usize>,
+}
+
+pub(crate) struct Snapshot {
+    pub(crate) memory:      Vec<u32>,
+    pub(crate) pointer:     Pointer,
+    pub(crate) counter:     usize,
+    pub(crate) input_queue: String,
+    pub(crate) input_mode:  bool,
+    pub(crate) procedures:  BTreeMap<u32, usize>,
+    pub(crate) call_stack:  Vec<usize>,
+    pub(crate) scheduler:   Scheduler,
+    pub(crate) storage:     u32,
+    pub(crate) emitted:     usize,
+}
+
+pub(crate) struct Segment {
+    pub(crate) snapshot: Snapshot,
+    pub(crate) undo:     Vec<Undo>,
+}
+
+pub(crate) struct History {
+    config:   HistoryConfig,
+    segments: VecDeque<Segment>,
+}
+
+impl History {
+    pub(crate) fn new(config: HistoryConfig) -> Self {
+        History {
+            config,
+            segments: VecDeque::new(),
+        }
+    }
+
+    pub(crate) fn needs_snapshot(&self) -> bool {
+        match self.segments.back() {
+            Some(segment) => segment.undo.len() >= self.config.snapshot_interval.max(1),
+            None => true,
+        }
+    }
+
+    pub(crate) fn push_snapshot(&mut self, snapshot: Snapshot) {
+        self.segments.push_back(Segment { snapshot, undo: Vec::new() });
+        while self.segments.len() > self.config.max_snapshots.max(1) {
+            self.segments.pop_front();
+        }
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.segments.clear();
+    }
+
+    pub(crate) fn record(&mut self, undo: Undo) {
+        if let Some(segment) = self.segments.back_mut() {
+            segment.undo.push(undo);
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.segments.iter().map(|segment| segment.undo.len()).sum()
+    }
+
+    // Drop empty segments at the end, so that `last_segment_len` reflects the undoable steps.
+    fn trim(&mut self) {
+        while let Some(segment) = self.segments.back() {
+            if segment.undo.is_empty() {
+                self.segments.pop_back();
+            } else {
+                break;
+            }
+        }
+    }
+
+    pub(crate) fn last_segment_len(&mut self) -> usize {
+        self.trim();
+        self.segments.back().map_or(0, |segment| segment.undo.len())
+    }
+
+    pub(crate) fn pop_segment(&mut self) -> Option<Segment> {
+        self.trim();
+        self.segments.pop_back()
+    }
+
+    pub(crate) fn pop(&mut self) -> Option<Undo> {
+        self.trim();
+        self.segments.back_mut().and_then(|segment| segment.undo.pop())
+    }
+
+    // How many steps have to be reverted until `pred` matches the reverted step.
+    pub(crate) fn distance_to<P: Fn(&Undo) -> bool>(&self, pred: P) -> Option<usize> {
+        self.segments.iter().rev()
+            .flat_map(|segment| segment.undo.iter().rev())
+            .position(pred)
+            .map(|idx| idx + 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Brainfuck, HistoryConfig};
+
+    #[test]
+    fn step_back_restores_memory() {
+        let mut bf = Brainfuck::new(String::from("+++>++<-")).unwrap();
+        bf.enable_history(HistoryConfig::default());
+        bf.step_loop().unwrap();
+        assert_eq!(bf.memory()[0], 2);
+        bf.step_back().unwrap();
+        assert_eq!(bf.memory()[0], 3);
+        assert_eq!(bf.pointer(), 0);
+        bf.step_back().unwrap();
+        assert_eq!(bf.pointer(), 1);
+    }
+
+    #[test]
+    fn back_to_previous_write() {
+        let mut bf = Brainfuck::new(String::from("++>+++<[->+<]")).unwrap();
+        bf.enable_history(HistoryConfig { snapshot_interval: 3, max_snapshots: 100 });
+        bf.step_loop().unwrap();
+        assert_eq!(bf.memory()[1], 5);
+        bf.back_to_write(1).unwrap();
+        assert_eq!(bf.memory()[1], 4);
+        bf.back_to_write(1).unwrap();
+        assert_eq!(bf.memory()[1], 3);
+        bf.back_to_write(1).unwrap();
+        assert_eq!(bf.memory()[1], 0);
+        assert!(bf.back_to_write(1).is_err());
+    }
+
+    #[test]
+    fn history_is_bounded() {
+        let mut bf = Brainfuck::new(String::from("+[+]")).unwrap();
+        bf.enable_history(HistoryConfig { snapshot_interval: 10, max_snapshots: 4 });
+        bf.step_loop().unwrap();
+        assert!(bf.history_len() <= 40);
+        while bf.step_back().is_ok() {}
+        assert_eq!(bf.history_len(), 0);
+    }
+
+    #[test]
+    fn back_to_breakpoint() {
+        let mut bf = Brainfuck::new(String::from("+>+>+")).unwrap();
+        bf.enable_history(HistoryConfig::default());
+        bf.add_breakpoint(2);
+        bf.step_loop().unwrap();
+        bf.back_to_breakpoint().unwrap();
+        assert_eq!(bf.pc(), 2);
+        assert_eq!(bf.pointer(), 1);
+        assert_eq!(bf.memory()[0], 1);
+        assert_eq!(bf.memory()[1], 0);
+    }
+
+    #[test]
+    fn step_back_over_input() {
+        let mut bf = Brainfuck::new(String::from(",.")).unwrap();
+        bf.enable_history(HistoryConfig::default());
+        bf.step().unwrap();
+        bf.set_input(String::from("ab")).unwrap();
+        bf.step().unwrap();
+        assert_eq!(bf.queue_remain(), 1);
+        bf.step_back().unwrap();
+        bf.step_back().unwrap();
+        assert!(bf.is_input_mode());
+        assert_eq!(bf.queue_remain(), 0);
+        assert_eq!(bf.memory()[0], 0);
+    }
+
+    #[test]
+    fn step_back_over_output() {
+        let mut bf = Brainfuck::new(String::from("+.+.+.")).unwrap();
+        bf.enable_history(HistoryConfig { snapshot_interval: 2, max_snapshots: 8 });
+        for _ in 0..4 {
+            bf.step().unwrap();
+        }
+        assert_eq!(bf.pop_result(), "\u{1}\u{2}");
+        bf.step().unwrap();
+        bf.step().unwrap();
+        // The buffered output is taken back, the popped output is not output again
+        bf.step_back().unwrap();
+        assert_eq!(bf.pop_result(), "");
+        bf.step_back().unwrap();
+        bf.step_back().unwrap();
+        bf.step_back().unwrap();
+        bf.step_loop().unwrap();
+        assert_eq!(bf.pop_result(), "\u{3}");
+        while bf.step_back().is_ok() {}
+        bf.step_loop().unwrap();
+        assert_eq!(bf.pop_result(), "");
+    }
+}
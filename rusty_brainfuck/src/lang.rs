@@ -0,0 +1,771 @@
+use std::collections::HashMap;
+
+use crate::{Span, MAX_MEMORY};
+
+/// An error of `compile_lang`, at a position of the source.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LangError {
+    pub message: &'static str,
+    pub span:    Span,
+}
+
+impl LangError {
+    // `line:col: error: message`, with the position resolved against `source`
+    pub fn describe(&self, source: &str) -> String {
+        let (line, col) = self.span.line_col(source);
+        format!("{}:{}: error: {}", line, col, self.message)
+    }
+}
+
+type Result<T> = std::result::Result<T, LangError>;
+
+fn error<T>(message: &'static str, span: Span) -> Result<T> {
+    Err(LangError { message, span })
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Tok {
+    Num(u8),
+    Ident(String),
+    Str(Vec<u8>),
+    Sym(&'static str),
+    Eof,
+}
+
+const SYMBOLS: [&str; 22] = [
+    "==", "!=", "<=", ">=", "&&", "||",
+    "+", "-", "*", "/", "%", "<", ">", "!", "=", "(", ")", "{", "}", ";", ",", "'",
+];
+
+fn lex(source: &str) -> Result<Vec<(Tok, Span)>> {
+    let bytes = source.as_bytes();
+    let mut tokens = Vec::new();
+    let mut pos = 0;
+    while pos < bytes.len() {
+        let start = pos;
+        let c = bytes[pos];
+        if c.is_ascii_whitespace() {
+            pos += 1;
+        } else if source[pos..].starts_with("//") {
+            pos = source[pos..].find('\n').map_or(bytes.len(), |len| pos + len);
+        } else if c.is_ascii_digit() {
+            while pos < bytes.len() && bytes[pos].is_ascii_digit() {
+                pos += 1;
+            }
+            match source[start..pos].parse::<u8>() {
+                Ok(n) => tokens.push((Tok::Num(n), Span::new(start, pos))),
+                Err(_) => return error("A number must be at most 255.", Span::new(start, pos)),
+            }
+        } else if c.is_ascii_alphabetic() || c == b'_' {
+            while pos < bytes.len() && (bytes[pos].is_ascii_alphanumeric() || bytes[pos] == b'_') {
+                pos += 1;
+            }
+            tokens.push((Tok::Ident(source[start..pos].to_string()), Span::new(start, pos)));
+        } else if c == b'"' || c == b'\'' {
+            // Strings, and characters which are numbers
+            let mut text = Vec::new();
+            pos += 1;
+            loop {
+                match bytes.get(pos) {
+                    None | Some(b'\n') => return error("The quote is never closed.", Span::new(start, pos)),
+                    Some(&q) if q == c => break,
+                    Some(b'\\') => {
+                        text.push(match bytes.get(pos + 1) {
+                            Some(b'n') => b'\n',
+                            Some(b't') => b'\t',
+                            Some(b'0') => 0,
+                            Some(&e @ (b'\\' | b'"' | b'\'')) => e,
+                            _ => return error("Unknown escape sequence.", Span::new(pos, pos + 2)),
+                        });
+                        pos += 2;
+                    },
+                    Some(&b) => {
+                        text.push(b);
+                        pos += 1;
+                    },
+                }
+            }
+            pos += 1;
+            let span = Span::new(start, pos);
+            match (c, text.as_slice()) {
+                (b'"', _) => tokens.push((Tok::Str(text), span)),
+                (_, &[n]) => tokens.push((Tok::Num(n), span)),
+                _ => return error("A character literal must be a single byte.", span),
+            }
+        } else {
+            let sym = SYMBOLS.iter().find(|sym| source[pos..].starts_with(*sym))
+                .ok_or(LangError { message: "Unexpected character.", span: Span::new(pos, pos + 1) })?;
+            pos += sym.len();
+            tokens.push((Tok::Sym(sym), Span::new(start, pos)));
+        }
+    }
+    tokens.push((Tok::Eof, Span::new(source.len(), source.len())));
+    Ok(tokens)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum BinOp {
+    Add, Sub, Mul, Div, Mod,
+    Eq, Ne, Lt, Gt, Le, Ge,
+    And, Or,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+    Num(u8),
+    Var(String, Span),
+    Cell(usize),  // Only made by the compiler itself
+    Not(Box<Expr>),
+    Neg(Box<Expr>),
+    Bin(BinOp, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Stmt {
+    Var(String, Span, Option<Expr>),
+    Assign(String, Span, Expr),
+    While(Span, Expr, Vec<Stmt>),  // Span of the keyword, where errors of the whole statement are reported
+    If(Span, Expr, Vec<Stmt>, Vec<Stmt>),
+    Print(Span, Vec<u8>),
+    PrintNum(Span, Expr),
+    Putc(Span, Expr),
+    Read(String, Span),
+}
+
+struct Parser {
+    tokens: Vec<(Tok, Span)>,
+    pos:    usize,
+}
+
+impl Parser {
+    fn peek(&self) -> &Tok {
+        &self.tokens[self.pos].0
+    }
+
+    fn span(&self) -> Span {
+        self.tokens[self.pos].1
+    }
+
+    fn next(&mut self) -> (Tok, Span) {
+        let token = self.tokens[self.pos].clone();
+        if token.0 != Tok::Eof {
+            self.pos += 1;
+        }
+        token
+    }
+
+    fn eat(&mut self, sym: &str) -> bool {
+        if *self.peek() == Tok::Sym(SYMBOLS.iter().find(|s| **s == sym).unwrap()) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, sym: &str, message: &'static str) -> Result<()> {
+        if self.eat(sym) { Ok(()) } else { error(message, self.span()) }
+    }
+
+    fn ident(&mut self) -> Result<(String, Span)> {
+        match self.next() {
+            (Tok::Ident(name), span) => Ok((name, span)),
+            (_, span) => error("Expected a variable name.", span),
+        }
+    }
+
+    fn block(&mut self) -> Result<Vec<Stmt>> {
+        self.expect("{", "Expected `{`.")?;
+        let mut stmts = Vec::new();
+        while !self.eat("}") {
+            if *self.peek() == Tok::Eof {
+                return error("The block is never closed.", self.span());
+            }
+            stmts.push(self.stmt()?);
+        }
+        Ok(stmts)
+    }
+
+    fn stmt(&mut self) -> Result<Stmt> {
+        let keyword = match self.peek() {
+            Tok::Ident(name) => name.clone(),
+            _ => return error("Expected a statement.", self.span()),
+        };
+        let keyword_span = self.span();
+        let stmt = match keyword.as_str() {
+            "var" => {
+                self.next();
+                let (name, span) = self.ident()?;
+                let value = if self.eat("=") { Some(self.expr()?) } else { None };
+                Stmt::Var(name, span, value)
+            },
+            "while" => {
+                self.next();
+                let cond = self.expr()?;
+                return Ok(Stmt::While(keyword_span, cond, self.block()?));
+            },
+            "if" => {
+                self.next();
+                let cond = self.expr()?;
+                let then = self.block()?;
+                let els = match self.peek() {
+                    Tok::Ident(name) if name == "else" => {
+                        self.next();
+                        match self.peek() {
+                            Tok::Ident(name) if name == "if" => vec![self.stmt()?],
+                            _ => self.block()?,
+                        }
+                    },
+                    _ => Vec::new(),
+                };
+                return Ok(Stmt::If(keyword_span, cond, then, els));
+            },
+            "print" => {
+                self.next();
+                match self.peek().clone() {
+                    Tok::Str(text) => {
+                        self.next();
+                        Stmt::Print(keyword_span, text)
+                    },
+                    _ => Stmt::PrintNum(keyword_span, self.expr()?),
+                }
+            },
+            "putc" => {
+                self.next();
+                Stmt::Putc(keyword_span, self.expr()?)
+            },
+            "read" => {
+                self.next();
+                let (name, span) = self.ident()?;
+                Stmt::Read(name, span)
+            },
+            _ => {
+                let (name, span) = self.ident()?;
+                self.expect("=", "Expected `=`.")?;
+                Stmt::Assign(name, span, self.expr()?)
+            },
+        };
+        self.expect(";", "Expected `;`.")?;
+        Ok(stmt)
+    }
+
+    fn expr(&mut self) -> Result<Expr> {
+        self.binary(0)
+    }
+
+    // Operators by increasing precedence
+    fn binary(&mut self, level: usize) -> Result<Expr> {
+        const LEVELS: [&[(&str, BinOp)]; 5] = [
+            &[("||", BinOp::Or)],
+            &[("&&", BinOp::And)],
+            &[("==", BinOp::Eq), ("!=", BinOp::Ne), ("<=", BinOp::Le), (">=", BinOp::Ge), ("<", BinOp::Lt), (">", BinOp::Gt)],
+            &[("+", BinOp::Add), ("-", BinOp::Sub)],
+            &[("*", BinOp::Mul), ("/", BinOp::Div), ("%", BinOp::Mod)],
+        ];
+        if level == LEVELS.len() {
+            return self.unary();
+        }
+        let mut lhs = self.binary(level + 1)?;
+        'outer: loop {
+            for &(sym, op) in LEVELS[level] {
+                if self.eat(sym) {
+                    let rhs = self.binary(level + 1)?;
+                    lhs = Expr::Bin(op, Box::new(lhs), Box::new(rhs));
+                    continue 'outer;
+                }
+            }
+            return Ok(lhs);
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expr> {
+        if self.eat("!") {
+            return Ok(Expr::Not(Box::new(self.unary()?)));
+        }
+        if self.eat("-") {
+            return Ok(Expr::Neg(Box::new(self.unary()?)));
+        }
+        match self.next() {
+            (Tok::Num(n), _) => Ok(Expr::Num(n)),
+            (Tok::Ident(name), span) => Ok(Expr::Var(name, span)),
+            (Tok::Sym("("), _) => {
+                let expr = self.expr()?;
+                self.expect(")", "Expected `)`.")?;
+                Ok(expr)
+            },
+            (_, span) => error("Expected an expression.", span),
+        }
+    }
+}
+
+// Emits Brainfuck while tracking the pointer. Cells above the variables form a stack of
+// temporaries, which are 0 whenever they are allocated or freed.
+struct Gen {
+    code:    String,
+    pointer: usize,
+    vars:    HashMap<String, usize>,
+    top:     usize,  // The first free cell
+}
+
+type Body<'a> = Box<dyn FnOnce(&mut Gen) -> Result<()> + 'a>;
+
+impl Gen {
+    fn alloc(&mut self, span: Span) -> Result<usize> {
+        if self.top >= MAX_MEMORY {
+            return error("The program needs more cells than the memory has.", span);
+        }
+        self.top += 1;
+        Ok(self.top - 1)
+    }
+
+    fn free(&mut self, cell: usize) {
+        debug_assert_eq!(cell + 1, self.top);
+        self.top -= 1;
+    }
+
+    fn var(&self, name: &str, span: Span) -> Result<usize> {
+        match self.vars.get(name) {
+            Some(&cell) => Ok(cell),
+            None => error("The variable is not declared.", span),
+        }
+    }
+
+    fn move_to(&mut self, cell: usize) {
+        let (c, n) = if cell > self.pointer { ('>', cell - self.pointer) } else { ('<', self.pointer - cell) };
+        self.code.extend(std::iter::repeat_n(c, n));
+        self.pointer = cell;
+    }
+
+    // Add `n` to `cell`, wrapping, by the shorter of `+` and `-`
+    fn add(&mut self, cell: usize, n: u8) {
+        self.move_to(cell);
+        if n <= 128 {
+            self.code.extend(std::iter::repeat_n('+', n as usize));
+        } else {
+            self.code.extend(std::iter::repeat_n('-', 256 - n as usize));
+        }
+    }
+
+    fn put(&mut self, cell: usize) {
+        self.move_to(cell);
+        self.code.push('.');
+    }
+
+    fn clear(&mut self, cell: usize) {
+        self.move_to(cell);
+        self.code.push_str("[-]");
+    }
+
+    // Run `body` while `cell` isn't 0. The body may move the pointer anywhere.
+    fn repeat<F: FnOnce(&mut Gen) -> Result<()>>(&mut self, cell: usize, body: F) -> Result<()> {
+        self.move_to(cell);
+        self.code.push('[');
+        body(self)?;
+        self.move_to(cell);
+        self.code.push(']');
+        Ok(())
+    }
+
+    // Empty `src` into each target, adding (`true`) or subtracting (`false`)
+    fn drain(&mut self, src: usize, targets: &[(usize, bool)]) -> Result<()> {
+        self.repeat(src, |g| {
+            g.add(src, 255);
+            for &(cell, plus) in targets {
+                g.add(cell, if plus { 1 } else { 255 });
+            }
+            Ok(())
+        })
+    }
+
+    // Add a copy of `src` to `dst`
+    fn copy(&mut self, src: usize, dst: usize, span: Span) -> Result<()> {
+        let tmp = self.alloc(span)?;
+        self.drain(src, &[(dst, true), (tmp, true)])?;
+        self.drain(tmp, &[(src, true)])?;
+        self.free(tmp);
+        Ok(())
+    }
+
+    // Replace the value of the temporary `cell` with 1 if whether it isn't 0 is `nonzero`, else 0
+    fn test(&mut self, cell: usize, nonzero: bool, span: Span) -> Result<()> {
+        let flag = self.alloc(span)?;
+        if !nonzero {
+            self.add(flag, 1);
+        }
+        self.repeat(cell, |g| {
+            g.clear(cell);
+            g.add(flag, if nonzero { 1 } else { 255 });
+            Ok(())
+        })?;
+        self.drain(flag, &[(cell, true)])?;
+        self.free(flag);
+        Ok(())
+    }
+
+    // Run `then` if the temporary `cond` isn't 0, else `els`. `cond` is 0 afterwards.
+    fn branch(&mut self, cond: usize, then: Body, els: Body, span: Span) -> Result<()> {
+        let other = self.alloc(span)?;
+        self.add(other, 1);
+        self.repeat(cond, |g| {
+            g.clear(cond);
+            g.add(other, 255);
+            then(g)
+        })?;
+        self.repeat(other, |g| {
+            g.add(other, 255);
+            els(g)
+        })?;
+        self.free(other);
+        Ok(())
+    }
+
+    fn while_loop(&mut self, cond: &Expr, body: Body, span: Span) -> Result<()> {
+        let flag = self.eval(cond, span)?;
+        self.repeat(flag, |g| {
+            g.clear(flag);
+            body(g)?;
+            let next = g.eval(cond, span)?;
+            g.drain(next, &[(flag, true)])?;
+            g.free(next);
+            Ok(())
+        })?;
+        self.free(flag);
+        Ok(())
+    }
+
+    // Whether `x < y`, as a new temporary. `x` and `y` are decremented together, and
+    // `y` ends up 0.
+    fn less(&mut self, x: usize, y: usize, span: Span) -> Result<usize> {
+        let result = self.alloc(span)?;
+        self.repeat(y, |g| {
+            let nonzero = g.alloc(span)?;
+            g.copy(x, nonzero, span)?;
+            g.branch(nonzero, Box::new(move |g: &mut Gen| {
+                g.add(x, 255);
+                Ok(())
+            }), Box::new(move |g: &mut Gen| {
+                // `x` ran out first
+                g.add(result, 1);
+                g.clear(y);
+                g.add(y, 1);
+                Ok(())
+            }), span)?;
+            g.free(nonzero);
+            g.add(y, 255);
+            Ok(())
+        })?;
+        Ok(result)
+    }
+
+    // Leave `a / b` (or `a % b`) in `a`, and free `b`. Division by 0 gives 0.
+    fn divide(&mut self, a: usize, b: usize, remainder: bool, span: Span) -> Result<()> {
+        let quotient = self.alloc(span)?;
+        let divisor = self.alloc(span)?;
+        self.copy(b, divisor, span)?;
+        self.branch(divisor, Box::new(move |g: &mut Gen| {
+            let cond = Expr::Bin(BinOp::Ge, Box::new(Expr::Cell(a)), Box::new(Expr::Cell(b)));
+            g.while_loop(&cond, Box::new(move |g: &mut Gen| {
+                let tmp = g.alloc(span)?;
+                g.copy(b, tmp, span)?;
+                g.drain(tmp, &[(a, false)])?;
+                g.free(tmp);
+                g.add(quotient, 1);
+                Ok(())
+            }), span)
+        }), Box::new(move |g: &mut Gen| {
+            g.clear(a);
+            Ok(())
+        }), span)?;
+        self.free(divisor);
+        if !remainder {
+            self.clear(a);
+            self.drain(quotient, &[(a, true)])?;
+        }
+        self.clear(quotient);
+        self.free(quotient);
+        self.clear(b);
+        self.free(b);
+        Ok(())
+    }
+
+    // Evaluate `expr` into a new temporary
+    fn eval(&mut self, expr: &Expr, span: Span) -> Result<usize> {
+        let span = match expr {
+            Expr::Var(_, span) => *span,
+            _ => span,
+        };
+        match expr {
+            Expr::Num(n) => {
+                let cell = self.alloc(span)?;
+                self.add(cell, *n);
+                Ok(cell)
+            },
+            Expr::Var(name, _) => {
+                let var = self.var(name, span)?;
+                let cell = self.alloc(span)?;
+                self.copy(var, cell, span)?;
+                Ok(cell)
+            },
+            Expr::Cell(src) => {
+                let cell = self.alloc(span)?;
+                self.copy(*src, cell, span)?;
+                Ok(cell)
+            },
+            Expr::Not(inner) => {
+                let cell = self.eval(inner, span)?;
+                self.test(cell, false, span)?;
+                Ok(cell)
+            },
+            Expr::Neg(inner) => {
+                let cell = self.eval(inner, span)?;
+                // `neg` counts down from 0 while `cell` counts down to 0, then is moved back
+                let neg = self.alloc(span)?;
+                self.drain(cell, &[(neg, false)])?;
+                self.drain(neg, &[(cell, true)])?;
+                self.free(neg);
+                Ok(cell)
+            },
+            Expr::Bin(op, lhs, rhs) => {
+                let a = self.eval(lhs, span)?;
+                let b = self.eval(rhs, span)?;
+                match op {
+                    BinOp::Add => self.drain(b, &[(a, true)])?,
+                    BinOp::Sub => self.drain(b, &[(a, false)])?,
+                    BinOp::Mul => {
+                        let product = self.alloc(span)?;
+                        self.repeat(a, |g| {
+                            g.add(a, 255);
+                            g.copy(b, product, span)
+                        })?;
+                        self.clear(b);
+                        self.drain(product, &[(a, true)])?;
+                        self.free(product);
+                    },
+                    BinOp::Div | BinOp::Mod => {
+                        self.divide(a, b, *op == BinOp::Mod, span)?;
+                        return Ok(a);
+                    },
+                    BinOp::Eq | BinOp::Ne => {
+                        self.drain(b, &[(a, false)])?;
+                        self.test(a, *op == BinOp::Ne, span)?;
+                    },
+                    BinOp::Lt | BinOp::Gt | BinOp::Le | BinOp::Ge => {
+                        // `a > b` is `b < a`, and `a <= b` is `!(b < a)`
+                        let swap = matches!(op, BinOp::Gt | BinOp::Le);
+                        let result = if swap { self.less(b, a, span)? } else { self.less(a, b, span)? };
+                        self.clear(a);
+                        self.clear(b);
+                        self.drain(result, &[(a, true)])?;
+                        self.free(result);
+                        if matches!(op, BinOp::Le | BinOp::Ge) {
+                            self.free(b);
+                            self.test(a, false, span)?;
+                            return Ok(a);
+                        }
+                    },
+                    BinOp::And => {
+                        let result = self.alloc(span)?;
+                        self.repeat(a, |g| {
+                            g.clear(a);
+                            g.repeat(b, |g| {
+                                g.clear(b);
+                                g.add(result, 1);
+                                Ok(())
+                            })
+                        })?;
+                        self.clear(b);
+                        self.drain(result, &[(a, true)])?;
+                        self.free(result);
+                    },
+                    BinOp::Or => {
+                        self.test(a, true, span)?;
+                        self.test(b, true, span)?;
+                        self.drain(b, &[(a, true)])?;
+                        self.test(a, true, span)?;
+                    },
+                }
+                self.free(b);
+                Ok(a)
+            },
+        }
+    }
+
+    fn print_number(&mut self, expr: &Expr, span: Span) -> Result<()> {
+        let n = self.eval(expr, span)?;
+        let bin = |op, lhs, rhs| Expr::Bin(op, Box::new(lhs), Box::new(rhs));
+        let hundreds = self.eval(&bin(BinOp::Div, Expr::Cell(n), Expr::Num(100)), span)?;
+        let tens = self.eval(&bin(BinOp::Mod, bin(BinOp::Div, Expr::Cell(n), Expr::Num(10)), Expr::Num(10)), span)?;
+        let ones = self.eval(&bin(BinOp::Mod, Expr::Cell(n), Expr::Num(10)), span)?;
+        let print_digit = move |g: &mut Gen, cell: usize| {
+            g.add(cell, b'0');
+            g.put(cell);
+            g.add(cell, 0u8.wrapping_sub(b'0'));
+        };
+        // Leading zeros are left out
+        let cond = self.eval(&Expr::Cell(hundreds), span)?;
+        self.branch(cond, Box::new(move |g: &mut Gen| {
+            print_digit(g, hundreds);
+            Ok(())
+        }), Box::new(|_: &mut Gen| Ok(())), span)?;
+        self.free(cond);
+        let cond = self.eval(&bin(BinOp::Or, Expr::Cell(hundreds), Expr::Cell(tens)), span)?;
+        self.branch(cond, Box::new(move |g: &mut Gen| {
+            print_digit(g, tens);
+            Ok(())
+        }), Box::new(|_: &mut Gen| Ok(())), span)?;
+        self.free(cond);
+        print_digit(self, ones);
+        for cell in [ones, tens, hundreds, n] {
+            self.clear(cell);
+            self.free(cell);
+        }
+        Ok(())
+    }
+
+    fn assign(&mut self, var: usize, expr: &Expr, span: Span) -> Result<()> {
+        let value = self.eval(expr, span)?;
+        self.clear(var);
+        self.drain(value, &[(var, true)])?;
+        self.free(value);
+        Ok(())
+    }
+
+    fn stmts(&mut self, stmts: &[Stmt]) -> Result<()> {
+        for stmt in stmts {
+            self.stmt(stmt)?;
+        }
+        Ok(())
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) -> Result<()> {
+        match stmt {
+            Stmt::Var(name, span, value) => {
+                let var = self.var(name, *span)?;
+                match value {
+                    Some(value) => self.assign(var, value, *span),
+                    None => {
+                        self.clear(var);
+                        Ok(())
+                    },
+                }
+            },
+            Stmt::Assign(name, span, value) => {
+                let var = self.var(name, *span)?;
+                self.assign(var, value, *span)
+            },
+            Stmt::While(span, cond, body) => self.while_loop(cond, Box::new(move |g: &mut Gen| g.stmts(body)), *span),
+            Stmt::If(span, cond, then, els) => {
+                let cond = self.eval(cond, *span)?;
+                self.branch(cond, Box::new(move |g: &mut Gen| g.stmts(then)), Box::new(move |g: &mut Gen| g.stmts(els)), *span)?;
+                self.free(cond);
+                Ok(())
+            },
+            Stmt::Print(span, text) => {
+                let cell = self.alloc(*span)?;
+                let mut value = 0u8;
+                for &byte in text {
+                    self.add(cell, byte.wrapping_sub(value));
+                    self.put(cell);
+                    value = byte;
+                }
+                self.clear(cell);
+                self.free(cell);
+                Ok(())
+            },
+            Stmt::PrintNum(span, expr) => self.print_number(expr, *span),
+            Stmt::Putc(span, expr) => {
+                let cell = self.eval(expr, *span)?;
+                self.put(cell);
+                self.clear(cell);
+                self.free(cell);
+                Ok(())
+            },
+            Stmt::Read(name, span) => {
+                let var = self.var(name, *span)?;
+                self.move_to(var);
+                self.code.push(',');
+                Ok(())
+            },
+        }
+    }
+}
+
+// Give every variable its cell, in the order of the declarations
+fn declare(stmts: &[Stmt], vars: &mut HashMap<String, usize>) -> Result<()> {
+    for stmt in stmts {
+        match stmt {
+            Stmt::Var(name, span, _) => {
+                if vars.contains_key(name) {
+                    return error("The variable is already declared.", *span);
+                }
+                let cell = vars.len();
+                vars.insert(name.clone(), cell);
+            },
+            Stmt::While(_, _, body) => declare(body, vars)?,
+            Stmt::If(_, _, then, els) => {
+                declare(then, vars)?;
+                declare(els, vars)?;
+            },
+            _ => (),
+        }
+    }
+    Ok(())
+}
+
+/// Compile a program of a small structured language into Brainfuck.
+///
+/// ```text
+/// // Comments run to the end of the line
+/// var n = 10;                      // Variables are global cells holding 0..=255
+/// while n > 0 {
+///     print n; print "\n";         // A number in decimal, or a string
+///     n = n - 1;
+/// }
+/// if n == 0 && !(n != 0) { putc 'A'; } else { read n; }
+/// ```
+///
+/// Expressions have `+ - * / %` (wrapping, division by 0 gives 0),
+/// comparisons, `&& || !` and unary `-`; their values are 0 or 1.
+///
+/// Cell allocation: variables take cells `0..n` in the order of their
+/// declarations, wherever they are in the program. The cells above them are a
+/// stack of temporaries, each 0 whenever it is allocated or freed, so the
+/// program never depends on cells being cleared. Every generated loop returns
+/// the pointer to its own cell, so the position of the pointer is known at
+/// every point of the program and moves are emitted as plain `<` and `>`.
+pub fn compile_lang(source: &str) -> std::result::Result<String, LangError> {
+    let mut parser = Parser { tokens: lex(source)?, pos: 0 };
+    let mut stmts = Vec::new();
+    while *parser.peek() != Tok::Eof {
+        stmts.push(parser.stmt()?);
+    }
+    let mut vars = HashMap::new();
+    declare(&stmts, &mut vars)?;
+    let mut gen = Gen { code: String::new(), pointer: 0, top: vars.len(), vars };
+    gen.stmts(&stmts)?;
+    Ok(gen.code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allocates_variables_in_order() {
+        // Both values are built in the first temporary, cell 2
+        assert_eq!(compile_lang("var a = 2; var b = 1;").unwrap(), ">>++<<[-]>>[-<<+>>]+<[-]>[-<+>]");
+    }
+
+    #[test]
+    fn errors_have_positions() {
+        let source = "var a;\nb = 1;";
+        assert_eq!(compile_lang(source).unwrap_err().describe(source), "2:1: error: The variable is not declared.");
+        assert_eq!(compile_lang("var a = 256;").unwrap_err().message, "A number must be at most 255.");
+        assert_eq!(compile_lang("var a; var a;").unwrap_err().message, "The variable is already declared.");
+        assert_eq!(compile_lang("while 1 { print 1;").unwrap_err().message, "The block is never closed.");
+        // Every cell is taken by a variable, so the string has none left
+        let mut source: String = (0..MAX_MEMORY).map(|n| format!("var v{};", n)).collect();
+        source.push_str("\n  print \"x\";");
+        assert_eq!(compile_lang(&source).unwrap_err().describe(&source), "2:3: error: The program needs more cells than the memory has.");
+    }
+}
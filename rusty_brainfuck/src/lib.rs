@@ -1,8 +1,49 @@
 use std::iter::Peekable;
-use std::str::Chars;
+use std::vec::IntoIter;
 use std::ops::DerefMut;
+use std::mem;
+use std::collections::{BTreeMap, BTreeSet};
+
+mod backend;
+mod decompile;
+mod dialect;
+mod ffi;
+mod fixture;
+mod formatter;
+mod generate;
+mod history;
+mod lang;
+mod lint;
+mod minify;
+mod observer;
+mod preprocess;
+mod profile;
+#[cfg(feature = "python")]
+mod python;
+mod thread;
+mod trace;
 
 use Inst::*;
+use dialect::Token;
+use history::{History, SavedThreads, Snapshot, Segment, Undo};
+use thread::{Scheduler, Thread};
+
+pub use backend::{
+    compile, compile_with_dialect, decode_bytecode, decode_bytecode_with_tape_size, is_bytecode, CompileConfig, Target,
+};
+pub use decompile::{decompile, Pseudocode, PseudoLine};
+pub use dialect::{Dialect, Extension, TranslateError};
+pub use fixture::{discover, Fixture, FixtureConfig, FixtureError, Outcome, Status, TestReport};
+pub use formatter::{format_program, FormatConfig};
+pub use generate::{generate, GenerateConfig, Tradeoff};
+pub use history::HistoryConfig;
+pub use lang::{compile_lang, LangError};
+pub use lint::{lint, lint_with_dialect, Lint, LintKind, Severity};
+pub use minify::{minify, minify_with_tape_size};
+pub use observer::{Observer, StepEvent};
+pub use preprocess::{Preprocessor, Expansion, SourceMap, Origin, PreprocessError};
+pub use profile::{Profiler, Profile, InstProfile, LoopProfile};
+pub use trace::{Tracer, TraceFormat, TraceFilter};
 
 type ProgramString = String;
 type ResultString  = String;
@@ -10,10 +51,19 @@ type InputString   = String;
 
 const MAX_MEMORY: usize = 30_000;
 
+/// The error of a step which moves the pointer left of the first cell.
+pub const ERROR_POINTER_LEFT: &str = "Too small pointer than the first address of memory.";
+/// The error of a step which moves the pointer right of the last cell.
+pub const ERROR_POINTER_RIGHT: &str = "Too large pointer than the size of memory.";
+/// The error of a step taken once the fuel is used up.
+pub const ERROR_OUT_OF_FUEL: &str = "Out of fuel.";
+const MAX_CALL_DEPTH: usize = 10_000;
+const DEFAULT_QUANTUM: usize = 1;
+
 #[derive(Debug, Clone, Copy, PartialEq)]
-enum Inst {
-    Add(u8),       // Add a number to the current address
-    Sub(u8),       // Sub a number to the current address
+pub enum Inst {
+    Add(u8),       // Add a number to the current address; longer runs are split
+    Sub(u8),       // Sub a number to the current address; longer runs are split
     Shr(usize),    // Shift the pointer to the right
     Shl(usize),    // Shift the pointer to the left
     Jpf(usize),    // Jump forward (to the closing bracket)
@@ -21,176 +71,246 @@ enum Inst {
     Wrt,           // Display the current pointee
     Red,           // Get a input, and put the input to the current address
     Stz,           // Store 0 to the current address
+    Def(usize),    // Define the procedure named by the current address, and skip its body
+    Ret,           // Return from a procedure
+    Cal,           // Call the procedure named by the current address
+    Frk,           // Fork a thread, which starts on the next address
+    End,           // End the program
+    Sav,           // Copy the current address into the storage
+    Lod,           // Copy the storage into the current address
+    Rsh(usize),    // Shift the bits of the current address to the right
+    Lsh(usize),    // Shift the bits of the current address to the left
+    Not,           // Invert the bits of the current address
+    Xor,           // Xor the storage into the current address
+    And,           // And the storage into the current address
+    Orr,           // Or the storage into the current address
+}
+
+// The byte range of the source code from which an instruction was generated
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Span {
+    pub start: usize,
+    pub end:   usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Span { start, end }
+    }
+
+    // 1-based line and column of the start of the span
+    pub fn line_col(&self, source: &str) -> (usize, usize) {
+        let before = &source[..self.start.min(source.len())];
+        let line = before.matches('\n').count() + 1;
+        let col = before.rsplit('\n').next().map_or(0, |l| l.chars().count()) + 1;
+        (line, col)
+    }
+}
+
+fn is_command(c: char) -> bool {
+    matches!(c, '+' | '-' | '>' | '<' | '[' | ']' | '.' | ',')
+}
+
+/// The optimizations applied while generating instructions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Passes {
+    pub fold_runs:   bool,  // `+++` becomes one `Add(3)`, and so on for `-`, `>`, `<`, `}` and `{`
+    pub clear_loops: bool,  // `[-]` becomes `Stz`
+}
+
+impl Passes {
+    // One instruction per command
+    pub fn none() -> Self {
+        Passes { fold_runs: false, clear_loops: false }
+    }
+}
+
+impl Default for Passes {
+    fn default() -> Self {
+        Passes { fold_runs: true, clear_loops: true }
+    }
 }
 
-struct CodeGen<'a> {
-    chars: Box<Peekable<Chars<'a>>>,
+struct CodeGen {
+    tokens: Box<Peekable<IntoIter<Token>>>,
+    spans:  Vec<Span>,
+    passes: Passes,
 }
 
-impl<'a> CodeGen<'a> {
-    pub fn new(program: &'a str) -> CodeGen<'a> {
-        CodeGen { chars: Box::new(program.chars().peekable()) }
+impl CodeGen {
+    pub fn new(program: &str) -> CodeGen {
+        CodeGen::with_dialect(program, &Dialect::brainfuck())
+    }
+
+    pub fn with_dialect(program: &str, dialect: &Dialect) -> CodeGen {
+        CodeGen {
+            tokens: Box::new(dialect.tokenize(program).into_iter().peekable()),
+            spans:  Vec::new(),
+            passes: Passes::default(),
+        }
+    }
+
+    pub fn with_passes(mut self, passes: Passes) -> CodeGen {
+        self.passes = passes;
+        self
+    }
+
+    // Consume the run of `ch` following the first one, up to `max` commands in all, and return
+    // its length and end offset.
+    fn take_run(tokens: &mut Peekable<IntoIter<Token>>, ch: char, end: usize, max: usize) -> (usize, usize) {
+        let mut len = 1;
+        let mut end = end;
+        while let Some(&token) = tokens.peek() {
+            if token.command != ch || len == max {
+                break;
+            }
+            tokens.next();
+            len += 1;
+            end = token.span.end;
+        }
+        (len, end)
     }
 
     pub fn generate_insts(&mut self) -> Result<Vec<Inst>, &'static str> {
-        let chars = self.chars.deref_mut();
+        let tokens = self.tokens.deref_mut();
         let mut result = Vec::new();
+        let mut spans = Vec::new();
 
-        let mut pos: usize = 0;
+        while let Some(token) = tokens.next() {
+            let Span { start, end } = token.span;
+            let (inst, end) = match token.command {
+                ch @ ('+' | '-' | '>' | '<' | '}' | '{') => {
+                    // `Add` and `Sub` take at most 255, which wider cells don't wrap around at
+                    let max = if ch == '+' || ch == '-' { u8::MAX as usize } else { usize::MAX };
+                    let (len, end) = if self.passes.fold_runs { CodeGen::take_run(tokens, ch, end, max) } else { (1, end) };
+                    let inst = match ch {
+                        '+' => Add(len as u8),
+                        '-' => Sub(len as u8),
+                        '>' => Shr(len),
+                        '}' => Rsh(len),
+                        '{' => Lsh(len),
+                        _   => Shl(len),
+                    };
+                    (inst, end)
+                },
+                '[' => (Jpf(0), end),
+                ']' => (Jpb(0), end),
+                '.' => (Wrt, end),
+                ',' => (Red, end),
+                '(' => (Def(0), end),
+                ')' => (Ret, end),
+                ':' => (Cal, end),
+                'Y' => (Frk, end),
+                '@' => (End, end),
+                '$' => (Sav, end),
+                '!' => (Lod, end),
+                '~' => (Not, end),
+                '^' => (Xor, end),
+                '&' => (And, end),
+                '|' => (Orr, end),
+                _   => return Err("Unknown command."),
+            };
+            result.push(inst);
+            spans.push(Span::new(start, end));
+        }
 
-        'outer: loop {
-            {
-                let ch = chars.peek();
-                if ch.is_none() {
-                    break 'outer;
-                }
+        // `[-]` is replaced with `Stz`
+        let mut temp_v = Vec::new();
+        let mut temp_spans = Vec::new();
+        let mut idx = 0;
+        while idx < result.len() {
+            if self.passes.clear_loops && result[idx..].starts_with(&[Jpf(0), Sub(1), Jpb(0)]) {
+                temp_v.push(Stz);
+                temp_spans.push(Span::new(spans[idx].start, spans[idx + 2].end));
+                idx += 3;
+            } else {
+                temp_v.push(result[idx]);
+                temp_spans.push(spans[idx]);
+                idx += 1;
             }
+        }
+        result = temp_v;
+        self.spans = temp_spans;
 
-            match chars.next().unwrap() {
-                '+' => {
-                    let start = pos;
-                    'add: loop {
-                        let ch = match chars.peek() {
-                            Some(ch) => *ch,
-                            None => {
-                                result.push(Add((pos - start + 1) as u8));
-                                break 'outer
-                            }
-                        };
-
-                        if ch != '+' {
-                            result.push(Add((pos - start + 1) as u8));
-                            break 'add;
-                        }
-
-                        chars.next();
-                        pos += 1;
-                    }
+        // Loops and procedure bodies nest, but may not cross each other
+        let mut opens = Vec::new();
+        for counter in 0..result.len() {
+            match result[counter] {
+                Jpf(_) | Def(_) => opens.push(counter),
+                Jpb(_) => match opens.pop().map(|open| (open, result[open])) {
+                    Some((open, Jpf(_))) => {
+                        result[open] = Jpf(counter);
+                        result[counter] = Jpb(open);
+                    },
+                    _ => return Err("Jumptable can't be constructed."),
                 },
-                '-' => {
-                    let start = pos;
-                    'sub: loop {
-                        let ch = match chars.peek() {
-                            Some(ch) => *ch,
-                            None => {
-                                result.push(Sub((pos - start + 1) as u8));
-                                break 'outer
-                            }
-                        };
-
-                        if ch != '-' {
-                            result.push(Sub((pos - start + 1) as u8));
-                            break 'sub;
-                        }
-
-                        chars.next();
-                        pos += 1;
-                    }
+                Ret => match opens.pop().map(|open| (open, result[open])) {
+                    Some((open, Def(_))) => result[open] = Def(counter),
+                    _ => return Err("Procedure can't be constructed."),
                 },
-                '>' => {
-                    let start = pos;
-                    'shr: loop {
-                        let ch = match chars.peek() {
-                            Some(ch) => *ch,
-                            None => {
-                                result.push(Shr(pos - start + 1));
-                                break 'outer
-                            }
-                        };
-
-                        if ch != '>' {
-                            result.push(Shr(pos - start + 1));
-                            break 'shr;
-                        }
-
-                        chars.next();
-                        pos += 1;
-                    }
-                },
-                '<' => {
-                    let start = pos;
-                    'shl: loop {
-                        let ch = match chars.peek() {
-                            Some(ch) => *ch,
-                            None => {
-                                result.push(Shl(pos - start + 1));
-                                break 'outer
-                            }
-                        };
-
-                        if ch != '<' {
-                            result.push(Shl(pos - start + 1));
-                            break 'shl;
-                        }
-
-                        chars.next();
-                        pos += 1;
-                    }
-                },
-                '[' => result.push(Jpf(0)),
-                ']' => result.push(Jpb(0)),
-                '.' => result.push(Wrt),
-                ',' => result.push(Red),
                 _ => (),
             }
         }
+        if let Some(&open) = opens.last() {
+            return match result[open] {
+                Def(_) => Err("Procedure can't be constructed."),
+                _ => Err("Jumptable can't be constructed."),
+            };
+        }
 
-        if result.len() > 3 {
-            let mut temp_v = Vec::new();
-            let mut iter3w = result.windows(3).peekable();
-            let mut pushed_num = 0;
-
-            while let Some(three) = iter3w.next() {
-                if three == &[Jpf(0), Sub(1), Jpb(0)] {
-                    temp_v.push(Stz);
-                    pushed_num = 2;
-                } else {
-                    if pushed_num > 0 {
-                        pushed_num -= 1;
-                    } else {
-                        temp_v.push(three[0]);
-                    }
-                }
-                if let Some(_) = iter3w.peek() {
-                    continue;
-                } else {
-                    if pushed_num == 1 {
-                        temp_v.push(three[2]);
-                    } else if pushed_num != 2 {
-                        temp_v.push(three[1]);
-                        temp_v.push(three[2]);
-                    }
-                }
-            }
+        Ok(result)
+    }
+}
 
-            result = temp_v;
+// What `,` stores when the input has ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EofPolicy {
+    Zero,
+    MinusOne,
+    Unchanged,
+}
+
+impl EofPolicy {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "zero" | "0" => Some(EofPolicy::Zero),
+            "minus-one" | "-1" => Some(EofPolicy::MinusOne),
+            "unchanged" => Some(EofPolicy::Unchanged),
+            _ => None,
         }
+    }
+}
 
-        for counter in 0..result.len() {
-            let inst = result[counter];
-            if let Jpf(_) = inst {
-                let mut staple = 1;
-                let mut seek = counter;
-                while staple != 0 && seek < result.len() {
-                    seek += 1;
-                    let inst2 = result[seek];
-                    match inst2 {
-                        Jpb(_) => { staple -= 1; },
-                        Jpf(_) => { staple += 1; },
-                         _  => (),
-                    }
-                }
-                if staple == 0 {
-                    result[counter] = Jpf(seek);
-                    result[seek] = Jpb(counter);
-                } else {
-                    // println!("{:?}", jumptable);
-                    return Err("Jumptable can't be constructed.");
-                }
-            }
+/// The number of bits of a cell. Cells wrap around past the largest value, and `,` stores
+/// it at the end of input with `EofPolicy::MinusOne`. `.` writes the lowest 8 bits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CellWidth {
+    Bits8,
+    Bits16,
+    Bits32,
+}
+
+impl CellWidth {
+    pub fn from_bits(bits: u32) -> Option<Self> {
+        match bits {
+            8 => Some(CellWidth::Bits8),
+            16 => Some(CellWidth::Bits16),
+            32 => Some(CellWidth::Bits32),
+            _ => None,
         }
+    }
 
-        Ok(result)
+    pub fn bits(&self) -> u32 {
+        match self {
+            CellWidth::Bits8 => 8,
+            CellWidth::Bits16 => 16,
+            CellWidth::Bits32 => 32,
+        }
+    }
+
+    // The largest value of a cell, with every bit set
+    pub fn max(&self) -> u32 {
+        u32::MAX >> (32 - self.bits())
     }
 }
 
@@ -198,12 +318,12 @@ impl<'a> CodeGen<'a> {
 struct Pointer(usize);
 
 impl Pointer {
-    fn shift_right_n(&mut self, n: usize) -> Result<(), &'static str> {
-        if self.0 + n < MAX_MEMORY {
+    fn shift_right_n(&mut self, n: usize, size: usize) -> Result<(), &'static str> {
+        if self.0 + n < size {
             self.0 += n;
             Ok(())
         } else {
-            Err("Too large pointer than the size of memory.")
+            Err(ERROR_POINTER_RIGHT)
         }
     }
 
@@ -212,7 +332,7 @@ impl Pointer {
             self.0 -= n;
             Ok(())
         } else {
-            Err("Too small pointer than the first address of memory.")
+            Err(ERROR_POINTER_LEFT)
         }
     }
 }
@@ -273,57 +393,237 @@ impl Counter {
     }
 }
 
-pub struct Brainfuck {
+pub struct Brainfuck<O = ()> {
     insts:       Vec<Inst>,
+    spans:       Vec<Span>,
+    source_len:  usize,                 // Where the spans of code added by `extend` start
     result:      ResultString,
-    memory:      Vec<u8>,
+    emitted:     usize,                 // Characters output so far, less those stepped back over
+    popped:      usize,                 // Characters taken by `pop_result`, which are never output again
+    memory:      Vec<u32>,
+    cell_width:  CellWidth,
     pointer:     Pointer,
     counter:     Counter,
     input_queue: InputString,
     input_mode:  bool,
+    history:     Option<History>,
+    breakpoints: BTreeSet<usize>,
+    procedures:  BTreeMap<u32, usize>,  // Name of a procedure and the counter of its definition
+    call_stack:  Vec<usize>,            // Counters of the calls to return to
+    scheduler:   Scheduler,
+    fuel:        Option<u64>,
+    storage:     u32,
+    dialect:     Dialect,
+    passes:      Passes,
+    observer:    O,
 }
 
 impl Brainfuck {
     pub fn new(program: ProgramString) -> Result<Self, &'static str> {
-        let mut program = program;
-        Brainfuck::serialize(&mut program);
-        let mut codegen = CodeGen::new(&program);
+        Brainfuck::with_dialect(program, &Dialect::brainfuck())
+    }
+
+    // Parse `program` written in `dialect`. Spans point into the dialect source.
+    pub fn with_dialect(program: ProgramString, dialect: &Dialect) -> Result<Self, &'static str> {
+        Brainfuck::with_passes(program, dialect, Passes::default())
+    }
+
+    // Parse `program` with only some of the optimizations, mostly to measure what they are worth
+    pub fn with_passes(program: ProgramString, dialect: &Dialect, passes: Passes) -> Result<Self, &'static str> {
+        let mut codegen = CodeGen::with_dialect(&program, dialect).with_passes(passes);
         let insts = codegen.generate_insts()?;
         let insts_len = insts.len();
         Ok(Brainfuck {
             insts,
+            spans:       codegen.spans,
+            source_len:  program.len(),
             result:      ResultString::new(),
+            emitted:     0,
+            popped:      0,
             memory:      vec![0; MAX_MEMORY],
+            cell_width:  CellWidth::Bits8,
             pointer:     0.into(),
             counter:     Counter::new(0, insts_len),
             input_queue: InputString::new(),
             input_mode:  false,
+            history:     None,
+            breakpoints: BTreeSet::new(),
+            procedures:  BTreeMap::new(),
+            call_stack:  Vec::new(),
+            scheduler:   Scheduler::new(DEFAULT_QUANTUM),
+            fuel:        None,
+            storage:     0,
+            dialect:     dialect.clone(),
+            passes,
+            observer:    (),
         })
     }
 
-    fn serialize(program: &mut ProgramString) {
-        program.retain(|c| c == '+' || c == '-' || c == '>' || c == '<' ||
-                       c == '[' || c == ']' || c == '.' || c == ',');
+    // Strip everything except the eight commands from the program
+    pub fn serialize(program: &mut ProgramString) {
+        program.retain(is_command);
+    }
+
+    // Strip everything except the tokens of `dialect`, including those of its extensions
+    pub fn serialize_with_dialect(program: &mut ProgramString, dialect: &Dialect) {
+        // A dialect has a token for every command it reads
+        *program = dialect.translate(program, dialect).unwrap();
+    }
+}
+
+impl<O: Observer> Brainfuck<O> {
+    // Attach an observer which is notified of the events of every following step.
+    pub fn with_observer<P: Observer>(self, observer: P) -> Brainfuck<P> {
+        Brainfuck {
+            insts:       self.insts,
+            spans:       self.spans,
+            source_len:  self.source_len,
+            result:      self.result,
+            emitted:     self.emitted,
+            popped:      self.popped,
+            memory:      self.memory,
+            cell_width:  self.cell_width,
+            pointer:     self.pointer,
+            counter:     self.counter,
+            input_queue: self.input_queue,
+            input_mode:  self.input_mode,
+            history:     self.history,
+            breakpoints: self.breakpoints,
+            procedures:  self.procedures,
+            call_stack:  self.call_stack,
+            scheduler:   self.scheduler,
+            fuel:        self.fuel,
+            storage:     self.storage,
+            dialect:     self.dialect,
+            passes:      self.passes,
+            observer,
+        }
+    }
+
+    pub fn detach_observer(self) -> (Brainfuck, O) {
+        let observer = self.observer;
+        let bf = Brainfuck {
+            insts:       self.insts,
+            spans:       self.spans,
+            source_len:  self.source_len,
+            result:      self.result,
+            emitted:     self.emitted,
+            popped:      self.popped,
+            memory:      self.memory,
+            cell_width:  self.cell_width,
+            pointer:     self.pointer,
+            counter:     self.counter,
+            input_queue: self.input_queue,
+            input_mode:  self.input_mode,
+            history:     self.history,
+            breakpoints: self.breakpoints,
+            procedures:  self.procedures,
+            call_stack:  self.call_stack,
+            scheduler:   self.scheduler,
+            fuel:        self.fuel,
+            storage:     self.storage,
+            dialect:     self.dialect,
+            passes:      self.passes,
+            observer:    (),
+        };
+        (bf, observer)
     }
 
+    pub fn observer(&self) -> &O {
+        &self.observer
+    }
+
+    pub fn observer_mut(&mut self) -> &mut O {
+        &mut self.observer
+    }
+
+    // Load another program written in the same dialect, and reset the machine
     pub fn initialize(&mut self, program: ProgramString) -> Result<(), &'static str> {
-        let mut program = program;
-        Brainfuck::serialize(&mut program);
-        let mut codegen = CodeGen::new(&program);
+        let mut codegen = CodeGen::with_dialect(&program, &self.dialect).with_passes(self.passes);
         let insts = codegen.generate_insts()?;
         let insts_len = insts.len();
         self.insts = insts;
+        self.spans = codegen.spans;
+        self.source_len = program.len();
         self.result = ResultString::new();
-        self.memory = vec![0; MAX_MEMORY];
+        self.emitted = 0;
+        self.popped = 0;
+        self.memory = vec![0; self.memory.len()];
         self.pointer = 0.into();
         self.counter = Counter::new(0, insts_len);
         self.input_queue = InputString::new();
         self.input_mode = false;
+        if let Some(history) = self.history.as_mut() {
+            history.clear();
+        }
+        self.breakpoints.clear();
+        self.procedures.clear();
+        self.call_stack.clear();
+        self.scheduler.reset();
+        self.storage = 0;
+        Ok(())
+    }
+
+    // Stop every thread, skipping the rest of the program
+    pub fn halt(&mut self) {
+        self.scheduler.waiting.clear();
+        self.call_stack.clear();
+        self.counter.index = self.counter.max_index;
+        self.input_mode = false;
+    }
+
+    // Append code in the same dialect, which runs once the code before it is done. The tape,
+    // pointer and procedures are kept, and the spans continue after the source so far, as if
+    // `program` was appended to it. The history is cleared.
+    pub fn extend(&mut self, program: &str) -> Result<(), &'static str> {
+        let mut codegen = CodeGen::with_dialect(program, &self.dialect).with_passes(self.passes);
+        let insts = codegen.generate_insts()?;
+        let base = self.insts.len();
+        self.insts.extend(insts.into_iter().map(|inst| match inst {
+            Jpf(idx) => Jpf(idx + base),
+            Jpb(idx) => Jpb(idx + base),
+            Def(idx) => Def(idx + base),
+            inst => inst,
+        }));
+        let offset = self.source_len;
+        self.spans.extend(codegen.spans.iter().map(|span| Span::new(span.start + offset, span.end + offset)));
+        self.source_len += program.len();
+        self.counter.max_index = self.insts.len();
+        for thread in self.scheduler.waiting.iter_mut() {
+            thread.counter.max_index = self.insts.len();
+        }
+        if let Some(history) = self.history.as_mut() {
+            history.clear();
+        }
         Ok(())
     }
 
     pub fn step(&mut self) -> Result<(), &'static str> {
-        let inst = self.insts.as_slice()[self.counter.index()];
+        if let Some(fuel) = self.fuel.as_mut() {
+            if *fuel == 0 {
+                return Err(ERROR_OUT_OF_FUEL);
+            }
+            *fuel -= 1;
+        }
+        let pc = self.counter.index();
+        let inst = self.insts.as_slice()[pc];
+        let pointer: usize = self.pointer.into();
+        let old_value = self.memory[pointer];
+
+        let cell = match inst {
+            Add(_) | Sub(_) | Stz | Frk | Lod | Rsh(_) | Lsh(_) | Not | Xor | And | Orr => Some(pointer),
+            _ => None,
+        };
+        let mut undo = self.begin_undo(cell);
+        if let (Some(undo), Def(_)) = (undo.as_mut(), inst) {
+            undo.procedure = Some((old_value, self.procedures.get(&old_value).copied()));
+        }
+        // Forking and ending change the waiting threads, other steps only the running one
+        let changes_threads = inst == Frk || (inst == End && !self.scheduler.waiting.is_empty());
+        if let (Some(undo), true) = (undo.as_mut(), changes_threads) {
+            undo.threads = Some(self.save_threads());
+        }
+        let mut forked = None;
 
         match inst {
             Add(n) => self.value_plus(n),
@@ -333,14 +633,75 @@ impl Brainfuck {
             Wrt => self.push_from_memory_into_result(),
             Jpf(idx) => self.jump_to_close_staple(idx)?,
             Jpb(idx) => self.jump_to_start_staple(idx)?,
-            Red => { self.input_mode = true; ()},
+            Red => self.input_mode = true,
             Stz => self.store_zero(),
+            Def(idx) => self.define_procedure(pc, idx)?,
+            Ret => {
+                let returned = self.return_from_procedure()?;
+                if let Some(undo) = undo.as_mut() {
+                    undo.returned = Some(returned);
+                }
+            },
+            Cal => self.call_procedure(pc)?,
+            Frk => forked = Some(self.fork()?),
+            End => self.end_program()?,
+            Sav => self.storage = old_value,
+            Lod => self.memory[pointer] = self.storage,
+            Rsh(n) => self.memory[pointer] = if n < 32 { old_value >> n } else { 0 },
+            Lsh(n) => self.memory[pointer] = if n < 32 { (old_value << n) & self.cell_width.max() } else { 0 },
+            Not => self.memory[pointer] = !old_value & self.cell_width.max(),
+            Xor => self.memory[pointer] = old_value ^ self.storage,
+            And => self.memory[pointer] = old_value & self.storage,
+            Orr => self.memory[pointer] = old_value | self.storage,
         }
 
         self.counter.inc()?;
+        if self.observer.enabled() {
+            self.notify_step(pc, inst, old_value, forked);
+        }
+        if !self.scheduler.waiting.is_empty() {
+            self.scheduler.tick();
+            self.schedule(&mut undo);
+        }
+        if let Some(undo) = undo.as_mut() {
+            undo.forked = forked;
+        }
+        self.commit_undo(undo);
+        if self.observer.enabled() && self.reach_eop() {
+            self.observer.on_halt();
+        }
         Ok(())
     }
 
+    fn notify_step(&mut self, pc: usize, inst: Inst, old_value: u32, forked: Option<(usize, u32)>) {
+        let pointer: usize = self.pointer.into();
+        let value = self.memory[pointer];
+        self.observer.on_step(&StepEvent {
+            pc,
+            inst,
+            span: self.spans[pc],
+            pointer,
+            cell: value,
+            thread: self.scheduler.current,
+        });
+        match inst {
+            Add(_) | Sub(_) | Stz | Lod | Rsh(_) | Lsh(_) | Not | Xor | And | Orr => {
+                self.observer.on_memory_write(pointer, old_value, value)
+            },
+            Wrt => self.observer.on_output(value as u8),
+            Red => self.observer.on_input_request(),
+            Jpf(_) if value != 0 => self.observer.on_loop_enter(pc),
+            Frk => {
+                self.observer.on_memory_write(pointer, old_value, value);
+                if let Some((address, old)) = forked {
+                    self.observer.on_memory_write(address, old, self.memory[address]);
+                }
+                self.observer.on_fork(self.scheduler.current, self.scheduler.next_id - 1);
+            },
+            _ => (),
+        }
+    }
+
     fn store_zero(&mut self) {
         let pointer: usize = self.pointer.into();
         self.memory[pointer] = 0;
@@ -348,16 +709,16 @@ impl Brainfuck {
 
     fn value_plus(&mut self, n: u8) {
         let pointer: usize = self.pointer.into();
-        self.memory[pointer] = self.memory[pointer].wrapping_add(n);
+        self.memory[pointer] = self.memory[pointer].wrapping_add(n.into()) & self.cell_width.max();
     }
 
     fn value_minus(&mut self, n: u8) {
         let pointer: usize = self.pointer.into();
-        self.memory[pointer] = self.memory[pointer].wrapping_sub(n);
+        self.memory[pointer] = self.memory[pointer].wrapping_sub(n.into()) & self.cell_width.max();
     }
 
     fn pointer_shift_right(&mut self, n: usize) -> Result<(), &'static str> {
-        self.pointer.shift_right_n(n)?;
+        self.pointer.shift_right_n(n, self.memory.len())?;
         Ok(())
     }
 
@@ -368,8 +729,11 @@ impl Brainfuck {
 
     fn push_from_memory_into_result(&mut self) {
         let pointer: usize = self.pointer.into();
-        let out_char: char = self.memory[pointer].into();
-        self.result.push(out_char);
+        let out_char: char = (self.memory[pointer] as u8).into();
+        if self.emitted >= self.popped {
+            self.result.push(out_char);
+        }
+        self.emitted += 1;
     }
 
     fn jump_to_close_staple(&mut self, index: usize) -> Result<(), &'static str> {
@@ -390,6 +754,151 @@ impl Brainfuck {
         Ok(())
     }
 
+    fn define_procedure(&mut self, pc: usize, index: usize) -> Result<(), &'static str> {
+        let pointer: usize = self.pointer.into();
+        self.procedures.insert(self.memory[pointer], pc);
+        self.counter.jump(index)
+    }
+
+    fn call_procedure(&mut self, pc: usize) -> Result<(), &'static str> {
+        let pointer: usize = self.pointer.into();
+        let definition = *self.procedures.get(&self.memory[pointer]).ok_or("Procedure is not defined.")?;
+        if self.call_stack.len() >= MAX_CALL_DEPTH {
+            return Err("Too deep procedure calls.");
+        }
+        self.call_stack.push(pc);
+        self.counter.jump(definition)
+    }
+
+    fn return_from_procedure(&mut self) -> Result<usize, &'static str> {
+        let call = self.call_stack.pop().ok_or("Return without a procedure call.")?;
+        self.counter.jump(call)?;
+        Ok(call)
+    }
+
+    // Every thread ends, too
+    fn end_program(&mut self) -> Result<(), &'static str> {
+        self.scheduler.waiting.clear();
+        self.counter.jump(self.insts.len() - 1)
+    }
+
+    // The parent keeps running with 0 in the current cell. The child starts after `Y`
+    // on the next cell, which is set to 1.
+    fn fork(&mut self) -> Result<(usize, u32), &'static str> {
+        let mut child = self.pointer;
+        child.shift_right_n(1, self.memory.len())?;
+        let mut counter = self.counter;
+        counter.inc()?;
+        let (pointer, address): (usize, usize) = (self.pointer.into(), child.into());
+        let old = self.memory[address];
+        self.memory[pointer] = 0;
+        self.memory[address] = 1;
+        self.scheduler.spawn(child, counter, self.call_stack.clone());
+        Ok((address, old))
+    }
+
+    // Hand the tape over to the next waiting thread once the running one finished or used
+    // up its quantum. Threads which start at the end of the program finish right away, and
+    // a thread waiting for input keeps running until it got it. The threads are saved in
+    // `undo` only when the running thread changes.
+    fn schedule(&mut self, undo: &mut Option<Undo>) {
+        let mut finished = self.counter.is_max();
+        if self.scheduler.waiting.is_empty() || self.input_mode || !(finished || self.scheduler.expired()) {
+            return;
+        }
+        if let Some(undo) = undo.as_mut() {
+            undo.threads.get_or_insert_with(|| self.save_threads());
+        }
+        while let Some(next) = self.scheduler.waiting.pop_front() {
+            let call_stack = mem::replace(&mut self.call_stack, next.call_stack);
+            if !finished {
+                self.scheduler.waiting.push_back(Thread {
+                    id:      self.scheduler.current,
+                    pointer: self.pointer,
+                    counter: self.counter,
+                    call_stack,
+                });
+            }
+            self.scheduler.current = next.id;
+            self.scheduler.slice = 0;
+            self.pointer = next.pointer;
+            self.counter = next.counter;
+            finished = self.counter.is_max();
+            if !finished {
+                break;
+            }
+        }
+    }
+
+    // Steps each Brainfork thread runs before the next one takes over
+    pub fn set_quantum(&mut self, quantum: usize) {
+        self.scheduler.quantum = quantum.max(1);
+    }
+
+    pub fn quantum(&self) -> usize {
+        self.scheduler.quantum
+    }
+
+    // Id of the running thread; the main thread is 0
+    pub fn thread(&self) -> usize {
+        self.scheduler.current
+    }
+
+    pub fn thread_count(&self) -> usize {
+        self.scheduler.waiting.len() + 1
+    }
+
+    // Limit the number of steps which can still be taken, over all threads. `None` removes
+    // the limit. Reverting steps doesn't refund fuel.
+    pub fn set_fuel(&mut self, fuel: Option<u64>) {
+        self.fuel = fuel;
+    }
+
+    pub fn fuel(&self) -> Option<u64> {
+        self.fuel
+    }
+
+    // Grow or shrink the tape. Cells beyond the new size are dropped.
+    pub fn set_tape_size(&mut self, size: usize) -> Result<(), &'static str> {
+        let pointers = self.scheduler.waiting.iter().map(|thread| thread.pointer);
+        if size == 0 {
+            return Err("Tape needs at least one cell.");
+        }
+        if std::iter::once(self.pointer).chain(pointers).any(|pointer| pointer.0 >= size) {
+            return Err(ERROR_POINTER_RIGHT);
+        }
+        self.memory.resize(size, 0);
+        Ok(())
+    }
+
+    pub fn tape_size(&self) -> usize {
+        self.memory.len()
+    }
+
+    // Change the width of the cells. Values too large for narrower cells keep their lower bits.
+    pub fn set_cell_width(&mut self, width: CellWidth) {
+        let max = width.max();
+        for cell in self.memory.iter_mut() {
+            *cell &= max;
+        }
+        self.storage &= max;
+        self.cell_width = width;
+    }
+
+    pub fn cell_width(&self) -> CellWidth {
+        self.cell_width
+    }
+
+    // The storage register of Extended Brainfuck Type I
+    pub fn storage(&self) -> u32 {
+        self.storage
+    }
+
+    // The counters of the calls of the procedures being executed, innermost last
+    pub fn call_stack(&self) -> &[usize] {
+        &self.call_stack
+    }
+
     pub fn queue_remain(&self) -> i32 {
         self.input_queue.len() as i32
     }
@@ -399,31 +908,84 @@ impl Brainfuck {
     }
 
     pub fn set_input(&mut self, input: InputString) -> Result<(), &'static str> {
-        if input.len() == 0 && self.queue_remain() <= 0 {
+        if input.is_empty() && self.queue_remain() <= 0 {
             Err("input empty String.")
         } else {
+            let pointer: usize = self.pointer.into();
+            let mut undo = self.begin_undo(Some(pointer));
+            let queue_len = self.input_queue.len();
             self.input_queue += &input;
-            let c = self.input_queue.remove(0) as i32;
-            match c {
-                0..=127 => {
-                    let pointer: usize = self.pointer.into();
-                    self.memory[pointer] = c as u8;
+            let ch = self.input_queue.remove(0);
+            match ch as i32 {
+                c @ 0..=127 => {
+                    let old_value = self.memory[pointer];
+                    self.memory[pointer] = c as u32;
+                    if let Some(undo) = undo.as_mut() {
+                        undo.input = Some((ch, queue_len));
+                    }
+                    self.input_mode = false;
+                    self.schedule(&mut undo);
+                    self.commit_undo(undo);
+                    if self.observer.enabled() {
+                        self.observer.on_memory_write(pointer, old_value, c as u32);
+                        self.observer.on_input(c as u8);
+                    }
                 },
                 _ => { return Err("Input contains non-ascii code."); },
             }
-            self.input_mode = false;
             Ok(())
         }
     }
 
+    // Answer the pending `,` with a raw byte, bypassing the input queue
+    pub fn set_input_byte(&mut self, byte: u8) -> Result<(), &'static str> {
+        self.answer_input(Some(byte.into()), false)
+    }
+
+    // Answer the pending `,` when there is no input left
+    pub fn set_eof(&mut self, policy: EofPolicy) -> Result<(), &'static str> {
+        let pointer: usize = self.pointer.into();
+        let value = match policy {
+            EofPolicy::Zero => 0,
+            EofPolicy::MinusOne => self.cell_width.max(),
+            EofPolicy::Unchanged => self.memory[pointer],
+        };
+        self.answer_input(Some(value).filter(|_| policy != EofPolicy::Unchanged), true)
+    }
+
+    // Store `value`, if any, where `,` was waiting. The end of input isn't reported as input.
+    fn answer_input(&mut self, value: Option<u32>, eof: bool) -> Result<(), &'static str> {
+        if !self.input_mode {
+            return Err("Not waiting for input.");
+        }
+        let pointer: usize = self.pointer.into();
+        let mut undo = self.begin_undo(Some(pointer));
+        let old_value = self.memory[pointer];
+        if let Some(value) = value {
+            self.memory[pointer] = value;
+        }
+        self.input_mode = false;
+        self.schedule(&mut undo);
+        self.commit_undo(undo);
+        if let (true, Some(value)) = (self.observer.enabled(), value) {
+            self.observer.on_memory_write(pointer, old_value, value);
+            if !eof {
+                self.observer.on_input(value as u8);
+            }
+        }
+        Ok(())
+    }
+
     pub fn pop_result(&mut self) -> ResultString {
         let result = self.result.clone();
         self.result.clear();
+        self.popped = self.popped.max(self.emitted);
         result
     }
 
+    // Every thread reached the end of the program
     pub fn reach_eop(&self) -> bool {
-        self.counter.is_max()
+        self.counter.is_max() && self.scheduler.waiting.is_empty()
     }
 
     pub fn include_comma(&self) -> bool {
@@ -439,6 +1001,233 @@ impl Brainfuck {
         }
         Ok(())
     }
+
+    pub fn memory(&self) -> &[u32] {
+        &self.memory
+    }
+
+    pub fn pointer(&self) -> usize {
+        self.pointer.into()
+    }
+
+    pub fn pc(&self) -> usize {
+        self.counter.index()
+    }
+
+    pub fn insts(&self) -> &[Inst] {
+        &self.insts
+    }
+
+    // The source span of the instruction at `pc`
+    pub fn span(&self, pc: usize) -> Option<Span> {
+        self.spans.get(pc).copied()
+    }
+
+    pub fn dialect(&self) -> &Dialect {
+        &self.dialect
+    }
+
+    pub fn spans(&self) -> &[Span] {
+        &self.spans
+    }
+
+    pub fn add_breakpoint(&mut self, pc: usize) {
+        self.breakpoints.insert(pc);
+    }
+
+    pub fn remove_breakpoint(&mut self, pc: usize) {
+        self.breakpoints.remove(&pc);
+    }
+
+    pub fn clear_breakpoints(&mut self) {
+        self.breakpoints.clear();
+    }
+
+    pub fn breakpoints(&self) -> &BTreeSet<usize> {
+        &self.breakpoints
+    }
+
+    pub fn at_breakpoint(&self) -> bool {
+        self.breakpoints.contains(&self.counter.index())
+    }
+
+    // Start recording undo information for every step, so that execution can be reversed.
+    pub fn enable_history(&mut self, config: HistoryConfig) {
+        self.history = Some(History::new(config));
+    }
+
+    pub fn disable_history(&mut self) {
+        self.history = None;
+    }
+
+    // The number of steps which can be reverted.
+    pub fn history_len(&self) -> usize {
+        self.history.as_ref().map_or(0, History::len)
+    }
+
+    pub fn step_back(&mut self) -> Result<(), &'static str> {
+        let history = self.history.as_mut().ok_or("History is not enabled.")?;
+        let undo = history.pop().ok_or("No more history to step back.")?;
+        self.revert(undo);
+        Ok(())
+    }
+
+    // Run backwards until just before the previous write to the cell at `address`.
+    pub fn back_to_write(&mut self, address: usize) -> Result<(), &'static str> {
+        let history = self.history.as_ref().ok_or("History is not enabled.")?;
+        let steps = history
+            .distance_to(|undo| {
+                undo.cell.is_some_and(|(addr, _)| addr == address)
+                    || undo.forked.is_some_and(|(addr, _)| addr == address)
+            })
+            .ok_or("No write to the cell in the history.")?;
+        self.rewind(steps);
+        Ok(())
+    }
+
+    // Run backwards until the counter reaches a breakpoint.
+    pub fn back_to_breakpoint(&mut self) -> Result<(), &'static str> {
+        let history = self.history.as_ref().ok_or("History is not enabled.")?;
+        let breakpoints = &self.breakpoints;
+        let steps = history
+            .distance_to(|undo| breakpoints.contains(&undo.counter))
+            .ok_or("No breakpoint in the history.")?;
+        self.rewind(steps);
+        Ok(())
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            memory:      self.memory.clone(),
+            pointer:     self.pointer,
+            counter:     self.counter.index(),
+            input_queue: self.input_queue.clone(),
+            input_mode:  self.input_mode,
+            procedures:  self.procedures.clone(),
+            call_stack:  self.call_stack.clone(),
+            scheduler:   self.scheduler.clone(),
+            storage:     self.storage,
+            emitted:     self.emitted,
+        }
+    }
+
+    fn save_threads(&self) -> Box<SavedThreads> {
+        Box::new(SavedThreads {
+            scheduler:  self.scheduler.clone(),
+            call_stack: self.call_stack.clone(),
+        })
+    }
+
+    fn begin_undo(&mut self, cell: Option<usize>) -> Option<Undo> {
+        if self.history.as_ref()?.needs_snapshot() {
+            let snapshot = self.snapshot();
+            self.history.as_mut()?.push_snapshot(snapshot);
+        }
+        Some(Undo {
+            counter:    self.counter.index(),
+            pointer:    self.pointer,
+            input_mode: self.input_mode,
+            cell:       cell.map(|addr| (addr, self.memory[addr])),
+            emitted:    self.emitted,
+            input:      None,
+            calls:      self.call_stack.len(),
+            returned:   None,
+            procedure:  None,
+            threads:    None,
+            slice:      self.scheduler.slice,
+            forked:     None,
+            storage:    self.storage,
+        })
+    }
+
+    fn commit_undo(&mut self, undo: Option<Undo>) {
+        if let (Some(history), Some(undo)) = (self.history.as_mut(), undo) {
+            history.record(undo);
+        }
+    }
+
+    // Go back to when `emitted` characters were output. Output which has already been popped
+    // can't be taken back, so it is only skipped when the steps are taken again.
+    fn take_back_output(&mut self, emitted: usize) {
+        let buffered = emitted.saturating_sub(self.popped);
+        if let Some((idx, _)) = self.result.char_indices().nth(buffered) {
+            self.result.truncate(idx);
+        }
+        self.emitted = emitted;
+    }
+
+    fn revert(&mut self, undo: Undo) {
+        self.counter.index = undo.counter;
+        self.pointer = undo.pointer;
+        self.input_mode = undo.input_mode;
+        if let Some((addr, value)) = undo.cell {
+            self.memory[addr] = value;
+        }
+        self.take_back_output(undo.emitted);
+        if let Some((ch, queue_len)) = undo.input {
+            self.input_queue.insert(0, ch);
+            self.input_queue.truncate(queue_len);
+        }
+        // Threads saved on a switch hold the call stack of the running thread after the step,
+        // which is taken back like any other
+        if let Some(saved) = undo.threads {
+            self.scheduler = saved.scheduler;
+            self.call_stack = saved.call_stack;
+        }
+        self.scheduler.slice = undo.slice;
+        self.call_stack.truncate(undo.calls);
+        if let Some(call) = undo.returned {
+            self.call_stack.push(call);
+        }
+        if let Some((addr, value)) = undo.forked {
+            self.memory[addr] = value;
+        }
+        self.storage = undo.storage;
+        match undo.procedure {
+            Some((name, Some(definition))) => { self.procedures.insert(name, definition); },
+            Some((name, None)) => { self.procedures.remove(&name); },
+            None => (),
+        }
+    }
+
+    fn restore(&mut self, segment: Segment) {
+        let snapshot = segment.snapshot;
+        self.take_back_output(snapshot.emitted);
+        self.memory = snapshot.memory;
+        self.pointer = snapshot.pointer;
+        self.counter.index = snapshot.counter;
+        self.input_queue = snapshot.input_queue;
+        self.input_mode = snapshot.input_mode;
+        self.procedures = snapshot.procedures;
+        self.call_stack = snapshot.call_stack;
+        self.scheduler = snapshot.scheduler;
+        self.storage = snapshot.storage;
+    }
+
+    // Revert `steps` steps. Whole segments are reverted at once by restoring their snapshot.
+    fn rewind(&mut self, mut steps: usize) {
+        while steps > 0 {
+            let history = match self.history.as_mut() {
+                Some(history) => history,
+                None => return,
+            };
+            let segment_len = history.last_segment_len();
+            if segment_len == 0 {
+                return;
+            }
+            if steps >= segment_len {
+                if let Some(segment) = history.pop_segment() {
+                    self.restore(segment);
+                }
+                steps -= segment_len;
+            } else {
+                if let Some(undo) = history.pop() {
+                    self.revert(undo);
+                }
+                steps -= 1;
+            }
+        }
+    }
 }
 
 #[cfg(test)]
@@ -451,10 +1240,251 @@ mod tests {
         assert_eq!(bf.step(), Err("Too large pointer than the size of memory."));
     }
 
+    #[test]
+    fn unbalanced_brackets() {
+        assert!(Brainfuck::new(String::from("+[")).is_err());
+        assert!(Brainfuck::new(String::from("+]")).is_err());
+    }
+
     #[test]
     fn pointer_minus() {
         let program = String::from("<");
         let mut bf = Brainfuck::new(program).unwrap();
         assert_eq!(bf.step(), Err("Too small pointer than the first address of memory."));
     }
+
+    fn pbrain(program: &str) -> Result<Brainfuck, &'static str> {
+        let mut bf = Brainfuck::with_dialect(String::from(program), &Dialect::pbrain())?;
+        bf.step_loop()?;
+        Ok(bf)
+    }
+
+    #[test]
+    fn procedures() {
+        // Procedure 1 adds 2 to the next cell, procedure 2 calls procedure 1 twice
+        let bf = pbrain("+(>++<)+(-::+)::").unwrap();
+        assert_eq!(bf.memory()[..2], [2, 8]);
+        assert!(bf.call_stack().is_empty());
+        // Without the extension, the procedure is a comment
+        let mut bf = Brainfuck::new(String::from("+(>++<):")).unwrap();
+        bf.step_loop().unwrap();
+        assert_eq!(bf.memory()[..2], [1, 2]);
+    }
+
+    #[test]
+    fn procedure_errors() {
+        assert_eq!(pbrain("+:").err(), Some("Procedure is not defined."));
+        assert_eq!(pbrain("+(:):").err(), Some("Too deep procedure calls."));
+        assert_eq!(pbrain("(").err(), Some("Procedure can't be constructed."));
+        assert_eq!(pbrain("[(])").err(), Some("Jumptable can't be constructed."));
+    }
+
+    #[test]
+    fn step_back_through_calls() {
+        let mut bf = Brainfuck::with_dialect(String::from("+(>+<)::"), &Dialect::pbrain()).unwrap();
+        bf.enable_history(HistoryConfig { snapshot_interval: 3, max_snapshots: 8 });
+        bf.step_loop().unwrap();
+        let steps = bf.history_len();
+        assert_eq!(bf.memory()[1], 2);
+        for _ in 0..steps {
+            bf.step_back().unwrap();
+        }
+        assert_eq!((bf.pc(), bf.memory()[..2].to_vec()), (0, vec![0, 0]));
+        bf.step_loop().unwrap();
+        assert_eq!(bf.memory()[1], 2);
+    }
+
+    fn brainfork(program: &str, quantum: usize) -> Brainfuck {
+        let mut bf = Brainfuck::with_dialect(String::from(program), &Dialect::brainfork()).unwrap();
+        bf.set_quantum(quantum);
+        bf
+    }
+
+    #[test]
+    fn forks_share_the_tape() {
+        let mut bf = brainfork("Y>+", 1);
+        bf.step().unwrap();
+        assert_eq!((bf.thread(), bf.thread_count()), (1, 2));
+        bf.step_loop().unwrap();
+        assert_eq!(bf.memory()[..3], [0, 2, 1]);
+        assert_eq!(bf.thread_count(), 1);
+        // A child forked at the end of the program finishes right away
+        let mut bf = brainfork("+Y", 1);
+        bf.step_loop().unwrap();
+        assert_eq!(bf.memory()[..2], [0, 1]);
+    }
+
+    #[test]
+    fn quantum_interleaving() {
+        let output = |quantum| {
+            let mut bf = brainfork("Y..", quantum);
+            bf.step_loop().unwrap();
+            bf.pop_result()
+        };
+        assert_eq!(output(1), "\u{1}\u{0}\u{1}\u{0}");
+        assert_eq!(output(2), "\u{0}\u{1}\u{1}\u{0}");
+    }
+
+    #[test]
+    fn fuel_runs_out() {
+        let mut bf = Brainfuck::new(String::from("+[]")).unwrap();
+        bf.set_fuel(Some(100));
+        assert_eq!(bf.step_loop(), Err("Out of fuel."));
+        assert_eq!(bf.fuel(), Some(0));
+        // Fuel is shared by all threads
+        let mut bf = brainfork("Y>+", 3);
+        bf.set_fuel(Some(4));
+        assert_eq!(bf.step_loop(), Err("Out of fuel."));
+    }
+
+    #[test]
+    fn tape_size_and_eof() {
+        let mut bf = Brainfuck::new(String::from(">>>,")).unwrap();
+        bf.set_tape_size(3).unwrap();
+        assert_eq!(bf.step_loop(), Err("Too large pointer than the size of memory."));
+        assert_eq!(bf.set_tape_size(0), Err("Tape needs at least one cell."));
+
+        bf.set_tape_size(4).unwrap();
+        assert_eq!(bf.set_eof(EofPolicy::Zero), Err("Not waiting for input."));
+        bf.step_loop().unwrap();
+        assert!(bf.is_input_mode());
+        bf.set_eof(EofPolicy::MinusOne).unwrap();
+        assert_eq!((bf.memory()[3], bf.is_input_mode(), bf.reach_eop()), (255, false, true));
+    }
+
+    #[test]
+    fn cell_widths() {
+        assert_eq!(CellWidth::from_bits(12), None);
+        let wraps = format!("{}[>+<[-]]-.", "+".repeat(256));
+        let mut bf = Brainfuck::new(wraps.clone()).unwrap();
+        bf.step_loop().unwrap();
+        assert_eq!(bf.memory()[..2], [255, 0]);
+        let mut bf = Brainfuck::new(wraps).unwrap();
+        bf.set_cell_width(CellWidth::Bits16);
+        bf.step_loop().unwrap();
+        // `.` writes the lowest byte
+        assert_eq!((bf.memory()[..2].to_vec(), bf.pop_result()), (vec![65535, 1], String::from("\u{ff}")));
+
+        let mut bf = Brainfuck::new(String::from(",")).unwrap();
+        bf.set_cell_width(CellWidth::Bits32);
+        bf.step_loop().unwrap();
+        bf.set_eof(EofPolicy::MinusOne).unwrap();
+        assert_eq!(bf.memory()[0], u32::MAX);
+        bf.set_cell_width(CellWidth::Bits8);
+        assert_eq!(bf.memory()[0], 255);
+    }
+
+    #[test]
+    fn extend_keeps_the_tape() {
+        let mut bf = Brainfuck::new(String::from("++>+")).unwrap();
+        bf.step_loop().unwrap();
+        bf.extend("[<+>-]<").unwrap();
+        assert!(!bf.reach_eop());
+        bf.step_loop().unwrap();
+        assert_eq!((bf.pointer(), &bf.memory()[..2]), (0, &[3, 0][..]));
+        assert_eq!(bf.span(3), Some(Span::new(4, 5)));
+        assert_eq!(bf.extend("]"), Err("Jumptable can't be constructed."));
+        assert_eq!(bf.insts().len(), 10);
+        bf.extend(">,+").unwrap();
+        bf.step().unwrap();
+        bf.halt();
+        assert!(bf.reach_eop() && !bf.is_input_mode());
+        assert_eq!(bf.pointer(), 1);
+    }
+
+    #[test]
+    fn optimization_passes() {
+        let dialect = Dialect::brainfuck();
+        let mut bf = Brainfuck::with_passes(String::from("+++[-]"), &dialect, Passes::none()).unwrap();
+        assert_eq!(bf.insts(), &[Add(1), Add(1), Add(1), Jpf(5), Sub(1), Jpb(3)][..]);
+        bf.extend(">>").unwrap();
+        assert_eq!(bf.insts().len(), 8);
+        let passes = Passes { clear_loops: false, ..Passes::default() };
+        let bf = Brainfuck::with_passes(String::from("+++[-]"), &dialect, passes).unwrap();
+        assert_eq!(bf.insts(), &[Add(3), Jpf(3), Sub(1), Jpb(1)][..]);
+    }
+
+    #[test]
+    fn raw_input_bytes() {
+        let mut bf = Brainfuck::new(String::from(",")).unwrap();
+        bf.enable_history(HistoryConfig::default());
+        bf.step().unwrap();
+        bf.set_input_byte(200).unwrap();
+        assert_eq!((bf.memory()[0], bf.is_input_mode()), (200, false));
+        assert_eq!(bf.set_input_byte(1), Err("Not waiting for input."));
+        bf.step_back().unwrap();
+        assert_eq!((bf.memory()[0], bf.is_input_mode()), (0, true));
+    }
+
+    #[test]
+    fn step_back_through_forks() {
+        let mut bf = brainfork("Y>+Y", 2);
+        bf.enable_history(HistoryConfig { snapshot_interval: 2, max_snapshots: 8 });
+        bf.step_loop().unwrap();
+        let memory = bf.memory()[..4].to_vec();
+        for _ in 0..bf.history_len() {
+            bf.step_back().unwrap();
+        }
+        assert_eq!((bf.pc(), bf.thread_count()), (0, 1));
+        assert!(bf.memory()[..4].iter().all(|&value| value == 0));
+        bf.step_loop().unwrap();
+        assert_eq!(bf.memory()[..4], memory[..]);
+    }
+
+    #[test]
+    fn step_back_each_switch() {
+        let mut bf = brainfork("Y+>+++<Y+>+", 3);
+        bf.enable_history(HistoryConfig::default());
+        let state = |bf: &Brainfuck| (bf.pc(), bf.pointer(), bf.thread(), bf.thread_count(), bf.memory()[..4].to_vec());
+        let mut states = vec![state(&bf)];
+        while !bf.reach_eop() {
+            bf.step().unwrap();
+            states.push(state(&bf));
+        }
+        let last = states.pop().unwrap();
+        while let Some(expected) = states.pop() {
+            bf.step_back().unwrap();
+            assert_eq!(state(&bf), expected);
+        }
+        // Running again from any step ends the same, so the slices of the quantum are taken back too
+        bf.step_loop().unwrap();
+        for back in 1..=bf.history_len() {
+            for _ in 0..back {
+                bf.step_back().unwrap();
+            }
+            bf.step_loop().unwrap();
+            assert_eq!(state(&bf), last);
+        }
+    }
+
+    #[test]
+    fn extended_type_i() {
+        let program = "+++$>!{}}~^& >++| @+";
+        let mut bf = Brainfuck::with_dialect(String::from(program), &Dialect::extended()).unwrap();
+        bf.enable_history(HistoryConfig::default());
+        bf.step_loop().unwrap();
+        assert_eq!((bf.memory()[..3].to_vec(), bf.storage()), (vec![3, 1, 3], 3));
+        for _ in 0..bf.history_len() {
+            bf.step_back().unwrap();
+        }
+        assert_eq!((bf.memory()[..3].to_vec(), bf.storage()), (vec![0, 0, 0], 0));
+        // `@` ends every thread
+        let dialect = Dialect::extended().with_extension(Extension::Brainfork).unwrap();
+        let mut bf = Brainfuck::with_dialect(String::from("Y@>+"), &dialect).unwrap();
+        bf.step_loop().unwrap();
+        assert_eq!(bf.memory()[..3], [0, 1, 0]);
+        // Without the extension, the commands are comments
+        let mut bf = Brainfuck::new(String::from("+@+")).unwrap();
+        bf.step_loop().unwrap();
+        assert_eq!(bf.memory()[0], 2);
+    }
+
+    #[test]
+    fn serialize_keeps_enabled_commands() {
+        let mut program = String::from("set 1+, store it$ and quit@!");
+        Brainfuck::serialize_with_dialect(&mut program, &Dialect::extended());
+        assert_eq!(program, "+,$@!");
+        Brainfuck::serialize(&mut program);
+        assert_eq!(program, "+,");
+    }
 }
@@ -0,0 +1,203 @@
+use std::fmt;
+
+use crate::{Dialect, Span};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LintKind {
+    UnmatchedOpen,   // `[` without `]`
+    UnmatchedClose,  // `]` without `[`
+    NeverEntered,    // A loop reached before any cell is changed
+    DeadAfterLoop,   // A loop right after another loop, where the cell is always 0
+    Cancelling,      // `+-`, `-+`, `<>` or `><`
+    InfiniteLoop,    // `[]`, which never ends once entered
+}
+
+impl LintKind {
+    pub fn severity(&self) -> Severity {
+        match self {
+            LintKind::UnmatchedOpen | LintKind::UnmatchedClose => Severity::Error,
+            _ => Severity::Warning,
+        }
+    }
+
+    pub fn message(&self) -> &'static str {
+        match self {
+            LintKind::UnmatchedOpen => "This bracket is never closed.",
+            LintKind::UnmatchedClose => "This bracket has no opening bracket.",
+            LintKind::NeverEntered => "This loop is never entered, because all cells are still 0.",
+            LintKind::DeadAfterLoop => "This loop is never entered, because the previous loop leaves the cell 0.",
+            LintKind::Cancelling => "These commands cancel each other out.",
+            LintKind::InfiniteLoop => "This empty loop never terminates once entered.",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Lint {
+    pub kind: LintKind,
+    pub span: Span,
+}
+
+impl Lint {
+    // `line:col: severity: message`, with the position resolved against `source`
+    pub fn describe(&self, source: &str) -> String {
+        let (line, col) = self.span.line_col(source);
+        format!("{}:{}: {}: {}", line, col, self.kind.severity(), self.kind.message())
+    }
+}
+
+// What is known about the current cell
+#[derive(Clone, Copy, PartialEq)]
+enum Zero {
+    Unknown,
+    Initial,    // No cell has been changed yet
+    AfterLoop,  // A loop just ended on this cell
+}
+
+/// Check a program for mistakes and redundant code.
+///
+/// Lints are sorted by their position in the source.
+pub fn lint(program: &str) -> Vec<Lint> {
+    lint_with_dialect(program, &Dialect::brainfuck())
+}
+
+pub fn lint_with_dialect(program: &str, dialect: &Dialect) -> Vec<Lint> {
+    let tokens = dialect.tokenize(program);
+    let span = |idx: usize| tokens[idx].span;
+    let mut lints = Vec::new();
+
+    // Brackets
+    let mut matching = vec![None; tokens.len()];
+    let mut opens = Vec::new();
+    for (idx, token) in tokens.iter().enumerate() {
+        match token.command {
+            '[' => opens.push(idx),
+            ']' => match opens.pop() {
+                Some(open) => {
+                    matching[open] = Some(idx);
+                    matching[idx] = Some(open);
+                },
+                None => lints.push(Lint { kind: LintKind::UnmatchedClose, span: span(idx) }),
+            },
+            _ => (),
+        }
+    }
+    for &open in &opens {
+        lints.push(Lint { kind: LintKind::UnmatchedOpen, span: span(open) });
+    }
+    let balanced = lints.is_empty();
+
+    // Cancelling pairs
+    let mut idx = 1;
+    while idx < tokens.len() {
+        match (tokens[idx - 1].command, tokens[idx].command) {
+            ('+', '-') | ('-', '+') | ('<', '>') | ('>', '<') => {
+                lints.push(Lint {
+                    kind: LintKind::Cancelling,
+                    span: Span::new(span(idx - 1).start, span(idx).end),
+                });
+                idx += 2;
+            },
+            _ => idx += 1,
+        }
+    }
+
+    // Loops whose cell is known to be 0, and empty loops. Jumps can't be followed without
+    // balanced brackets.
+    if balanced {
+        let mut zero = Zero::Initial;
+        let mut idx = 0;
+        while idx < tokens.len() {
+            match tokens[idx].command {
+                '+' | '-' | ',' => zero = Zero::Unknown,
+                '>' | '<' if zero == Zero::AfterLoop => zero = Zero::Unknown,
+                ']' => zero = Zero::AfterLoop,
+                '[' => {
+                    let close = matching[idx].unwrap_or(idx);
+                    let whole = Span::new(span(idx).start, span(close).end);
+                    match zero {
+                        Zero::Initial => {
+                            lints.push(Lint { kind: LintKind::NeverEntered, span: whole });
+                            idx = close + 1;
+                            continue;
+                        },
+                        Zero::AfterLoop => {
+                            lints.push(Lint { kind: LintKind::DeadAfterLoop, span: whole });
+                            idx = close + 1;
+                            continue;
+                        },
+                        Zero::Unknown => {
+                            if close == idx + 1 {
+                                lints.push(Lint { kind: LintKind::InfiniteLoop, span: whole });
+                            }
+                        },
+                    }
+                },
+                '.' | '>' | '<' => (),
+                // Commands of extensions, like procedure calls, may change any cell
+                _ => zero = Zero::Unknown,
+            }
+            idx += 1;
+        }
+    }
+
+    lints.sort_by_key(|lint| lint.span);
+    lints
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(program: &str) -> Vec<LintKind> {
+        lint(program).into_iter().map(|lint| lint.kind).collect()
+    }
+
+    #[test]
+    fn unbalanced_brackets() {
+        assert_eq!(lint("+[[-]"), vec![Lint { kind: LintKind::UnmatchedOpen, span: Span::new(1, 2) }]);
+        assert_eq!(kinds("+]["), vec![LintKind::UnmatchedClose, LintKind::UnmatchedOpen]);
+    }
+
+    #[test]
+    fn dead_loops() {
+        assert_eq!(lint("[.]>+"), vec![Lint { kind: LintKind::NeverEntered, span: Span::new(0, 3) }]);
+        assert_eq!(kinds(">>[-]+[-][>.<]"), vec![LintKind::NeverEntered, LintKind::DeadAfterLoop]);
+        assert_eq!(kinds("+[-]>[-]"), vec![]);
+    }
+
+    #[test]
+    fn cancelling_and_infinite() {
+        assert_eq!(kinds("++-><"), vec![LintKind::Cancelling, LintKind::Cancelling]);
+        assert_eq!(kinds("+[]"), vec![LintKind::InfiniteLoop]);
+        assert_eq!(kinds("[]"), vec![LintKind::NeverEntered]);
+    }
+
+    #[test]
+    fn dialect_spans() {
+        let source = "Ook! Ook? Ook? Ook!";
+        assert_eq!(lint_with_dialect(source, &Dialect::ook()),
+                   vec![Lint { kind: LintKind::NeverEntered, span: Span::new(0, 19) }]);
+    }
+
+    #[test]
+    fn describe_position() {
+        let source = "+\n +-";
+        assert_eq!(lint(source)[0].describe(source), "2:2: warning: These commands cancel each other out.");
+    }
+}
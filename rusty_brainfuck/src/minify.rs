@@ -0,0 +1,179 @@
+use std::collections::BTreeMap;
+
+use crate::{lint, Brainfuck, CodeGen, LintKind, MAX_MEMORY};
+
+// Whether the body of each loop, keyed by the index of its `[`, moves the pointer back to
+// where it started, so that every iteration starts on the same cell.
+fn balanced_loops(program: &[char]) -> BTreeMap<usize, bool> {
+    let mut balanced = BTreeMap::new();
+    let mut opens: Vec<(usize, Option<isize>)> = Vec::new();
+    let mut moved = Some(0);
+    for (idx, &c) in program.iter().enumerate() {
+        match c {
+            '>' => moved = moved.map(|n| n + 1),
+            '<' => moved = moved.map(|n| n - 1),
+            '[' => {
+                opens.push((idx, moved));
+                moved = Some(0);
+            },
+            ']' => if let Some((open, outer)) = opens.pop() {
+                let is_balanced = moved == Some(0);
+                balanced.insert(open, is_balanced);
+                moved = outer.filter(|_| is_balanced);
+            },
+            _ => (),
+        }
+    }
+    balanced
+}
+
+// Where the pointer is, if known
+#[derive(Clone, Copy)]
+struct Position {
+    pointer: Option<usize>,
+}
+
+impl Position {
+    fn after(self, c: char) -> Position {
+        let pointer = match c {
+            '>' => self.pointer.map(|p| p + 1),
+            '<' => self.pointer.and_then(|p| p.checked_sub(1)),
+            _ => self.pointer,
+        };
+        Position { pointer }
+    }
+
+    // Whether `a` followed by `b` can be dropped without changing what the program does,
+    // including moving off either end of a tape of `tape_size` cells
+    fn cancels(self, a: char, b: char, tape_size: usize) -> bool {
+        match (a, b, self.pointer) {
+            ('+', '-', _) | ('-', '+', _) => true,
+            ('<', '>', Some(p)) => p >= 1,
+            ('>', '<', Some(p)) => p + 1 < tape_size,
+            _ => false,
+        }
+    }
+}
+
+// Remove adjacent pairs of opposite commands, including the pairs which meet after
+// an inner pair is removed, like `++--`. Pointer moves only cancel where the pointer
+// provably stays on the tape.
+fn cancel(program: &str, tape_size: usize) -> String {
+    let program: Vec<char> = program.chars().collect();
+    let balanced = balanced_loops(&program);
+    // Every kept command, with the position before it
+    let mut kept: Vec<(char, Position)> = Vec::new();
+    let mut loops: Vec<(Position, bool)> = Vec::new();
+    let mut position = Position { pointer: Some(0) };
+    for (idx, &c) in program.iter().enumerate() {
+        if let Some(&(last, before)) = kept.last() {
+            if before.cancels(last, c, tape_size) {
+                kept.pop();
+                position = before;
+                continue;
+            }
+        }
+        kept.push((c, position));
+        position = match c {
+            '[' => {
+                let is_balanced = balanced.get(&idx).copied().unwrap_or(false);
+                loops.push((position, is_balanced));
+                Position { pointer: position.pointer.filter(|_| is_balanced) }
+            },
+            ']' => match loops.pop() {
+                Some((open, true)) => open,
+                Some((_, false)) => Position { pointer: None },
+                None => position,
+            },
+            c => position.after(c),
+        };
+    }
+    kept.into_iter().map(|(c, _)| c).collect()
+}
+
+// Remove the loops the linter proves are never entered.
+fn remove_dead_loops(program: &str) -> String {
+    let mut result = String::with_capacity(program.len());
+    let mut pos = 0;
+    for l in lint(program) {
+        if let LintKind::NeverEntered | LintKind::DeadAfterLoop = l.kind {
+            result.push_str(&program[pos..l.span.start]);
+            pos = l.span.end;
+        }
+    }
+    result.push_str(&program[pos..]);
+    result
+}
+
+/// Produce the smallest equivalent program.
+///
+/// Comments are stripped, opposite commands like `+-` and `<>` cancel out, and
+/// loops which can never be entered are dropped, until nothing changes anymore.
+/// Pointer moves only cancel where the pointer provably stays on the tape, so a
+/// program which moves the pointer off the tape still fails in the same place.
+pub fn minify(program: &str) -> Result<String, &'static str> {
+    minify_with_tape_size(program, MAX_MEMORY)
+}
+
+/// Like `minify`, for a tape of `tape_size` cells.
+pub fn minify_with_tape_size(program: &str, tape_size: usize) -> Result<String, &'static str> {
+    CodeGen::new(program).generate_insts()?;
+
+    let mut result = String::from(program);
+    Brainfuck::serialize(&mut result);
+    loop {
+        let next = remove_dead_loops(&cancel(&result, tape_size));
+        if next == result {
+            break;
+        }
+        result = next;
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(program: &str) -> (String, Vec<u32>) {
+        let mut bf = Brainfuck::new(String::from(program)).unwrap();
+        bf.step_loop().unwrap();
+        (bf.pop_result(), bf.memory().to_vec())
+    }
+
+    #[test]
+    fn strips_and_cancels() {
+        assert_eq!(minify("add 3 +++ --").unwrap(), "+");
+        // `<>` cancels away from the first cell, `><` away from the last one
+        assert_eq!(minify(">+<>-").unwrap(), ">");
+        assert_eq!(minify("><").unwrap(), "");
+        assert_eq!(minify(">>+<<+><").unwrap(), ">>+<<+");
+        assert_eq!(minify("+>>+-<<-").unwrap(), "");
+        assert_eq!(minify("<> fails on the first cell").unwrap(), "<>");
+        assert_eq!(minify("+[>+<-]><").unwrap(), "+[>+<-]");
+        assert_eq!(minify_with_tape_size(">>< fails on the last cell", 2).unwrap(), ">><");
+        assert_eq!(minify("+[>]>< may be past the end").unwrap(), "+[>]><");
+    }
+
+    #[test]
+    fn removes_dead_loops() {
+        assert_eq!(minify("[comment loop, never run.]+[-][>.<]").unwrap(), "+[-]");
+        assert_eq!(minify("+[-]>[.]<-").unwrap(), "+[-]>[.]<-");
+        // Removing the dead loop makes `>` and `<` meet
+        assert_eq!(minify(">><<>[.]<+").unwrap(), "+");
+        assert_eq!(minify("-+[.]++").unwrap(), "++");
+    }
+
+    #[test]
+    fn same_behaviour() {
+        let program = "[header]++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>.>---.+++++++..+++.>>.<-.<.+++.------.--------.>>+.>++.+-<>";
+        let minified = minify(program).unwrap();
+        assert!(minified.len() < program.len());
+        assert_eq!(run(program), run(&minified));
+    }
+
+    #[test]
+    fn rejects_invalid_programs() {
+        assert!(minify("[").is_err());
+    }
+}
@@ -0,0 +1,200 @@
+use crate::{Inst, Span};
+
+/// The state right after a step was executed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StepEvent {
+    pub pc:      usize,
+    pub inst:    Inst,
+    pub span:    Span,
+    pub pointer: usize,  // The pointer after the step
+    pub cell:    u32,    // The value of the current cell after the step
+    pub thread:  usize,  // The Brainfork thread which took the step; 0 is the main thread
+}
+
+/// Callbacks invoked by a `Brainfuck` while it runs.
+///
+/// Every method has an empty default, so an observer only implements the events
+/// it is interested in. `()` is the observer of a plain `Brainfuck`; its
+/// `enabled` returns `false`, which lets the interpreter skip collecting events.
+/// Reverting steps with the execution history doesn't raise events.
+pub trait Observer {
+    fn enabled(&self) -> bool {
+        true
+    }
+
+    // Called after every step, before the more specific events of the step
+    fn on_step(&mut self, _event: &StepEvent) {}
+
+    // `.` wrote `byte`
+    fn on_output(&mut self, _byte: u8) {}
+
+    // `,` was executed, and the interpreter waits for `set_input`
+    fn on_input_request(&mut self) {}
+
+    // `set_input` stored `byte` into the current cell
+    fn on_input(&mut self, _byte: u8) {}
+
+    // `[` at `pc` was executed with a non-zero cell
+    fn on_loop_enter(&mut self, _pc: usize) {}
+
+    fn on_memory_write(&mut self, _address: usize, _old: u32, _new: u32) {}
+
+    // `Y` of thread `parent` started thread `child`
+    fn on_fork(&mut self, _parent: usize, _child: usize) {}
+
+    // The counter reached the end of the program
+    fn on_halt(&mut self) {}
+}
+
+impl Observer for () {
+    #[inline]
+    fn enabled(&self) -> bool {
+        false
+    }
+}
+
+impl<T: Observer + ?Sized> Observer for Box<T> {
+    fn enabled(&self) -> bool {
+        (**self).enabled()
+    }
+
+    fn on_step(&mut self, event: &StepEvent) {
+        (**self).on_step(event)
+    }
+
+    fn on_output(&mut self, byte: u8) {
+        (**self).on_output(byte)
+    }
+
+    fn on_input_request(&mut self) {
+        (**self).on_input_request()
+    }
+
+    fn on_input(&mut self, byte: u8) {
+        (**self).on_input(byte)
+    }
+
+    fn on_loop_enter(&mut self, pc: usize) {
+        (**self).on_loop_enter(pc)
+    }
+
+    fn on_memory_write(&mut self, address: usize, old: u32, new: u32) {
+        (**self).on_memory_write(address, old, new)
+    }
+
+    fn on_fork(&mut self, parent: usize, child: usize) {
+        (**self).on_fork(parent, child)
+    }
+
+    fn on_halt(&mut self) {
+        (**self).on_halt()
+    }
+}
+
+// A pair of observers receives every event in order, e.g. `(Profiler::new(), tracer)`.
+impl<A: Observer, B: Observer> Observer for (A, B) {
+    fn enabled(&self) -> bool {
+        self.0.enabled() || self.1.enabled()
+    }
+
+    fn on_step(&mut self, event: &StepEvent) {
+        self.0.on_step(event);
+        self.1.on_step(event);
+    }
+
+    fn on_output(&mut self, byte: u8) {
+        self.0.on_output(byte);
+        self.1.on_output(byte);
+    }
+
+    fn on_input_request(&mut self) {
+        self.0.on_input_request();
+        self.1.on_input_request();
+    }
+
+    fn on_input(&mut self, byte: u8) {
+        self.0.on_input(byte);
+        self.1.on_input(byte);
+    }
+
+    fn on_loop_enter(&mut self, pc: usize) {
+        self.0.on_loop_enter(pc);
+        self.1.on_loop_enter(pc);
+    }
+
+    fn on_memory_write(&mut self, address: usize, old: u32, new: u32) {
+        self.0.on_memory_write(address, old, new);
+        self.1.on_memory_write(address, old, new);
+    }
+
+    fn on_fork(&mut self, parent: usize, child: usize) {
+        self.0.on_fork(parent, child);
+        self.1.on_fork(parent, child);
+    }
+
+    fn on_halt(&mut self) {
+        self.0.on_halt();
+        self.1.on_halt();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Brainfuck, Observer, StepEvent};
+
+    #[derive(Default)]
+    struct Recorder {
+        events: Vec<String>,
+    }
+
+    impl Observer for Recorder {
+        fn on_step(&mut self, event: &StepEvent) {
+            self.events.push(format!("step {}", event.pc));
+        }
+
+        fn on_output(&mut self, byte: u8) {
+            self.events.push(format!("output {}", byte));
+        }
+
+        fn on_input_request(&mut self) {
+            self.events.push(String::from("input?"));
+        }
+
+        fn on_input(&mut self, byte: u8) {
+            self.events.push(format!("input {}", byte));
+        }
+
+        fn on_loop_enter(&mut self, pc: usize) {
+            self.events.push(format!("loop {}", pc));
+        }
+
+        fn on_memory_write(&mut self, address: usize, old: u32, new: u32) {
+            self.events.push(format!("write {} {}->{}", address, old, new));
+        }
+
+        fn on_halt(&mut self) {
+            self.events.push(String::from("halt"));
+        }
+    }
+
+    #[test]
+    fn events_in_order() {
+        let bf = Brainfuck::new(String::from(",[-.]")).unwrap();
+        let mut bf = bf.with_observer(Recorder::default());
+        bf.step().unwrap();
+        bf.set_input(String::from("\u{2}")).unwrap();
+        bf.step_loop().unwrap();
+        let (_, recorder) = bf.detach_observer();
+        assert_eq!(recorder.events, vec![
+            "step 0", "input?",
+            "write 0 0->2", "input 2",
+            "step 1", "loop 1",
+            "step 2", "write 0 2->1",
+            "step 3", "output 1",
+            "step 4",
+            "step 2", "write 0 1->0",
+            "step 3", "output 0",
+            "step 4", "halt",
+        ]);
+    }
+}
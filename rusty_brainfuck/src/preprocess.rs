@@ -0,0 +1,596 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::{is_command, Span};
+
+const MAX_DEPTH: usize = 64;
+const MAX_COUNT: usize = 1_000_000;      // Of a single `*N`
+const MAX_EXPANSION: usize = 1 << 22;    // Units expanded in all, which bounds the program too
+const MAIN: &str = "<main>";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PreprocessError {
+    pub message: &'static str,
+    pub file:    String,
+    pub span:    Span,
+    pub line:    usize,
+    pub col:     usize,
+}
+
+impl fmt::Display for PreprocessError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}:{}: error: {}", self.file, self.line, self.col, self.message)
+    }
+}
+
+/// Where a command of the preprocessed program came from.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Origin {
+    pub file: usize,  // Index into `SourceMap::files`
+    pub span: Span,   // The text in that file which produced the command
+    pub root: Span,   // The text in the main source, e.g. the outermost macro call or include
+}
+
+/// Maps every byte of a preprocessed program back to the macro source.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct SourceMap {
+    files:   Vec<String>,
+    origins: Vec<Origin>,
+}
+
+impl SourceMap {
+    // Names of the sources; the first one is the main source
+    pub fn files(&self) -> &[String] {
+        &self.files
+    }
+
+    // The origin of the command at byte `offset` of the program
+    pub fn origin(&self, offset: usize) -> Option<Origin> {
+        self.origins.get(offset).copied()
+    }
+
+    pub fn file_name(&self, origin: &Origin) -> &str {
+        &self.files[origin.file]
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expansion {
+    pub program:    String,
+    pub source_map: SourceMap,
+}
+
+/// Expands macro source into plain Brainfuck.
+///
+/// Lines starting with `#` are directives:
+///
+/// ```text
+/// #define name(a, b) body     a macro on one line
+/// #define name(a, b)          a macro on the following lines, up to `#end`
+/// #include "file.bf"          expand another file, searched next to the including
+///                             file and in the include directories
+/// #cell name [index]          name a cell; without an index, the next free cell
+/// #at name                    declare where the pointer is, e.g. after `[>]`
+/// ```
+///
+/// Everywhere else, `name(x, y)` or `name` calls a macro, a parameter expands
+/// to its argument, `@name` moves the pointer to a named cell, and a command or
+/// macro call followed by `*N` is repeated N times, as in `+*65` or `+*n` with
+/// a parameter `n`. N is at most a million, and the whole expansion at most
+/// about four million commands and calls. Any other text is a comment, and is dropped; so are the
+/// comments of included files. Beware that words of comments which happen to
+/// be macro names are expanded.
+///
+/// The pointer position is tracked from cell 0 through `<` and `>`. It is lost
+/// after a loop which doesn't return the pointer to where it started, and cell
+/// names can't be used in such a loop.
+#[derive(Debug, Clone, Default)]
+pub struct Preprocessor {
+    files:        HashMap<String, String>,
+    include_dirs: Vec<PathBuf>,
+}
+
+impl Preprocessor {
+    pub fn new() -> Self {
+        Preprocessor::default()
+    }
+
+    // A file which can be included by `name` without touching the file system
+    pub fn add_file(&mut self, name: &str, contents: &str) {
+        self.files.insert(name.to_string(), contents.to_string());
+    }
+
+    pub fn include_dir<P: AsRef<Path>>(&mut self, dir: P) {
+        self.include_dirs.push(dir.as_ref().to_path_buf());
+    }
+
+    pub fn run(&self, source: &str) -> Result<Expansion, PreprocessError> {
+        Expander::new(self).run(MAIN, None, source)
+    }
+
+    // Expand a file; files it includes are also searched next to it
+    pub fn run_file<P: AsRef<Path>>(&self, path: P) -> Result<Expansion, PreprocessError> {
+        let path = path.as_ref();
+        let name = path.display().to_string();
+        let source = fs::read_to_string(path).map_err(|_| PreprocessError {
+            message: "Failed to read the file.",
+            file:    name.clone(),
+            span:    Span::new(0, 0),
+            line:    1,
+            col:     1,
+        })?;
+        Expander::new(self).run(&name, path.parent().map(Path::to_path_buf), &source)
+    }
+}
+
+struct Macro {
+    params: Vec<String>,
+    body:   String,
+    file:   usize,
+    base:   usize,  // Offset of the body in its file
+}
+
+// An argument of a macro call, expanded where the call was written
+struct Arg {
+    text:  String,
+    scope: Scope,
+}
+
+type Env = HashMap<String, Rc<Arg>>;
+
+// The text of each argument and its offset
+type Args = Vec<(String, usize)>;
+
+// Where a text being expanded is located, and what its parameters are
+#[derive(Clone)]
+struct Scope {
+    file: usize,
+    base: usize,
+    env:  Rc<Env>,
+    root: Option<Span>,
+}
+
+struct Loop {
+    start:  Option<usize>,  // The pointer position at `[`
+    named:  bool,           // Whether a cell name was used inside
+    origin: Origin,
+}
+
+struct SourceFile {
+    name: String,
+    dir:  Option<PathBuf>,
+    text: Rc<str>,
+}
+
+enum Unit {
+    Command(char),
+    Cell(String),
+    Param(Rc<Arg>),
+    Call(Rc<Macro>, Args),
+}
+
+struct Expander<'a> {
+    preprocessor: &'a Preprocessor,
+    files:        Vec<SourceFile>,
+    including:    Vec<usize>,
+    macros:       HashMap<String, Rc<Macro>>,
+    cells:        HashMap<String, usize>,
+    next_cell:    usize,
+    program:      String,
+    origins:      Vec<Origin>,
+    pointer:      Option<usize>,
+    loops:        Vec<Loop>,
+    depth:        usize,
+    expanded:     usize,
+}
+
+fn is_ident_start(c: char) -> bool {
+    c.is_ascii_alphabetic() || c == '_'
+}
+
+fn ident_len(text: &str) -> usize {
+    text.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_')).unwrap_or(text.len())
+}
+
+// A `*N` suffix at the start of `text`, and its length. N may be a parameter.
+fn repetition(text: &str, scope: &Scope) -> Option<(usize, usize)> {
+    let word = text.strip_prefix('*')?;
+    let len = ident_len(word);
+    Expander::resolve(&word[..len], scope).parse().ok().map(|count| (count, len + 1))
+}
+
+// The arguments in parentheses at the start of `text` with their offsets, and the length
+// of the whole list
+fn arguments(text: &str) -> Result<(Args, usize), &'static str> {
+    let mut args = Vec::new();
+    let mut depth = 0;
+    let mut start = 1;
+    for (idx, c) in text.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' | ',' if depth == 1 => {
+                let arg = &text[start..idx];
+                let trimmed = arg.trim_start();
+                let offset = start + arg.len() - trimmed.len();
+                if !trimmed.trim_end().is_empty() || c == ',' || !args.is_empty() {
+                    args.push((trimmed.trim_end().to_string(), offset));
+                }
+                start = idx + 1;
+                if c == ')' {
+                    return Ok((args, idx + 1));
+                }
+            },
+            ')' => depth -= 1,
+            _ => (),
+        }
+    }
+    Err("The arguments are never closed.")
+}
+
+impl<'a> Expander<'a> {
+    fn new(preprocessor: &'a Preprocessor) -> Self {
+        Expander {
+            preprocessor,
+            files:     Vec::new(),
+            including: Vec::new(),
+            macros:    HashMap::new(),
+            cells:     HashMap::new(),
+            next_cell: 0,
+            program:   String::new(),
+            origins:   Vec::new(),
+            pointer:   Some(0),
+            loops:     Vec::new(),
+            depth:     0,
+            expanded:  0,
+        }
+    }
+
+    fn run(mut self, name: &str, dir: Option<PathBuf>, source: &str) -> Result<Expansion, PreprocessError> {
+        self.files.push(SourceFile { name: name.to_string(), dir, text: Rc::from(source) });
+        self.including.push(0);
+        let scope = Scope { file: 0, base: 0, env: Rc::new(Env::new()), root: None };
+        self.expand(source, &scope)?;
+        if let Some(l) = self.loops.last() {
+            return Err(self.error("This loop is never closed.", l.origin.file, l.origin.span));
+        }
+        Ok(Expansion {
+            program:    self.program,
+            source_map: SourceMap {
+                files:   self.files.into_iter().map(|file| file.name).collect(),
+                origins: self.origins,
+            },
+        })
+    }
+
+    fn error(&self, message: &'static str, file: usize, span: Span) -> PreprocessError {
+        let (line, col) = span.line_col(&self.files[file].text);
+        PreprocessError { message, file: self.files[file].name.clone(), span, line, col }
+    }
+
+    fn expand(&mut self, text: &str, scope: &Scope) -> Result<(), PreprocessError> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            let span = Span::new(scope.base, scope.base + text.len());
+            return Err(self.error("Macros are expanded too deeply.", scope.file, span));
+        }
+        let mut pos = 0;
+        let mut line_start = true;
+        while let Some(c) = text[pos..].chars().next() {
+            if c == '\n' {
+                line_start = true;
+            }
+            if c.is_whitespace() {
+                pos += c.len_utf8();
+                continue;
+            }
+            if line_start && c == '#' {
+                pos = self.directive(text, pos, scope)?;
+                continue;
+            }
+            line_start = false;
+
+            let start = pos;
+            let span = |end: usize| Span::new(scope.base + start, scope.base + end);
+            let (unit, mut end) = if is_command(c) {
+                (Unit::Command(c), pos + 1)
+            } else if c == '@' && text[pos + 1..].starts_with(is_ident_start) {
+                let len = ident_len(&text[pos + 1..]);
+                (Unit::Cell(text[pos + 1..pos + 1 + len].to_string()), pos + 1 + len)
+            } else if is_ident_start(c) {
+                let len = ident_len(&text[pos..]);
+                let name = &text[pos..pos + len];
+                if let Some(arg) = scope.env.get(name) {
+                    (Unit::Param(arg.clone()), pos + len)
+                } else if let Some(m) = self.macros.get(name).cloned() {
+                    let (args, args_len) = if text[pos + len..].starts_with('(') {
+                        arguments(&text[pos + len..])
+                            .map_err(|message| self.error(message, scope.file, span(pos + len)))?
+                    } else {
+                        (Vec::new(), 0)
+                    };
+                    if args.len() != m.params.len() {
+                        return Err(self.error("Wrong number of arguments.", scope.file, span(pos + len + args_len)));
+                    }
+                    let args = args.into_iter().map(|(arg, offset)| (arg, pos + len + offset)).collect();
+                    (Unit::Call(m, args), pos + len + args_len)
+                } else {
+                    pos += len;
+                    continue;
+                }
+            } else {
+                pos += c.len_utf8();
+                continue;
+            };
+            let count = match repetition(&text[end..], scope) {
+                Some((count, len)) => {
+                    end += len;
+                    count
+                },
+                None => 1,
+            };
+            pos = end;
+
+            let span = span(end);
+            if count > MAX_COUNT {
+                return Err(self.error("The repetition count is too large.", scope.file, span));
+            }
+            let origin = Origin { file: scope.file, span, root: scope.root.unwrap_or(span) };
+            let inner = |file: usize, base: usize, env: Rc<Env>| Scope { file, base, env, root: Some(origin.root) };
+            for _ in 0..count {
+                self.expanded += 1;
+                if self.expanded > MAX_EXPANSION {
+                    return Err(self.error("The program expands too much.", scope.file, span));
+                }
+                match &unit {
+                    Unit::Command(c) => self.emit(*c, origin).map_err(|message| self.error(message, scope.file, span))?,
+                    Unit::Cell(name) => {
+                        let cell = *self.cells.get(&Expander::resolve(name, scope))
+                            .ok_or_else(|| self.error("Unknown cell name.", scope.file, span))?;
+                        self.move_to(cell, origin).map_err(|message| self.error(message, scope.file, span))?;
+                    },
+                    Unit::Param(arg) => self.expand(&arg.text, &arg.scope)?,
+                    Unit::Call(m, args) => {
+                        let env: Env = m.params.iter().zip(args.iter())
+                            .map(|(param, (text, offset))| {
+                                let scope = Scope { base: scope.base + offset, ..scope.clone() };
+                                (param.clone(), Rc::new(Arg { text: text.clone(), scope }))
+                            })
+                            .collect();
+                        self.expand(&m.body, &inner(m.file, m.base, Rc::new(env)))?;
+                    },
+                }
+            }
+        }
+        self.depth -= 1;
+        Ok(())
+    }
+
+    // Execute the directive at `pos`, and return the position after it
+    fn directive(&mut self, text: &str, pos: usize, scope: &Scope) -> Result<usize, PreprocessError> {
+        let line_end = text[pos..].find('\n').map_or(text.len(), |len| pos + len);
+        let line = &text[pos..line_end];
+        let span = Span::new(scope.base + pos, scope.base + line_end);
+        let keyword_len = 1 + ident_len(&line[1..]);
+        let rest = line[keyword_len..].trim();
+        let rest_start = pos + line.len() - line[keyword_len..].trim_start().len();
+        let error = |message| self.error(message, scope.file, span);
+        match &line[1..keyword_len] {
+            "define" => {
+                let name_len = ident_len(rest);
+                if name_len == 0 {
+                    return Err(error("A macro needs a name."));
+                }
+                let name = rest[..name_len].to_string();
+                let mut body = rest[name_len..].trim_start();
+                let mut params = Vec::new();
+                if body.starts_with('(') {
+                    let (args, len) = arguments(body).map_err(error)?;
+                    for (param, _) in args {
+                        if ident_len(&param) != param.len() || param.is_empty() {
+                            return Err(error("A parameter must be a name."));
+                        }
+                        params.push(param);
+                    }
+                    body = body[len..].trim_start();
+                }
+                let (body, base, end) = if body.is_empty() {
+                    let start = (line_end + 1).min(text.len());
+                    let mut end = start;
+                    loop {
+                        if end >= text.len() {
+                            return Err(error("The macro is never ended with #end."));
+                        }
+                        let next = text[end..].find('\n').map_or(text.len(), |len| end + len);
+                        if text[end..next].trim() == "#end" {
+                            break (text[start..end].to_string(), start, next);
+                        }
+                        end = next + 1;
+                    }
+                } else {
+                    // `rest` and so `body` end before any trailing space or `\r`
+                    (body.to_string(), rest_start + rest.len() - body.len(), line_end)
+                };
+                let m = Macro { params, body, file: scope.file, base: scope.base + base };
+                self.macros.insert(name, Rc::new(m));
+                Ok(end)
+            },
+            "include" => {
+                let name = rest.trim_matches('"');
+                let file = match self.load(name, scope.file) {
+                    Ok(file) => file,
+                    Err(message) => return Err(self.error(message, scope.file, span)),
+                };
+                if self.including.contains(&file) {
+                    return Err(self.error("The file includes itself.", scope.file, span));
+                }
+                self.including.push(file);
+                let text = self.files[file].text.clone();
+                let root = Some(scope.root.unwrap_or(span));
+                self.expand(&text, &Scope { file, base: 0, env: Rc::new(Env::new()), root })?;
+                self.including.pop();
+                Ok(line_end)
+            },
+            "cell" => {
+                let mut words = rest.split_whitespace();
+                let name = words.next().filter(|name| ident_len(name) == name.len())
+                    .ok_or_else(|| error("A cell needs a name."))?;
+                let cell = match words.next() {
+                    Some(index) => index.parse().map_err(|_| error("The index of a cell must be a number."))?,
+                    None => self.next_cell,
+                };
+                self.next_cell = self.next_cell.max(cell + 1);
+                self.cells.insert(name.to_string(), cell);
+                Ok(line_end)
+            },
+            "at" => {
+                let cell = match self.cells.get(rest) {
+                    Some(&cell) => cell,
+                    None => rest.parse().map_err(|_| error("Unknown cell name."))?,
+                };
+                self.pointer = Some(cell);
+                Ok(line_end)
+            },
+            // Any other line starting with `#` is a comment
+            _ => Ok(line_end),
+        }
+    }
+
+    fn load(&mut self, name: &str, from: usize) -> Result<usize, &'static str> {
+        if let Some(idx) = self.files.iter().position(|file| file.name == name) {
+            return Ok(idx);
+        }
+        if let Some(text) = self.preprocessor.files.get(name) {
+            self.files.push(SourceFile { name: name.to_string(), dir: None, text: Rc::from(text.as_str()) });
+            return Ok(self.files.len() - 1);
+        }
+        let dirs = self.files[from].dir.iter().chain(self.preprocessor.include_dirs.iter());
+        for dir in dirs {
+            let path = dir.join(name);
+            if let Ok(text) = fs::read_to_string(&path) {
+                let name = path.display().to_string();
+                if let Some(idx) = self.files.iter().position(|file| file.name == name) {
+                    return Ok(idx);
+                }
+                let dir = path.parent().map(Path::to_path_buf);
+                self.files.push(SourceFile { name, dir, text: Rc::from(text) });
+                return Ok(self.files.len() - 1);
+            }
+        }
+        Err("The included file is not found.")
+    }
+
+    // A parameter used as a cell name or count stands for the text of its argument
+    fn resolve(name: &str, scope: &Scope) -> String {
+        match scope.env.get(name) {
+            Some(arg) => Expander::resolve(arg.text.trim(), &arg.scope),
+            None => name.to_string(),
+        }
+    }
+
+    fn emit(&mut self, c: char, origin: Origin) -> Result<(), &'static str> {
+        match c {
+            '>' => self.pointer = self.pointer.map(|pointer| pointer + 1),
+            '<' => {
+                self.pointer = match self.pointer {
+                    Some(0) => return Err("The pointer moves left of the first cell."),
+                    pointer => pointer.map(|pointer| pointer - 1),
+                }
+            },
+            '[' => self.loops.push(Loop { start: self.pointer, named: false, origin }),
+            ']' => {
+                let l = self.loops.pop().ok_or("This bracket has no opening bracket.")?;
+                if l.start != self.pointer {
+                    if l.named {
+                        return Err("A cell name is used in a loop which moves the pointer.");
+                    }
+                    self.pointer = None;
+                }
+            },
+            _ => (),
+        }
+        self.program.push(c);
+        self.origins.push(origin);
+        Ok(())
+    }
+
+    fn move_to(&mut self, cell: usize, origin: Origin) -> Result<(), &'static str> {
+        let pointer = self.pointer.ok_or("The pointer position is unknown here; declare it with #at.")?;
+        for l in self.loops.iter_mut() {
+            l.named = true;
+        }
+        let (c, n) = if cell >= pointer { ('>', cell - pointer) } else { ('<', pointer - cell) };
+        for _ in 0..n {
+            self.emit(c, origin)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Brainfuck;
+
+    fn expand(source: &str) -> String {
+        Preprocessor::new().run(source).unwrap().program
+    }
+
+    #[test]
+    fn macros_and_repetition() {
+        assert_eq!(expand("print A: +*65 ."), "+++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++.");
+        assert_eq!(expand("#define twice(x) x x\ntwice(+*3) twice(>)*2"), "++++++>>>>");
+        let source = "#define clear\n  [-]  zero the cell\n#end\n+ clear >clear()";
+        assert_eq!(expand(source), "+[-]>[-]");
+        // Arguments are expanded where the call is written
+        assert_eq!(expand("#define a(x) x\n#define b(x) a(x x)\nb(-)"), "--");
+        assert_eq!(expand("#define add(n) +*n\nadd(3)"), "+++");
+    }
+
+    #[test]
+    fn cell_names() {
+        let source = "#cell a\n#cell b 3\n#define move(from, to) @from[-@to+@from]\n@b +++ move(b, a) @a .";
+        let program = expand(source);
+        assert_eq!(program, ">>>+++[-<<<+>>>]<<<.");
+        let mut bf = Brainfuck::new(program).unwrap();
+        bf.step_loop().unwrap();
+        assert_eq!(bf.memory()[..4], [3, 0, 0, 0]);
+
+        let error = Preprocessor::new().run("#cell a 2\n+[>]\n@a").unwrap_err();
+        assert_eq!((error.message, error.line, error.col), ("The pointer position is unknown here; declare it with #at.", 3, 1));
+        assert_eq!(expand("#cell a 2\n+[>]\n#at 5\n@a"), "+[>]<<<");
+    }
+
+    #[test]
+    fn includes_and_source_map() {
+        let mut preprocessor = Preprocessor::new();
+        preprocessor.add_file("lib.bf", "#define out .\n");
+        let expansion = preprocessor.run("#include \"lib.bf\"\n+ out").unwrap();
+        assert_eq!(expansion.program, "+.");
+        let map = &expansion.source_map;
+        assert_eq!(map.origin(0), Some(Origin { file: 0, span: Span::new(18, 19), root: Span::new(18, 19) }));
+        let origin = map.origin(1).unwrap();
+        assert_eq!((map.file_name(&origin), origin.span, origin.root), ("lib.bf", Span::new(12, 13), Span::new(20, 23)));
+
+        // Trailing space and CRLF line endings don't shift the spans of a macro
+        for source in ["#define out . \n+ out", "#define out .\r\n+ out"] {
+            let origin = Preprocessor::new().run(source).unwrap().source_map.origin(1).unwrap();
+            assert_eq!(origin.span, Span::new(12, 13));
+        }
+
+        preprocessor.add_file("loop.bf", "#include loop.bf");
+        assert_eq!(preprocessor.run("#include loop.bf").unwrap_err().message, "The file includes itself.");
+    }
+
+    #[test]
+    fn errors() {
+        let error = |source| Preprocessor::new().run(source).unwrap_err().to_string();
+        assert_eq!(error("#define f(x) x\n f(+, -)"), "<main>:2:2: error: Wrong number of arguments.");
+        assert_eq!(error("#define f f\nf"), "<main>:1:11: error: Macros are expanded too deeply.");
+        assert_eq!(error("+[\n"), "<main>:1:2: error: This loop is never closed.");
+        assert_eq!(error("+*1000000000"), "<main>:1:1: error: The repetition count is too large.");
+        assert_eq!(error("#define f +*1000\n#define g f*1000\ng*1000"), "<main>:1:11: error: The program expands too much.");
+    }
+}
@@ -0,0 +1,190 @@
+use std::fmt;
+
+use crate::{Brainfuck, Inst, Observer, Span, StepEvent};
+
+/// An observer counting the executions of every instruction and loop.
+///
+/// ```
+/// use rusty_brainfuck::{Brainfuck, Profiler};
+///
+/// let bf = Brainfuck::new(String::from("++[>+<-]")).unwrap();
+/// let mut bf = bf.with_observer(Profiler::new());
+/// bf.step_loop().unwrap();
+/// let profile = bf.observer().report(&bf);
+/// assert_eq!(profile.loops[0].iterations, 2);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Profiler {
+    counts:      Vec<u64>,
+    taken:       Vec<u64>,  // How many times a jump instruction entered (or re-entered) its loop body
+    max_pointer: usize,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Profiler::default()
+    }
+
+    fn count(counts: &[u64], pc: usize) -> u64 {
+        counts.get(pc).copied().unwrap_or(0)
+    }
+
+    // Map the counters back to the instructions and source spans of `bf`
+    pub fn report<O: Observer>(&self, bf: &Brainfuck<O>) -> Profile {
+        let insts = bf.insts();
+        let spans = bf.spans();
+        let instructions = insts.iter().zip(spans).enumerate()
+            .map(|(pc, (inst, span))| InstProfile {
+                pc,
+                inst:  *inst,
+                span:  *span,
+                count: Profiler::count(&self.counts, pc),
+            })
+            .collect();
+
+        let loops = insts.iter().enumerate()
+            .filter_map(|(open, inst)| match inst {
+                Inst::Jpf(close) => Some((open, *close)),
+                _ => None,
+            })
+            .map(|(open, close)| LoopProfile {
+                open,
+                close,
+                span:       Span::new(spans[open].start, spans[close].end),
+                entries:    Profiler::count(&self.counts, open),
+                // A non-zero cell at `[` enters the body, and at `]` jumps back into it
+                iterations: Profiler::count(&self.taken, open) + Profiler::count(&self.taken, close),
+                steps:      (open..=close).map(|pc| Profiler::count(&self.counts, pc)).sum(),
+            })
+            .collect();
+
+        Profile {
+            steps: self.counts.iter().sum(),
+            instructions,
+            loops,
+            max_pointer: self.max_pointer,
+        }
+    }
+}
+
+impl Observer for Profiler {
+    fn on_step(&mut self, event: &StepEvent) {
+        if event.pc >= self.counts.len() {
+            self.counts.resize(event.pc + 1, 0);
+            self.taken.resize(event.pc + 1, 0);
+        }
+        self.counts[event.pc] += 1;
+        // Jumps don't change the cell, so it still tells whether the body is run
+        if let Inst::Jpf(_) | Inst::Jpb(_) = event.inst {
+            if event.cell != 0 {
+                self.taken[event.pc] += 1;
+            }
+        }
+        self.max_pointer = self.max_pointer.max(event.pointer);
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct InstProfile {
+    pub pc:    usize,
+    pub inst:  Inst,
+    pub span:  Span,
+    pub count: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LoopProfile {
+    pub open:       usize,  // pc of `[`
+    pub close:      usize,  // pc of `]`
+    pub span:       Span,
+    pub entries:    u64,    // How many times `[` was reached
+    pub iterations: u64,    // How many times the body was run
+    pub steps:      u64,    // Steps executed inside the loop, nested loops included
+}
+
+/// The result of a profiled run, see `Profiler::report`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Profile {
+    pub steps:        u64,
+    pub instructions: Vec<InstProfile>,
+    pub loops:        Vec<LoopProfile>,
+    pub max_pointer:  usize,  // The rightmost cell the pointer reached
+}
+
+impl Profile {
+    // Loops sorted by the number of steps spent in them, the hottest first
+    pub fn hottest_loops(&self, n: usize) -> Vec<&LoopProfile> {
+        let mut loops: Vec<&LoopProfile> = self.loops.iter().collect();
+        loops.sort_by(|a, b| b.steps.cmp(&a.steps).then(a.open.cmp(&b.open)));
+        loops.truncate(n);
+        loops
+    }
+
+    // A human readable report, with positions resolved against `source`
+    pub fn report(&self, source: &str) -> String {
+        ProfileReport { profile: self, source }.to_string()
+    }
+}
+
+struct ProfileReport<'a> {
+    profile: &'a Profile,
+    source:  &'a str,
+}
+
+impl<'a> fmt::Display for ProfileReport<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let profile = self.profile;
+        writeln!(f, "steps:       {}", profile.steps)?;
+        writeln!(f, "tape extent: 0..={}", profile.max_pointer)?;
+        writeln!(f, "hottest loops:")?;
+        for lp in profile.hottest_loops(10) {
+            let (line, col) = lp.span.line_col(self.source);
+            writeln!(f, "  {:>5}:{:<4} steps {:>12}  entries {:>10}  iterations {:>10}",
+                     line, col, lp.steps, lp.entries, lp.iterations)?;
+        }
+        writeln!(f, "instructions:")?;
+        for inst in &profile.instructions {
+            let (line, col) = inst.span.line_col(self.source);
+            writeln!(f, "  {:>5}:{:<4} {:<12} {:>12}", line, col, format!("{:?}", inst.inst), inst.count)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Brainfuck, Inst, Profiler, Span};
+
+    #[test]
+    fn counts_instructions_and_loops() {
+        let program = "++[>+++[>+<-]<-]";
+        let mut bf = Brainfuck::new(String::from(program)).unwrap().with_observer(Profiler::new());
+        bf.step_loop().unwrap();
+        let profile = bf.observer().report(&bf);
+
+        assert_eq!(profile.instructions[0].inst, Inst::Add(2));
+        assert_eq!(profile.instructions[0].count, 1);
+        assert_eq!(profile.max_pointer, 2);
+
+        let outer = &profile.loops[0];
+        assert_eq!(outer.span, Span::new(2, 16));
+        assert_eq!(outer.entries, 1);
+        assert_eq!(outer.iterations, 2);
+
+        let inner = &profile.loops[1];
+        assert_eq!(inner.entries, 2);
+        assert_eq!(inner.iterations, 6);
+        assert_eq!(profile.hottest_loops(1)[0].open, outer.open);
+        assert_eq!(profile.steps, outer.steps + 1);
+    }
+
+    #[test]
+    fn report_uses_line_and_column() {
+        let program = "+\n  [-]\n  [>]";
+        let mut bf = Brainfuck::new(String::from(program)).unwrap().with_observer(Profiler::new());
+        bf.step_loop().unwrap();
+        let report = bf.observer().report(&bf).report(program);
+        assert!(report.contains("3:3"));
+        assert!(report.contains("Stz"));
+    }
+}
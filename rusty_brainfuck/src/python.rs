@@ -0,0 +1,172 @@
+// The `rusty_brainfuck` Python extension module, built with the python feature. Copy
+// librusty_brainfuck.so to rusty_brainfuck.so, where Python finds it:
+//
+//     bf = rusty_brainfuck.Brainfuck(",[.,]", tape_size=100, cell_width=16, eof="minus-one")
+//     assert bf.run(b"hi") == b"hi"
+use std::collections::VecDeque;
+
+use pyo3::create_exception;
+use pyo3::exceptions::{PyEOFError, PyIndexError, PyRuntimeError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+
+use crate::{Brainfuck, CellWidth, EofPolicy};
+
+create_exception!(rusty_brainfuck, BrainfuckError, PyRuntimeError, "An error of the program, or of its brackets.");
+
+// Steps between checks for Ctrl-C
+const SIGNAL_INTERVAL: u64 = 1 << 16;
+
+/// A Brainfuck machine with wrapping cells of `cell_width` bits (8, 16 or 32), run from Python.
+///
+/// `.` writes the lowest byte of a cell. `eof` is what `,` stores once the input is used up: "zero", "minus-one", "unchanged", or
+/// "error" to raise EOFError. Without `fuel`, the program may run forever.
+#[pyclass(name = "Brainfuck", module = "rusty_brainfuck")]
+struct PyBrainfuck {
+    bf:     Brainfuck,
+    eof:    Option<EofPolicy>,  // None raises EOFError
+    input:  VecDeque<u8>,
+    output: Vec<u8>,
+}
+
+impl PyBrainfuck {
+    // Answer a waiting `,` from the queue, or else by the EOF policy
+    fn answer_input(&mut self) -> PyResult<()> {
+        let answered = match (self.input.pop_front(), self.eof) {
+            (Some(byte), _) => self.bf.set_input_byte(byte),
+            (None, Some(policy)) => self.bf.set_eof(policy),
+            (None, None) => return Err(PyEOFError::new_err("The program reads past the end of its input.")),
+        };
+        answered.map_err(BrainfuckError::new_err)
+    }
+
+    fn step_once(&mut self) -> PyResult<()> {
+        if self.bf.is_input_mode() {
+            return self.answer_input();
+        }
+        self.bf.step().map_err(BrainfuckError::new_err)?;
+        self.output.extend(self.bf.pop_result().chars().map(|c| c as u8));
+        Ok(())
+    }
+
+    fn finished(&self) -> bool {
+        self.bf.reach_eop() && !self.bf.is_input_mode()
+    }
+}
+
+#[pymethods]
+impl PyBrainfuck {
+    #[new]
+    #[pyo3(signature = (program, tape_size = 30_000, cell_width = 8, eof = "zero", fuel = None))]
+    fn new(program: &str, tape_size: usize, cell_width: u32, eof: &str, fuel: Option<u64>) -> PyResult<Self> {
+        let cell_width = CellWidth::from_bits(cell_width)
+            .ok_or_else(|| PyValueError::new_err("The cell width is 8, 16 or 32."))?;
+        let eof = match eof {
+            "error" => None,
+            name => Some(EofPolicy::from_name(name).ok_or_else(|| {
+                PyValueError::new_err("The EOF policy is one of zero, minus-one, unchanged and error.")
+            })?),
+        };
+        let mut bf = Brainfuck::new(program.to_string()).map_err(BrainfuckError::new_err)?;
+        bf.set_tape_size(tape_size).map_err(PyValueError::new_err)?;
+        bf.set_cell_width(cell_width);
+        bf.set_fuel(fuel);
+        Ok(PyBrainfuck { bf, eof, input: VecDeque::new(), output: Vec::new() })
+    }
+
+    /// Run to the end with `input` after any fed before, and return the output not yet read.
+    #[pyo3(signature = (input = b"".as_slice()))]
+    fn run<'py>(&mut self, py: Python<'py>, input: &[u8]) -> PyResult<Bound<'py, PyBytes>> {
+        self.input.extend(input);
+        let mut steps = 0u64;
+        while !self.finished() {
+            self.step_once()?;
+            steps += 1;
+            if steps.is_multiple_of(SIGNAL_INTERVAL) {
+                py.check_signals()?;
+            }
+        }
+        self.read_output(py)
+    }
+
+    /// Take up to `count` steps, and return whether the program ended.
+    #[pyo3(signature = (count = 1))]
+    fn step(&mut self, count: u64) -> PyResult<bool> {
+        for _ in 0..count {
+            if self.finished() {
+                break;
+            }
+            self.step_once()?;
+        }
+        Ok(self.finished())
+    }
+
+    /// Queue input for the `,` commands to come.
+    fn feed(&mut self, input: &[u8]) {
+        self.input.extend(input);
+    }
+
+    /// The output since the last read.
+    fn read_output<'py>(&mut self, py: Python<'py>) -> PyResult<Bound<'py, PyBytes>> {
+        let output = PyBytes::new(py, &self.output);
+        self.output.clear();
+        Ok(output)
+    }
+
+    /// The cells, as bytes: `cell_width / 8` little-endian bytes each.
+    #[getter]
+    fn tape<'py>(&self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        let size = self.bf.cell_width().bits() as usize / 8;
+        let bytes: Vec<u8> = self.bf.memory().iter().flat_map(|cell| cell.to_le_bytes()[..size].to_vec()).collect();
+        PyBytes::new(py, &bytes)
+    }
+
+    /// The value of a cell.
+    fn __getitem__(&self, index: usize) -> PyResult<u32> {
+        self.bf.memory().get(index).copied().ok_or_else(|| PyIndexError::new_err("No such cell."))
+    }
+
+    /// The index of the current cell.
+    #[getter]
+    fn pointer(&self) -> usize {
+        self.bf.pointer()
+    }
+
+    /// The index of the next instruction.
+    #[getter]
+    fn pc(&self) -> usize {
+        self.bf.pc()
+    }
+
+    /// Whether the program ended.
+    #[getter(finished)]
+    fn is_finished(&self) -> bool {
+        self.finished()
+    }
+
+    /// The number of cells.
+    #[getter]
+    fn tape_size(&self) -> usize {
+        self.bf.tape_size()
+    }
+
+    /// The number of bits of a cell.
+    #[getter]
+    fn cell_width(&self) -> u32 {
+        self.bf.cell_width().bits()
+    }
+
+    /// The steps left, or None without a limit.
+    #[getter]
+    fn fuel(&self) -> Option<u64> {
+        self.bf.fuel()
+    }
+}
+
+#[pymodule]
+#[pyo3(name = "rusty_brainfuck")]
+fn python_module(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_class::<PyBrainfuck>()?;
+    module.add("BrainfuckError", module.py().get_type::<BrainfuckError>())?;
+    Ok(())
+}
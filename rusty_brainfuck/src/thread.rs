@@ -0,0 +1,80 @@
+use std::collections::VecDeque;
+
+use crate::{Counter, Pointer};
+
+// A thread of Brainfork waiting for its turn. The running thread lives in the `Brainfuck` itself.
+#[derive(Clone)]
+pub(crate) struct Thread {
+    pub(crate) id:         usize,
+    pub(crate) pointer:    Pointer,
+    pub(crate) counter:    Counter,
+    pub(crate) call_stack: Vec<usize>,
+}
+
+// Round-robin scheduling of the threads over the shared tape
+#[derive(Clone)]
+pub(crate) struct Scheduler {
+    pub(crate) current: usize,              // Id of the running thread
+    pub(crate) waiting: VecDeque<Thread>,   // In the order they will run
+    pub(crate) next_id: usize,
+    pub(crate) quantum: usize,              // Steps a thread runs before the next one takes over
+    pub(crate) slice:   usize,              // Steps the running thread took in its quantum
+}
+
+impl Scheduler {
+    pub(crate) fn new(quantum: usize) -> Self {
+        Scheduler {
+            current: 0,
+            waiting: VecDeque::new(),
+            next_id: 1,
+            quantum: quantum.max(1),
+            slice:   0,
+        }
+    }
+
+    pub(crate) fn reset(&mut self) {
+        *self = Scheduler::new(self.quantum);
+    }
+
+    // Queue a new thread behind the waiting ones, and return its id.
+    pub(crate) fn spawn(&mut self, pointer: Pointer, counter: Counter, call_stack: Vec<usize>) -> usize {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.waiting.push_back(Thread { id, pointer, counter, call_stack });
+        id
+    }
+
+    // Count a step of the running thread. Alone, it never has to give way.
+    pub(crate) fn tick(&mut self) {
+        if !self.waiting.is_empty() {
+            self.slice += 1;
+        }
+    }
+
+    // Whether the running thread has to give way, when it isn't finished
+    pub(crate) fn expired(&self) -> bool {
+        !self.waiting.is_empty() && self.slice >= self.quantum
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_robin_order() {
+        let mut scheduler = Scheduler::new(2);
+        let counter = Counter::new(0, 1);
+        assert_eq!(scheduler.spawn(Pointer(1), counter, Vec::new()), 1);
+        assert_eq!(scheduler.spawn(Pointer(2), counter, Vec::new()), 2);
+        let ids: Vec<usize> = scheduler.waiting.iter().map(|thread| thread.id).collect();
+        assert_eq!(ids, vec![1, 2]);
+        scheduler.tick();
+        assert!(!scheduler.expired());
+        scheduler.tick();
+        assert!(scheduler.expired());
+        scheduler.reset();
+        assert!(scheduler.waiting.is_empty());
+        assert_eq!(scheduler.quantum, 2);
+    }
+}
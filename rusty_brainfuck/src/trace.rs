@@ -0,0 +1,187 @@
+use std::io::Write;
+
+use crate::{Observer, Span, StepEvent};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TraceFormat {
+    Text,        // One human readable line per event
+    JsonLines,   // One JSON object per event
+}
+
+/// Restricts which steps are written by a `Tracer`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TraceFilter {
+    pub range: Option<Span>,  // Only steps whose instruction overlaps this source range
+    pub every: u64,           // Only every Nth step (1 traces all steps)
+}
+
+impl Default for TraceFilter {
+    fn default() -> Self {
+        TraceFilter {
+            range: None,
+            every: 1,
+        }
+    }
+}
+
+/// An observer writing a record for every step and I/O event.
+///
+/// Steps are recorded after they are executed, so `pointer` and `cell` show
+/// the effect of the instruction. I/O events are not thinned out by `every`.
+/// Once a Brainfork thread was forked, steps also show the thread taking them.
+/// Write errors don't stop the interpreter; they are reported by `finish`.
+pub struct Tracer<W: Write> {
+    writer: W,
+    format: TraceFormat,
+    filter: TraceFilter,
+    steps:  u64,
+    span:   Span,  // The span of the last step, which I/O events are attributed to
+    forked: bool,
+    failed: bool,
+}
+
+impl<W: Write> Tracer<W> {
+    pub fn new(writer: W, format: TraceFormat, filter: TraceFilter) -> Self {
+        Tracer {
+            writer,
+            format,
+            filter,
+            steps:  0,
+            span:   Span::new(0, 0),
+            forked: false,
+            failed: false,
+        }
+    }
+
+    pub fn writer(&self) -> &W {
+        &self.writer
+    }
+
+    // Flush the writer and hand it back, or report that a record couldn't be written
+    pub fn finish(mut self) -> Result<W, &'static str> {
+        if self.writer.flush().is_err() || self.failed {
+            Err("Failed to write the trace.")
+        } else {
+            Ok(self.writer)
+        }
+    }
+
+    fn in_range(&self, span: Span) -> bool {
+        match self.filter.range {
+            Some(range) => span.start < range.end && range.start < span.end,
+            None => true,
+        }
+    }
+
+    fn io(&mut self, event: &str, value: u8) {
+        if !self.in_range(self.span) {
+            return;
+        }
+        let written = match self.format {
+            TraceFormat::Text => writeln!(
+                self.writer, "{:>10} {:<6} {:>3} {:?}",
+                self.steps, event, value, value as char),
+            TraceFormat::JsonLines => writeln!(
+                self.writer, "{{\"step\":{},\"event\":\"{}\",\"value\":{}}}",
+                self.steps, event, value),
+        };
+        self.failed |= written.is_err();
+    }
+}
+
+impl<W: Write> Observer for Tracer<W> {
+    fn on_step(&mut self, event: &StepEvent) {
+        self.steps += 1;
+        self.span = event.span;
+        if !(self.steps - 1).is_multiple_of(self.filter.every.max(1)) || !self.in_range(event.span) {
+            return;
+        }
+        let inst = format!("{:?}", event.inst);
+        let written = match (self.format, self.forked) {
+            (TraceFormat::Text, false) => writeln!(
+                self.writer, "{:>10} pc={:<6} {:<12} ptr={:<6} cell={}",
+                self.steps, event.pc, inst, event.pointer, event.cell),
+            (TraceFormat::Text, true) => writeln!(
+                self.writer, "{:>10} pc={:<6} {:<12} ptr={:<6} cell={:<3} thread={}",
+                self.steps, event.pc, inst, event.pointer, event.cell, event.thread),
+            (TraceFormat::JsonLines, false) => writeln!(
+                self.writer,
+                "{{\"step\":{},\"pc\":{},\"inst\":\"{}\",\"start\":{},\"end\":{},\"pointer\":{},\"cell\":{}}}",
+                self.steps, event.pc, inst, event.span.start, event.span.end, event.pointer, event.cell),
+            (TraceFormat::JsonLines, true) => writeln!(
+                self.writer,
+                "{{\"step\":{},\"pc\":{},\"inst\":\"{}\",\"start\":{},\"end\":{},\"pointer\":{},\"cell\":{},\"thread\":{}}}",
+                self.steps, event.pc, inst, event.span.start, event.span.end, event.pointer, event.cell, event.thread),
+        };
+        self.failed |= written.is_err();
+    }
+
+    fn on_output(&mut self, byte: u8) {
+        self.io("output", byte);
+    }
+
+    fn on_input(&mut self, byte: u8) {
+        self.io("input", byte);
+    }
+
+    fn on_fork(&mut self, parent: usize, child: usize) {
+        self.forked = true;
+        if !self.in_range(self.span) {
+            return;
+        }
+        let written = match self.format {
+            TraceFormat::Text => writeln!(self.writer, "{:>10} fork   {} -> {}", self.steps, parent, child),
+            TraceFormat::JsonLines => writeln!(
+                self.writer, "{{\"step\":{},\"event\":\"fork\",\"parent\":{},\"child\":{}}}",
+                self.steps, parent, child),
+        };
+        self.failed |= written.is_err();
+    }
+
+    fn on_halt(&mut self) {
+        self.failed |= self.writer.flush().is_err();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Brainfuck, Dialect, Span, TraceFilter, TraceFormat, Tracer};
+
+    fn run_traced(program: &str, format: TraceFormat, filter: TraceFilter) -> Vec<String> {
+        let bf = Brainfuck::with_dialect(String::from(program), &Dialect::brainfork()).unwrap();
+        let mut bf = bf.with_observer(Tracer::new(Vec::new(), format, filter));
+        bf.step_loop().unwrap();
+        let (_, tracer) = bf.detach_observer();
+        let trace = tracer.finish().unwrap();
+        String::from_utf8(trace).unwrap().lines().map(String::from).collect()
+    }
+
+    #[test]
+    fn json_lines_trace() {
+        let lines = run_traced("++>.", TraceFormat::JsonLines, TraceFilter::default());
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[0], r#"{"step":1,"pc":0,"inst":"Add(2)","start":0,"end":2,"pointer":0,"cell":2}"#);
+        assert_eq!(lines[3], r#"{"step":3,"event":"output","value":0}"#);
+    }
+
+    #[test]
+    fn filtered_trace() {
+        let filter = TraceFilter { range: None, every: 2 };
+        let lines = run_traced("+>+>+>+", TraceFormat::Text, filter);
+        assert_eq!(lines.len(), 4);
+        assert!(lines[1].trim_start().starts_with("3 pc=2"));
+
+        let filter = TraceFilter { range: Some(Span::new(3, 5)), every: 1 };
+        let lines = run_traced("+ [->+<]", TraceFormat::Text, filter);
+        assert!(lines.iter().all(|line| !line.contains("Jpf")));
+        assert!(lines.iter().any(|line| line.contains("Shr(1)")));
+    }
+
+    #[test]
+    fn threads_in_trace() {
+        let lines = run_traced("Y+", TraceFormat::JsonLines, TraceFilter::default());
+        assert_eq!(lines[1], r#"{"step":1,"event":"fork","parent":0,"child":1}"#);
+        assert_eq!(lines[2], r#"{"step":2,"pc":1,"inst":"Add(1)","start":1,"end":2,"pointer":1,"cell":2,"thread":1}"#);
+        assert!(lines[3].ends_with(r#""cell":1,"thread":0}"#));
+    }
+}
@@ -0,0 +1,167 @@
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Output, Stdio};
+
+// Write `program` to a file of its own, so tests can run in parallel
+fn program_file(name: &str, program: &str) -> PathBuf {
+    let path = env::temp_dir().join(format!("bf-cli-{}-{}.bf", name, std::process::id()));
+    fs::write(&path, program).unwrap();
+    path
+}
+
+fn output(args: &[&str], input: &[u8]) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_bf"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(input).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+fn bf(args: &[&str], input: &[u8]) -> (i32, String, String) {
+    let output = output(args, input);
+    (
+        output.status.code().unwrap(),
+        String::from_utf8_lossy(&output.stdout).into_owned(),
+        String::from_utf8_lossy(&output.stderr).into_owned(),
+    )
+}
+
+#[test]
+fn run_with_input_and_eof() {
+    let cat = program_file("cat", ",[.,]");
+    let cat = cat.to_str().unwrap();
+    assert_eq!(bf(&["run", cat], b"hello"), (0, String::from("hello"), String::new()));
+    let (code, stdout, _) = bf(&["run", "--eof", "error", cat], b"hi");
+    assert_eq!((code, stdout.as_str()), (6, "hi"));
+}
+
+#[test]
+fn exit_codes() {
+    let spin = program_file("spin", "+[]");
+    assert_eq!(bf(&["run", "--fuel", "100", spin.to_str().unwrap()], b"").0, 5);
+    let left = program_file("left", "<");
+    let (code, _, stderr) = bf(&["run", left.to_str().unwrap()], b"");
+    assert_eq!(code, 1);
+    assert!(stderr.contains("1:1:"));
+    let open = program_file("open", "[");
+    assert_eq!(bf(&["run", open.to_str().unwrap()], b"").0, 3);
+    assert_eq!(bf(&["lint", open.to_str().unwrap()], b"").0, 1);
+    assert_eq!(bf(&["run", "--tape-size", "ten", "a.bf"], b"").0, 2);
+    assert_eq!(bf(&["run", "/nonexistent/a.bf"], b"").0, 4);
+}
+
+#[test]
+fn bytecode_runs() {
+    let hello = program_file("hello", "++++++++[>++++++++<-]>+.+.");
+    let bytecode = hello.with_extension("bfc");
+    let args = ["compile", "--target", "bytecode", "-o", bytecode.to_str().unwrap(), hello.to_str().unwrap()];
+    assert_eq!(bf(&args, b"").0, 0);
+    assert_eq!(bf(&["run", bytecode.to_str().unwrap()], b""), (0, String::from("AB"), String::new()));
+}
+
+#[test]
+fn cell_widths() {
+    // 256 wraps to 0 in 8-bit cells only
+    let wraps = program_file("wraps", &format!("{}[>+<[-]]>.", "+".repeat(256)));
+    let wraps = wraps.to_str().unwrap();
+    assert_eq!(output(&["run", wraps], b"").stdout, b"\x00");
+    assert_eq!(output(&["run", "--cell-width", "16", wraps], b"").stdout, b"\x01");
+    let (_, stdout, _) = bf(&["repl", "--cell-width", "16"], b"-\n:mem 0\n");
+    assert!(stdout.contains("00000  ffff "));
+    let (code, c, _) = bf(&["compile", "--cell-width", "32", wraps], b"");
+    assert_eq!(code, 0);
+    assert!(c.contains("uint32_t"));
+    assert_eq!(bf(&["run", "--cell-width", "12", wraps], b"").0, 2);
+}
+
+#[test]
+fn streams_raw_bytes() {
+    let copy = program_file("copy", ",.,.,.,.");
+    let output = output(&["run", copy.to_str().unwrap()], b"a\n\xff\x00");
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"a\n\xff\x00");
+}
+
+#[test]
+fn repl_keeps_the_tape() {
+    let lines = b"++++++++[>++++++++<-]\n>+.\n:mem 2\n<<\n+.\n";
+    let (code, stdout, _) = bf(&["repl"], lines);
+    assert_eq!(code, 0);
+    assert!(stdout.contains("A\n"));
+    assert!(stdout.contains("00000  00 41 00"));
+    assert!(stdout.contains("error: runtime error: Too small pointer"));
+    // The failed line is skipped, the pointer stays where it was
+    assert!(stdout.contains("B\n"));
+}
+
+#[test]
+fn repl_reset_keeps_tracing_and_save_keeps_breakpoints() {
+    let saved = env::temp_dir().join(format!("bf-cli-saved-{}.bf", std::process::id()));
+    let lines = format!(":trace on\n+\n:reset\n+>#+\n+\n:break 0\n:break 2\n:save {}\n", saved.display());
+    let (code, _, stderr) = bf(&["repl"], lines.as_bytes());
+    assert_eq!(code, 0);
+    // The steps after :reset are traced too
+    assert_eq!(stderr.lines().count(), 5);
+    // The breakpoint typed as `#` is toggled off, the one on pc 0 is added
+    assert_eq!(fs::read_to_string(&saved).unwrap(), "#+>+\n+\n");
+}
+
+#[test]
+fn fixtures_report() {
+    let dir = env::temp_dir().join(format!("bf-cli-fixtures-{}", std::process::id()));
+    fs::create_dir_all(dir.join("nested")).unwrap();
+    fs::write(dir.join("cat.bf"), ",[.,]").unwrap();
+    fs::write(dir.join("cat.in"), "meow").unwrap();
+    fs::write(dir.join("cat.out"), "meow").unwrap();
+    fs::write(dir.join("nested/one.bf"), ";; out: \\x02\n+.").unwrap();
+    let (code, stdout, _) = bf(&["test", dir.to_str().unwrap()], b"");
+    assert_eq!(code, 1);
+    assert!(stdout.contains("pass  ") && stdout.contains("FAIL  "));
+    assert!(stdout.contains("-\\x02\n+\\x01\n"));
+    fs::write(dir.join("nested/one.bf"), ";; out: \\x01\n+.").unwrap();
+    let (code, stdout, _) = bf(&["test", "--format", "junit", dir.to_str().unwrap()], b"");
+    assert_eq!(code, 0);
+    assert!(stdout.contains("<testsuite name=\"bf\" tests=\"2\" failures=\"0\" errors=\"0\""));
+    // A fixture which can't be loaded is an error of its own, and the others still run
+    fs::write(dir.join("nested/bad.bf"), ";; color: red\n+.").unwrap();
+    let (code, stdout, _) = bf(&["test", dir.to_str().unwrap()], b"");
+    assert_eq!(code, 1);
+    assert!(stdout.contains("ERROR ") && stdout.contains("bad: Unknown key in the header."));
+    assert!(stdout.ends_with("2 passed, 0 failed, 1 errors\n"));
+    assert_eq!(bf(&["test", "--input", "cat.in", dir.to_str().unwrap()], b"").0, 2);
+    // --dialect is the default of fixtures without a dialect of their own
+    let ook = program_file("ook-fixture", ";; out: \\x01\nOok. Ook. Ook! Ook.");
+    assert_eq!(bf(&["test", "--dialect", "ook", ook.to_str().unwrap()], b"").0, 0);
+    assert_eq!(bf(&["test", ook.to_str().unwrap()], b"").0, 1);
+}
+
+#[test]
+fn dialects_in_fmt_and_minify() {
+    let ook = program_file("ook", "Ook. Ook. Ook! Ook! Ook. Ook. Ook. Ook. Ook! Ook.");
+    let ook = ook.to_str().unwrap();
+    assert_eq!(bf(&["run", ook], b"").1, "\x02");
+    assert_eq!(bf(&["minify", ook], b""), (0, String::from("Ook. Ook. Ook. Ook. Ook! Ook.\n"), String::new()));
+    let (code, stdout, stderr) = bf(&["fmt", ook], b"");
+    assert_eq!((code, stdout.as_str()), (3, ""));
+    assert!(stderr.contains("fmt only formats Brainfuck, not ook"));
+    let fork = program_file("fork", "+Y.");
+    assert_eq!(bf(&["minify", "--dialect", "brainfork", fork.to_str().unwrap()], b"").0, 3);
+}
+
+#[test]
+fn generate_prints_the_text() {
+    let program = program_file("generated", "");
+    for tradeoff in ["size", "speed"].iter() {
+        let (code, generated, _) = bf(&["generate", "--tradeoff", tradeoff, "--max-cells", "2", "-"], b"Hi!\n");
+        assert_eq!(code, 0);
+        assert_eq!(generated.contains('['), *tradeoff == "size");
+        fs::write(&program, generated).unwrap();
+        assert_eq!(bf(&["run", program.to_str().unwrap()], b"").1, "Hi!\n");
+    }
+}
@@ -0,0 +1,325 @@
+// Random programs run through every engine, which must agree with a naive reference interpreter.
+// Set BF_DIFF_SEED and BF_DIFF_CASES to explore more programs than the default run does.
+extern crate rusty_brainfuck;
+
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+use rusty_brainfuck::{
+    compile, decode_bytecode, format_program, minify, Brainfuck, CellWidth, CompileConfig, FormatConfig, Target,
+    ERROR_OUT_OF_FUEL, ERROR_POINTER_LEFT, ERROR_POINTER_RIGHT,
+};
+
+const TAPE: usize = 32;
+const FUEL: u64 = 100_000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Error {
+    Left,
+    Right,
+    Fuel,
+    Other,
+}
+
+type Engine = fn(&str, &[u8], CellWidth) -> Run;
+
+#[derive(Debug, Clone, PartialEq)]
+struct Run {
+    output: Vec<u8>,
+    tape:   Option<Vec<u32>>,  // None when the engine can't show its tape
+    error:  Option<Error>,
+}
+
+// xorshift64*, so that a seed reproduces a failure without any dependency
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+}
+
+fn env_number(name: &str, default: u64) -> u64 {
+    env::var(name).ok().and_then(|value| value.parse().ok()).unwrap_or(default)
+}
+
+fn generate(rng: &mut Rng, len: usize, depth: usize) -> String {
+    let mut program = String::new();
+    while program.len() < len {
+        match rng.below(20) {
+            0..=4 => program += &"+".repeat(1 + rng.below(4)),
+            5..=7 => program += &"-".repeat(1 + rng.below(3)),
+            8..=11 => program += &">".repeat(1 + rng.below(3)),
+            12..=13 => program.push('<'),
+            14 => program.push('.'),
+            15 => program.push(','),
+            16 => program += "[-]",
+            _ if depth < 3 => {
+                // Loops that count their cell down usually end
+                let body_len = rng.below(len / 2 + 1);
+                let body = generate(rng, body_len, depth + 1);
+                program += &format!("[{}-]", body);
+            },
+            _ => program.push('#'),
+        }
+    }
+    program
+}
+
+// One command at a time, the way the language is defined
+fn reference(program: &str, input: &[u8], width: CellWidth) -> Run {
+    let code: Vec<u8> = program.bytes().collect();
+    let mut matches = vec![0; code.len()];
+    let mut open = Vec::new();
+    for (pc, &c) in code.iter().enumerate() {
+        match c {
+            b'[' => open.push(pc),
+            b']' => {
+                let start = open.pop().unwrap();
+                matches[start] = pc;
+                matches[pc] = start;
+            },
+            _ => (),
+        }
+    }
+    let (mut tape, mut pointer, mut pc, mut steps) = (vec![0u32; TAPE], 0, 0, 0);
+    let mut input = input.iter();
+    let mut output = Vec::new();
+    let mut error = None;
+    while pc < code.len() {
+        steps += 1;
+        if steps > FUEL {
+            error = Some(Error::Fuel);
+            break;
+        }
+        match code[pc] {
+            b'+' => tape[pointer] = if tape[pointer] == width.max() { 0 } else { tape[pointer] + 1 },
+            b'-' => tape[pointer] = if tape[pointer] == 0 { width.max() } else { tape[pointer] - 1 },
+            b'>' if pointer + 1 == TAPE => {
+                error = Some(Error::Right);
+                break;
+            },
+            b'>' => pointer += 1,
+            b'<' if pointer == 0 => {
+                error = Some(Error::Left);
+                break;
+            },
+            b'<' => pointer -= 1,
+            b'.' => output.push(tape[pointer] as u8),
+            b',' => tape[pointer] = input.next().map_or(0, |&byte| byte.into()),
+            b'[' if tape[pointer] == 0 => pc = matches[pc],
+            b']' if tape[pointer] != 0 => pc = matches[pc],
+            _ => (),
+        }
+        pc += 1;
+    }
+    Run { output, tape: Some(tape), error }
+}
+
+fn interpret(program: &str, input: &[u8], width: CellWidth) -> Run {
+    let mut bf = Brainfuck::new(String::from(program)).unwrap();
+    bf.set_tape_size(TAPE).unwrap();
+    bf.set_cell_width(width);
+    bf.set_fuel(Some(FUEL));
+    let mut input = input.iter();
+    let mut output = Vec::new();
+    let error = loop {
+        if bf.is_input_mode() {
+            match input.next() {
+                Some(&byte) => bf.set_input_byte(byte).unwrap(),
+                None => bf.set_eof(rusty_brainfuck::EofPolicy::Zero).unwrap(),
+            }
+            continue;
+        }
+        if bf.reach_eop() {
+            break None;
+        }
+        if let Err(err) = bf.step() {
+            break Some(match err {
+                ERROR_POINTER_LEFT => Error::Left,
+                ERROR_POINTER_RIGHT => Error::Right,
+                ERROR_OUT_OF_FUEL => Error::Fuel,
+                _ => Error::Other,
+            });
+        }
+        output.extend(bf.pop_result().chars().map(|c| c as u8));
+    };
+    Run { output, tape: Some(bf.memory().to_vec()), error }
+}
+
+fn bytecode(program: &str, input: &[u8], width: CellWidth) -> Run {
+    let config = CompileConfig { target: Target::Bytecode, ..CompileConfig::default() };
+    let bytes = compile(program, &config).unwrap();
+    interpret(&decode_bytecode(&bytes).unwrap(), input, width)
+}
+
+fn formatted(program: &str, input: &[u8], width: CellWidth) -> Run {
+    interpret(&format_program(program, &FormatConfig::default()).unwrap(), input, width)
+}
+
+fn minified(program: &str, input: &[u8], width: CellWidth) -> Run {
+    interpret(&minify(program).unwrap(), input, width)
+}
+
+fn c_compiler() -> Option<String> {
+    let cc = env::var("CC").unwrap_or_else(|_| String::from("cc"));
+    let found = Command::new(&cc).arg("--version").stdout(Stdio::null()).stderr(Stdio::null()).status();
+    found.is_ok_and(|status| status.success()).then_some(cc)
+}
+
+// The C program has no bounds checks and no tape to compare, so only the output of runs
+// without errors is
+fn c_backend(program: &str, input: &[u8], width: CellWidth) -> Run {
+    let config = CompileConfig { tape_size: TAPE, cell_width: width, ..CompileConfig::default() };
+    let dir = env::temp_dir().join(format!("bf-diff-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let (source, binary) = (dir.join("program.c"), dir.join("program"));
+    fs::write(&source, compile(program, &config).unwrap()).unwrap();
+    let status = Command::new(c_compiler().unwrap()).arg("-o").arg(&binary).arg(&source).status().unwrap();
+    assert!(status.success(), "the C backend generated invalid code for {:?}", program);
+    let mut child = Command::new(&binary).stdin(Stdio::piped()).stdout(Stdio::piped()).spawn().unwrap();
+    // The program may end before it reads all of its input
+    let _ = child.stdin.take().unwrap().write_all(input);
+    Run { output: child.wait_with_output().unwrap().stdout, tape: None, error: None }
+}
+
+// Whether `engine` disagrees with the reference on a run the reference finished
+fn differs(engine: Engine, width: CellWidth, only_successes: bool, program: &str, input: &[u8]) -> bool {
+    let expected = reference(program, input, width);
+    if expected.error == Some(Error::Fuel) || (only_successes && expected.error.is_some()) {
+        return false;
+    }
+    let actual = engine(program, input, width);
+    let tape_differs = matches!((&expected.tape, &actual.tape), (Some(a), Some(b)) if a != b);
+    actual.output != expected.output || actual.error != expected.error || tape_differs
+}
+
+// Remove balanced pieces and loop brackets of the program, and bytes of the input, while it still fails
+fn shrink(program: &str, input: &[u8], fails: &dyn Fn(&str, &[u8]) -> bool) -> (String, Vec<u8>) {
+    let (mut program, mut input) = (String::from(program), input.to_vec());
+    loop {
+        let mut smaller = false;
+        let mut size = program.len();
+        while size > 0 {
+            let mut start = 0;
+            while start + size <= program.len() {
+                let piece = &program[start..start + size];
+                let balanced = piece.bytes().try_fold(0i32, |depth, c| {
+                    let depth = depth + (c == b'[') as i32 - (c == b']') as i32;
+                    (depth >= 0).then_some(depth)
+                }) == Some(0);
+                let candidate = format!("{}{}", &program[..start], &program[start + size..]);
+                if balanced && fails(&candidate, &input) {
+                    program = candidate;
+                    smaller = true;
+                } else {
+                    start += 1;
+                }
+            }
+            size /= 2;
+        }
+        // A loop body may fail on its own
+        let mut open = Vec::new();
+        for (at, c) in program.clone().char_indices() {
+            match c {
+                '[' => open.push(at),
+                ']' => {
+                    let start = open.pop().unwrap();
+                    let candidate = format!("{}{}{}", &program[..start], &program[start + 1..at], &program[at + 1..]);
+                    if fails(&candidate, &input) {
+                        program = candidate;
+                        smaller = true;
+                        break;
+                    }
+                },
+                _ => (),
+            }
+        }
+        for at in (0..input.len()).rev() {
+            let mut candidate = input.clone();
+            candidate.remove(at);
+            if fails(&program, &candidate) {
+                input = candidate;
+                smaller = true;
+            }
+        }
+        if !smaller {
+            return (program, input);
+        }
+    }
+}
+
+fn check(name: &str, engine: Engine, width: CellWidth, only_successes: bool, cases: u64) {
+    let seed = env_number("BF_DIFF_SEED", 0x5eed_b1a5);
+    let mut rng = Rng(seed | 1);
+    for case in 0..cases {
+        let len = 4 + rng.below(60);
+        let program = generate(&mut rng, len, 0);
+        let input: Vec<u8> = (0..rng.below(8)).map(|_| rng.next() as u8).collect();
+        let fails = |program: &str, input: &[u8]| differs(engine, width, only_successes, program, input);
+        if fails(&program, &input) {
+            let (program, input) = shrink(&program, &input, &fails);
+            panic!(
+                "{} disagrees with the reference ({}-bit cells, seed {:#x}, case {})\nprogram: {:?}\ninput: {:?}\nexpected: {:?}\nactual: {:?}",
+                name, width.bits(), seed, case, program, input, reference(&program, &input, width), engine(&program, &input, width));
+        }
+    }
+}
+
+#[test]
+fn interpreter_agrees() {
+    for &width in [CellWidth::Bits8, CellWidth::Bits16, CellWidth::Bits32].iter() {
+        check("interpreter", interpret, width, false, env_number("BF_DIFF_CASES", 500));
+    }
+}
+
+#[test]
+fn bytecode_agrees() {
+    check("bytecode", bytecode, CellWidth::Bits8, false, env_number("BF_DIFF_CASES", 500));
+}
+
+#[test]
+fn formatter_agrees() {
+    check("formatter", formatted, CellWidth::Bits8, false, env_number("BF_DIFF_CASES", 500));
+}
+
+#[test]
+fn minifier_agrees() {
+    check("minifier", minified, CellWidth::Bits8, false, env_number("BF_DIFF_CASES", 500));
+}
+
+// Every case compiles a C program, so this one runs fewer of them, and none without a compiler
+#[test]
+fn c_backend_agrees() {
+    if c_compiler().is_none() {
+        eprintln!("no C compiler found, skipping the C backend");
+        return;
+    }
+    for &width in [CellWidth::Bits8, CellWidth::Bits16, CellWidth::Bits32].iter() {
+        check("C backend", c_backend, width, true, env_number("BF_DIFF_CASES", 500) / 25);
+    }
+}
+
+#[test]
+fn shrinking_keeps_the_failure() {
+    // Pretend that printing two neighbouring cells is a bug
+    let fails = |program: &str, _: &[u8]| program.contains(".>.");
+    let (program, input) = shrink("++[>+.<-]>>+.>.<<,", b"xyz", &fails);
+    assert_eq!((program.as_str(), input.as_slice()), (".>.", &b""[..]));
+    assert_eq!(reference("+[>+<-]>.<<", b"", CellWidth::Bits8), Run { output: vec![1], tape: Some(reference_tape(&[0, 1])), error: Some(Error::Left) });
+}
+
+fn reference_tape(cells: &[u32]) -> Vec<u32> {
+    let mut tape = cells.to_vec();
+    tape.resize(TAPE, 0);
+    tape
+}
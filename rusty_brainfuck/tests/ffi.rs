@@ -0,0 +1,167 @@
+// The C interface: the header generated from src/ffi.rs, and a C program linked against the
+// shared library. Run `BF_BLESS=1 cargo test --test ffi` to regenerate the header.
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+const HEADER: &str = "include/rusty_brainfuck.h";
+
+fn c_type(rust: &str) -> String {
+    let pointer = |inner: String| if inner.ends_with('*') { inner + "*" } else { inner + " *" };
+    match rust.trim() {
+        "c_int" => String::from("int"),
+        "c_char" => String::from("char"),
+        "usize" => String::from("size_t"),
+        "u64" => String::from("uint64_t"),
+        "u8" => String::from("uint8_t"),
+        "BfMachine" => String::from("BfMachine"),
+        rust if rust.starts_with("*const ") => pointer(format!("const {}", c_type(&rust[7..]))),
+        rust if rust.starts_with("*mut ") => pointer(c_type(&rust[5..])),
+        rust => panic!("no C type for {}", rust),
+    }
+}
+
+// `type name`, or `type *name` for pointers
+fn declare(c_type: &str, name: &str) -> String {
+    format!("{}{}{}", c_type, if c_type.ends_with('*') { "" } else { " " }, name)
+}
+
+fn function(signature: &str) -> String {
+    let (name, rest) = signature.split_once('(').unwrap();
+    let (params, rest) = rest.split_once(')').unwrap();
+    let params: Vec<String> = params.split(", ")
+        .map(|param| {
+            let (name, rust) = param.split_once(':').unwrap();
+            declare(&c_type(rust), name.trim())
+        })
+        .collect();
+    let ret = match rest.trim().trim_end_matches('{').trim().strip_prefix("->") {
+        Some(ret) => c_type(ret),
+        None => String::from("void"),
+    };
+    format!("{};", declare(&ret, &format!("{}({})", name, params.join(", "))))
+}
+
+fn generate_header(source: &str) -> String {
+    let mut header = String::from(
+        "// Generated from src/ffi.rs by tests/ffi.rs. Do not edit.\n\
+         #ifndef RUSTY_BRAINFUCK_H\n#define RUSTY_BRAINFUCK_H\n\n\
+         #include <stddef.h>\n#include <stdint.h>\n\n\
+         #ifdef __cplusplus\nextern \"C\" {\n#endif\n\n");
+    let mut docs = Vec::new();
+    let mut previous = "";
+    for line in source.lines().take_while(|line| !line.starts_with("#[cfg(test)]")) {
+        if let Some(doc) = line.strip_prefix("///") {
+            docs.push(format!("//{}", doc));
+        } else if let Some(constant) = line.strip_prefix("pub const ") {
+            let (name, rest) = constant.split_once(':').unwrap();
+            let (value, comment) = rest.split_once('=').unwrap().1.split_once(';').unwrap();
+            let value = match value.trim() {
+                value if value.starts_with('-') => format!("({})", value),
+                value => value.to_string(),
+            };
+            header += &format!("{:<36} {}\n", format!("#define {} {}", name, value), comment.trim());
+        } else if let Some(rest) = line.strip_prefix("pub struct ") {
+            let name = rest.trim_end_matches('{').trim();
+            header += &format!("typedef struct {} {};\n\n", name, name);
+        } else if let Some(signature) = line.strip_prefix("pub unsafe extern \"C\" fn ").or_else(|| line.strip_prefix("pub extern \"C\" fn ")) {
+            for doc in docs.drain(..) {
+                header += &doc;
+                header.push('\n');
+            }
+            header += &function(signature);
+            header += "\n\n";
+        } else if line.is_empty() && previous.starts_with("pub const ") {
+            header.push('\n');
+        }
+        if !line.starts_with("///") && !line.starts_with("#[") {
+            docs.clear();
+        }
+        previous = line;
+    }
+    header += "#ifdef __cplusplus\n}\n#endif\n\n#endif\n";
+    header
+}
+
+#[test]
+fn header_is_generated() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let header = generate_header(&fs::read_to_string(root.join("src/ffi.rs")).unwrap());
+    if env::var_os("BF_BLESS").is_some() {
+        fs::write(root.join(HEADER), &header).unwrap();
+    }
+    let current = fs::read_to_string(root.join(HEADER)).unwrap_or_default();
+    assert!(current == header, "{} is out of date, run `BF_BLESS=1 cargo test --test ffi`", HEADER);
+}
+
+// Build the shared library on its own in target/ffi, so that the test doesn't depend on what
+// the last build left in target/debug, and return the directory it is in
+fn build_library() -> PathBuf {
+    let target = env::current_exe().unwrap().ancestors().nth(3).unwrap().join("ffi");
+    let status = Command::new(env!("CARGO"))
+        .args(["build", "--lib", "--quiet", "--manifest-path"])
+        .arg(Path::new(env!("CARGO_MANIFEST_DIR")).join("Cargo.toml"))
+        .arg("--target-dir").arg(&target)
+        .status()
+        .unwrap();
+    assert!(status.success(), "cargo build --lib failed");
+    let dir = target.join("debug");
+    assert!(dir.join("librusty_brainfuck.so").exists(), "cargo build --lib made no librusty_brainfuck.so");
+    dir
+}
+
+const SMOKE: &str = r#"
+#include <stdio.h>
+#include "rusty_brainfuck.h"
+
+int main(void) {
+    BfMachine *machine;
+    if (bf_create(",[.,]", 0, &machine) != BF_OK) return 1;
+    uint64_t steps = 0;
+    if (bf_run(machine, 1000, &steps) != BF_NEEDS_INPUT) return 2;
+    bf_feed_input(machine, (const uint8_t *)"hello", 5);
+    bf_close_input(machine);
+    if (bf_run(machine, 1000, &steps) != BF_OK) return 3;
+    uint8_t output[16];
+    size_t len = bf_read_output(machine, output, sizeof output);
+    printf("%.*s %d\n", (int)len, (const char *)output, (int)(steps > 0));
+    bf_destroy(machine);
+
+    if (bf_create("<", 0, &machine) != BF_OK) return 4;
+    int status = bf_run(machine, 10, NULL);
+    printf("%d %s | %s\n", status, bf_status_message(status), bf_last_error(machine));
+    bf_destroy(machine);
+    return 0;
+}
+"#;
+
+#[test]
+fn c_program_links() {
+    let cc = env::var("CC").unwrap_or_else(|_| String::from("cc"));
+    let found = Command::new(&cc).arg("--version").stdout(Stdio::null()).stderr(Stdio::null()).status();
+    if !found.is_ok_and(|status| status.success()) {
+        eprintln!("no C compiler, skipping");
+        return;
+    }
+    let lib = build_library();
+    let dir = env::temp_dir().join(format!("bf-ffi-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let (source, binary) = (dir.join("smoke.c"), dir.join("smoke"));
+    fs::write(&source, SMOKE).unwrap();
+    let status = Command::new(&cc)
+        .arg("-Wall").arg("-Werror")
+        .arg("-I").arg(Path::new(env!("CARGO_MANIFEST_DIR")).join("include"))
+        .arg("-o").arg(&binary).arg(&source)
+        .arg("-L").arg(&lib).arg("-lrusty_brainfuck")
+        .arg(format!("-Wl,-rpath,{}", lib.display()))
+        .status()
+        .unwrap();
+    assert!(status.success());
+    // cargo points LD_LIBRARY_PATH at target/debug, which would win over the rpath
+    let output = Command::new(&binary).env("LD_LIBRARY_PATH", &lib).output().unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "hello 1\n-3 Too small pointer than the first address of memory. | 1:1: Too small pointer than the first address of memory.\n");
+}
@@ -0,0 +1,99 @@
+extern crate rusty_brainfuck;
+
+use rusty_brainfuck::{compile_lang, Brainfuck};
+
+fn run_with_input(source: &str, input: &str) -> String {
+    let program = compile_lang(source).unwrap();
+    let mut bf = Brainfuck::new(program).unwrap();
+    let mut input = input.chars();
+    let mut output = String::new();
+    while !bf.reach_eop() {
+        bf.step().unwrap();
+        if bf.is_input_mode() {
+            bf.set_input(input.next().unwrap().to_string()).unwrap();
+        }
+        output += &bf.pop_result();
+    }
+    output
+}
+
+fn run(source: &str) -> String {
+    run_with_input(source, "")
+}
+
+#[test]
+fn prints_strings_and_numbers() {
+    assert_eq!(run(r#"print "Hello, World!\n";"#), "Hello, World!\n");
+    assert_eq!(run("print 0; print \" \"; print 7; print \" \"; print 42; print \" \"; print 255;"), "0 7 42 255");
+    assert_eq!(run("putc 'A' + 1;"), "B");
+}
+
+#[test]
+fn arithmetic() {
+    let source = "
+        var a = 17;
+        var b = 5;
+        print a + b; print \",\";
+        print a - b; print \",\";
+        print a * b; print \",\";
+        print a / b; print \",\";
+        print a % b; print \",\";
+        print b - a; print \",\";
+        print -b; print \",\";
+        print (a + 3) * 2 - b / 2;
+    ";
+    assert_eq!(run(source), "22,12,85,3,2,244,251,38");
+    assert_eq!(run("print 200 + 100; print \",\"; print 7 / 0; print \",\"; print 7 % 0;"), "44,0,0");
+}
+
+#[test]
+fn comparisons_and_logic() {
+    let source = "
+        var a = 3;
+        var b = 200;
+        print a < b; print a > b; print a <= 3; print b >= 201;
+        print a == 3; print a != 3; print !a; print !0;
+        print a < b && b > 100; print a > b || b == 200; print 0 || 0; print 0 && 5;
+    ";
+    assert_eq!(run(source), "101010011100");
+}
+
+#[test]
+fn control_flow() {
+    let source = "
+        // FizzBuzz up to 15
+        var i = 1;
+        while i <= 15 {
+            if i % 15 == 0 {
+                print \"FizzBuzz\";
+            } else if i % 3 == 0 {
+                print \"Fizz\";
+            } else if i % 5 == 0 {
+                print \"Buzz\";
+            } else {
+                print i;
+            }
+            print \" \";
+            i = i + 1;
+        }
+    ";
+    assert_eq!(run(source), "1 2 Fizz 4 Buzz Fizz 7 8 Fizz Buzz 11 Fizz 13 14 FizzBuzz ");
+}
+
+#[test]
+fn nested_loops_and_input() {
+    let source = "
+        var c;
+        read c;
+        while c != '.' {
+            var n = c - '0';
+            while n > 0 {
+                putc '*';
+                n = n - 1;
+            }
+            print \"\\n\";
+            read c;
+        }
+    ";
+    assert_eq!(run_with_input(source, "312."), "***\n*\n**\n");
+}
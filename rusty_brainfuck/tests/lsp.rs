@@ -0,0 +1,66 @@
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+fn frame(body: &str) -> String {
+    format!("Content-Length: {}\r\n\r\n{}", body.len(), body)
+}
+
+// Send a whole session to the server, and split what it answers into message bodies
+fn session(messages: &[&str]) -> (i32, Vec<String>) {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_bf-lsp"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let input: String = messages.iter().map(|message| frame(message)).collect();
+    child.stdin.take().unwrap().write_all(input.as_bytes()).unwrap();
+    let output = child.wait_with_output().unwrap();
+    let mut stdout = String::from_utf8(output.stdout).unwrap();
+    let mut bodies = Vec::new();
+    while let Some(end) = stdout.find("\r\n\r\n") {
+        let length: usize = stdout[..end].trim_start_matches("Content-Length: ").parse().unwrap();
+        bodies.push(stdout[end + 4..end + 4 + length].to_string());
+        stdout = stdout[end + 4 + length..].to_string();
+    }
+    (output.status.code().unwrap(), bodies)
+}
+
+#[test]
+fn language_features() {
+    let (code, replies) = session(&[
+        r#"{"jsonrpc":"2.0","id":1,"method":"initialize","params":{"capabilities":{}}}"#,
+        r#"{"jsonrpc":"2.0","method":"initialized","params":{}}"#,
+        r#"{"jsonrpc":"2.0","method":"textDocument/didOpen","params":{"textDocument":{"uri":"file:///a.bf","languageId":"brainfuck","version":1,"text":"++[->+<]\n]"}}}"#,
+        r#"{"jsonrpc":"2.0","id":2,"method":"textDocument/hover","params":{"textDocument":{"uri":"file:///a.bf"},"position":{"line":0,"character":4}}}"#,
+        r#"{"jsonrpc":"2.0","id":3,"method":"textDocument/definition","params":{"textDocument":{"uri":"file:///a.bf"},"position":{"line":0,"character":7}}}"#,
+        r#"{"jsonrpc":"2.0","id":4,"method":"textDocument/formatting","params":{"textDocument":{"uri":"file:///a.bf"},"options":{"tabSize":2,"insertSpaces":true}}}"#,
+        r#"{"jsonrpc":"2.0","method":"textDocument/didChange","params":{"textDocument":{"uri":"file:///a.bf","version":2},"contentChanges":[{"text":"+[\n-]"}]}}"#,
+        r#"{"jsonrpc":"2.0","id":5,"method":"textDocument/formatting","params":{"textDocument":{"uri":"file:///a.bf"},"options":{"tabSize":2,"insertSpaces":true}}}"#,
+        r#"{"jsonrpc":"2.0","id":6,"method":"shutdown"}"#,
+        r#"{"jsonrpc":"2.0","method":"exit"}"#,
+    ]);
+    assert_eq!(code, 0);
+    assert_eq!(replies.len(), 8);
+    assert!(replies[0].contains(r#""hoverProvider":true"#));
+    assert_eq!(
+        replies[1],
+        r#"{"jsonrpc":"2.0","method":"textDocument/publishDiagnostics","params":{"uri":"file:///a.bf","diagnostics":[{"range":{"start":{"line":1,"character":0},"end":{"line":1,"character":1}},"severity":1,"source":"bf","message":"This bracket has no opening bracket."}]}}"#);
+    assert!(replies[2].contains(r#"cell[p] = 0\ncell[p+1] += cell[p]"#));
+    assert!(replies[2].contains(r#""range":{"start":{"line":0,"character":2},"end":{"line":0,"character":8}}"#));
+    assert_eq!(
+        replies[3],
+        r#"{"jsonrpc":"2.0","id":3,"result":{"uri":"file:///a.bf","range":{"start":{"line":0,"character":2},"end":{"line":0,"character":3}}}}"#);
+    assert!(replies[4].contains(r#""id":4,"error":{"code":-32803"#));
+    assert!(replies[5].contains(r#""diagnostics":[]"#));
+    assert_eq!(
+        replies[6],
+        r#"{"jsonrpc":"2.0","id":5,"result":[{"range":{"start":{"line":0,"character":0},"end":{"line":1,"character":2}},"newText":"+[-]\n"}]}"#);
+    assert_eq!(replies[7], r#"{"jsonrpc":"2.0","id":6,"result":null}"#);
+}
+
+#[test]
+fn exit_without_shutdown() {
+    let (code, replies) = session(&["{oops", r#"{"jsonrpc":"2.0","method":"exit"}"#]);
+    assert_eq!(code, 1);
+    assert_eq!(replies, [r#"{"jsonrpc":"2.0","id":null,"error":{"code":-32700,"message":"Invalid JSON."}}"#]);
+}
@@ -0,0 +1,37 @@
+// The Python extension module, run by tests/python/test_bindings.py with the local interpreter:
+//
+//     cargo test --features python --test python
+//
+// Set PYTHON to choose another interpreter than python3.
+#![cfg(feature = "python")]
+
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+#[test]
+fn bindings() {
+    // Build the module on its own in target/python, rather than trust what the last build left
+    let target = env::current_exe().unwrap().ancestors().nth(3).unwrap().join("python");
+    let status = Command::new(env!("CARGO"))
+        .args(["build", "--lib", "--quiet", "--features", "python", "--manifest-path"])
+        .arg(Path::new(env!("CARGO_MANIFEST_DIR")).join("Cargo.toml"))
+        .arg("--target-dir").arg(&target)
+        .status()
+        .unwrap();
+    assert!(status.success(), "cargo build --lib --features python failed");
+    let library = target.join("debug/librusty_brainfuck.so");
+    assert!(library.exists(), "cargo build --lib --features python made no {}", library.display());
+
+    // Python imports the module from a file named after it
+    let dir = env::temp_dir().join(format!("bf-python-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::copy(&library, dir.join("rusty_brainfuck.so")).unwrap();
+
+    let python = env::var("PYTHON").unwrap_or_else(|_| String::from("python3"));
+    let script = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/python/test_bindings.py");
+    let output = Command::new(&python).arg(&script).env("PYTHONPATH", &dir).output().unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+}
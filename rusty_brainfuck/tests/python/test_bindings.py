@@ -0,0 +1,69 @@
+import unittest
+
+import rusty_brainfuck
+from rusty_brainfuck import Brainfuck, BrainfuckError
+
+
+class BindingsTest(unittest.TestCase):
+    def test_run(self):
+        self.assertEqual(Brainfuck(",[.,]").run(b"hello"), b"hello")
+        self.assertEqual(Brainfuck("++++++++[>++++++++<-]>+.").run(), b"A")
+        self.assertEqual(Brainfuck(",.").run(b"\xff"), b"\xff")
+
+    def test_eof(self):
+        self.assertEqual(Brainfuck("+,.").run(), b"\x00")
+        self.assertEqual(Brainfuck("+,.", eof="minus-one").run(), b"\xff")
+        self.assertEqual(Brainfuck("+,.", eof="unchanged").run(), b"\x01")
+        with self.assertRaises(EOFError):
+            Brainfuck(",", eof="error").run()
+        with self.assertRaises(ValueError):
+            Brainfuck("", eof="never")
+
+    def test_stepping_and_tape(self):
+        bf = Brainfuck("+++>++.", tape_size=4)
+        self.assertEqual((bf.tape_size, bf.pointer, bf.pc), (4, 0, 0))
+        self.assertFalse(bf.step())
+        self.assertEqual((bf[0], bf.pointer, bf.pc), (3, 0, 1))
+        self.assertTrue(bf.step(10))
+        self.assertTrue(bf.finished)
+        self.assertEqual(bf.tape, b"\x03\x02\x00\x00")
+        self.assertEqual(bf.read_output(), b"\x02")
+        self.assertEqual(bf.read_output(), b"")
+        with self.assertRaises(IndexError):
+            bf[4]
+
+    def test_cell_width(self):
+        self.assertEqual(Brainfuck("").cell_width, 8)
+        bf = Brainfuck("-.>" + "+" * 256, tape_size=2, cell_width=16)
+        self.assertEqual(bf.run(), b"\xff")
+        self.assertEqual((bf[0], bf[1], bf.cell_width), (65535, 256, 16))
+        self.assertEqual(bf.tape, b"\xff\xff\x00\x01")
+        bf = Brainfuck(",", tape_size=1, cell_width=32, eof="minus-one")
+        bf.run()
+        self.assertEqual(bf.tape, b"\xff" * 4)
+        with self.assertRaises(ValueError):
+            Brainfuck("", cell_width=12)
+
+    def test_feed(self):
+        bf = Brainfuck(",.,.", eof="error")
+        bf.feed(b"a")
+        self.assertEqual(bf.run(b"b"), b"ab")
+
+    def test_errors(self):
+        with self.assertRaises(BrainfuckError):
+            Brainfuck("[")
+        with self.assertRaisesRegex(BrainfuckError, "Too large pointer"):
+            Brainfuck(">>", tape_size=2).run()
+        with self.assertRaisesRegex(BrainfuckError, "Out of fuel"):
+            Brainfuck("+[]", fuel=100).run()
+        with self.assertRaises(ValueError):
+            Brainfuck("", tape_size=0)
+        self.assertTrue(issubclass(rusty_brainfuck.BrainfuckError, RuntimeError))
+
+    def test_docstrings(self):
+        self.assertIn("wrapping cells of `cell_width` bits", Brainfuck.__doc__)
+        self.assertIn("whether the program ended", Brainfuck.step.__doc__)
+
+
+if __name__ == "__main__":
+    unittest.main()
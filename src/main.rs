@@ -5,7 +5,7 @@ use iui::prelude::*;
 use iui::controls::{VerticalBox, HorizontalBox, LayoutStrategy, Button, Label, Entry, MultilineEntry};
 use iui::menus::Menu;
 
-use rusty_brainfuck::Brainfuck;
+use rusty_brainfuck::{Brainfuck, Dialect, lint_with_dialect};
 
 use futures::{
     executor::ThreadPool,
@@ -49,6 +49,7 @@ fn main() {
          source_multi,
          mut run_button,
          mut stop_button,
+         mut lint_button,
          result_multi,
          input_entry,
          mut input_button
@@ -59,8 +60,10 @@ fn main() {
         let mut run_horibox = run_horibox.clone();
         let run_button = Button::new(&ui, "Run");
         let stop_button = Button::new(&ui, "Stop");
+        let lint_button = Button::new(&ui, "Lint");
         run_horibox.append(&ui, run_button.clone(), LayoutStrategy::Compact);
         run_horibox.append(&ui, stop_button.clone(), LayoutStrategy::Compact);
+        run_horibox.append(&ui, lint_button.clone(), LayoutStrategy::Compact);
 
         let result_label = Label::new(&ui, "Result:");
         let mut result_multi = MultilineEntry::new(&ui);
@@ -81,7 +84,7 @@ fn main() {
         vertbox.append(&ui, input_label, LayoutStrategy::Compact);
         vertbox.append(&ui, input_horibox, LayoutStrategy::Compact);
 
-        (source_label, source_multi, run_button, stop_button, result_multi, input_entry, input_button)
+        (source_label, source_multi, run_button, stop_button, lint_button, result_multi, input_entry, input_button)
     };
 
     let file_menu = Menu::new(&ui, "File");
@@ -134,6 +137,19 @@ fn main() {
         app_state.borrow_mut().stop = true;
         app_state.borrow_mut().running = true;
     });
+    lint_button.on_clicked(&ui, |_| {
+        let source = source_multi.value(&ui);
+        let mut result_multi = result_multi.clone();
+        let mut console = result_multi.value(&ui);
+        let lints = lint_with_dialect(&source, &Dialect::detect(&source));
+        if lints.is_empty() {
+            console += "\n[Lint: No problems found]";
+        }
+        for l in lints {
+            console += &("\n[Lint ".to_string() + &l.describe(&source) + "]");
+        }
+        result_multi.set_value(&ui, &console);
+    });
     input_button.on_clicked(&ui, |_| {
         let app_state = app_state.clone();
         app_state.borrow_mut().running = true;
@@ -243,7 +259,8 @@ fn main() {
                 if app_state.borrow().bf_futures.len() == 0 {
                     let program = source_multi.value(&ui);
                     if !program.is_empty() {
-                        match Brainfuck::new(program) {
+                        let dialect = Dialect::detect(&program);
+                        match Brainfuck::with_dialect(program, &dialect) {
                             Ok(bf) => {
                                 // ここでは、初期化された状態のBrainfuckインタプリタから実行を始めさせる
                                 let tx = tx.clone();